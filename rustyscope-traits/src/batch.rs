@@ -0,0 +1,130 @@
+//! Config changes that are applied all or nothing, see
+//! `Command::ConfigBatch`
+
+use crate::{Command, ConfigAction, ConfigErr};
+
+/// most actions a batch can hold
+pub const MAX_BATCH: usize = 16;
+
+/// collects the frames that follow a `Command::ConfigBatch`
+#[derive(Debug, Clone)]
+pub struct Batch {
+    actions: [ConfigAction; MAX_BATCH],
+    len: usize,
+    announced: usize,
+    received: usize,
+    /// first frame that makes the batch unusable
+    refused: Option<(u8, ConfigErr)>,
+}
+
+impl Batch {
+    /// a batch of `announced` frames
+    pub fn new(announced: u8) -> Self {
+        Self {
+            actions: [ConfigAction::ResetPins; MAX_BATCH],
+            len: 0,
+            announced: announced as usize,
+            received: 0,
+            refused: None,
+        }
+    }
+
+    /// frames still to be read, they have to be read even
+    /// when the batch is refused to stay in step with the host
+    pub fn remaining(&self) -> usize {
+        self.announced - self.received
+    }
+
+    /// take the next frame, anything but a `Command::Config`
+    /// refuses the batch
+    pub fn push(&mut self, command: Command) {
+        let index = self.received as u8;
+        self.received += 1;
+        if self.refused.is_some() {
+            return;
+        }
+        match command {
+            Command::Config(action) if self.len < MAX_BATCH => {
+                self.actions[self.len] = action;
+                self.len += 1;
+            }
+            Command::Config(_) => {
+                self.refused = Some((index, ConfigErr::BatchTooLong { max: MAX_BATCH as u8 }))
+            }
+            _ => self.refused = Some((index, ConfigErr::CommunicationProblem)),
+        }
+    }
+
+    /// the actions to apply, or the index of the frame that
+    /// refused the batch and why
+    pub fn actions(&self) -> Result<&[ConfigAction], (u8, ConfigErr)> {
+        match self.refused {
+            Some(refused) => Err(refused),
+            None => Ok(&self.actions[..self.len]),
+        }
+    }
+
+    /// the frames a host sends for `actions`, starting with
+    /// the `Command::ConfigBatch`
+    pub fn frames(actions: &[ConfigAction])
+        -> Result<impl Iterator<Item = Command> + '_, ConfigErr> {
+        if actions.len() > MAX_BATCH {
+            return Err(ConfigErr::BatchTooLong { max: MAX_BATCH as u8 });
+        }
+        let start = Command::ConfigBatch(actions.len() as u8);
+        Ok(core::iter::once(start).chain(actions.iter().map(|&a| Command::Config(a))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PinId;
+
+    const PIN: ConfigAction = ConfigAction::AnalogPins(PinId::new(0, 2));
+
+    #[test]
+    fn collected() {
+        let mut batch = Batch::new(2);
+        assert_eq!(batch.remaining(), 2);
+        batch.push(Command::Config(ConfigAction::ResetPins));
+        batch.push(Command::Config(PIN));
+        assert_eq!(batch.remaining(), 0);
+        assert_eq!(batch.actions(), Ok(&[ConfigAction::ResetPins, PIN][..]));
+        assert_eq!(Batch::new(0).actions(), Ok(&[][..]));
+    }
+
+    #[test]
+    fn not_a_config() {
+        let mut batch = Batch::new(3);
+        batch.push(Command::Config(PIN));
+        batch.push(Command::Ping);
+        batch.push(Command::Config(PIN));
+        assert_eq!(batch.remaining(), 0);
+        assert_eq!(batch.actions(), Err((1, ConfigErr::CommunicationProblem)));
+    }
+
+    #[test]
+    fn too_long() {
+        let mut batch = Batch::new(20);
+        while batch.remaining() > 0 {
+            batch.push(Command::Config(PIN));
+        }
+        let max = MAX_BATCH as u8;
+        assert_eq!(batch.actions(), Err((max, ConfigErr::BatchTooLong { max })));
+        assert!(Batch::frames(&[PIN; MAX_BATCH + 1]).is_err());
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let actions = [ConfigAction::ResetPins, PIN, ConfigAction::AnalogRate(1000)];
+        let mut frames = Batch::frames(&actions).unwrap();
+        let mut batch = match frames.next() {
+            Some(Command::ConfigBatch(n)) => Batch::new(n),
+            other => panic!("expected a batch got {:?}", other),
+        };
+        frames.for_each(|f| batch.push(f));
+        assert_eq!(batch.remaining(), 0);
+        assert_eq!(batch.actions(), Ok(&actions[..]));
+    }
+}
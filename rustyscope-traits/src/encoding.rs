@@ -0,0 +1,419 @@
+//! Ways to pack samples into the payload of a `Reply::Data`, every
+//! payload can be decoded on its own
+
+use core::convert::TryFrom;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, defmt::Format, Copy, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    /// little endian `i16`, two bytes a sample
+    Raw,
+    /// two samples in three bytes, readings are clamped
+    /// to 0..=4095
+    Packed12,
+    /// one byte a sample for 8 bit resolution, readings
+    /// are clamped to 0..=255
+    Bits8,
+    /// zigzag varint of the difference to the previous
+    /// sample, one byte while the signal changes slowly.
+    /// Channels are interleaved so this works best with one
+    DeltaVarint,
+    /// runs of equal samples as a count byte followed by
+    /// the sample as little endian `i16`
+    Rle,
+}
+
+/// what the firmware sends after a reset
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Raw
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, defmt::Format, Copy, Clone, PartialEq)]
+pub enum DecodeErr {
+    /// the payload ends in the middle of a sample
+    Truncated,
+    /// bytes that no encoder produces
+    Invalid,
+    /// more samples than fit the output
+    OutputFull,
+}
+
+/// bytes a varint of `v` takes
+fn varint_len(v: u32) -> usize {
+    match v {
+        0..=0x7f => 1,
+        0x80..=0x3fff => 2,
+        _ => 3,
+    }
+}
+
+fn zigzag(d: i32) -> u32 {
+    ((d << 1) ^ (d >> 31)) as u32
+}
+
+fn unzigzag(z: u32) -> i32 {
+    (z >> 1) as i32 ^ -((z & 1) as i32)
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 5] = [
+        Encoding::Raw,
+        Encoding::Packed12,
+        Encoding::Bits8,
+        Encoding::DeltaVarint,
+        Encoding::Rle,
+    ];
+
+    /// as written in profiles and on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Raw => "raw",
+            Encoding::Packed12 => "packed12",
+            Encoding::Bits8 => "bits8",
+            Encoding::DeltaVarint => "delta-varint",
+            Encoding::Rle => "rle",
+        }
+    }
+
+    /// whether negative readings survive, a differential
+    /// channel needs an encoding that keeps them
+    pub fn signed(self) -> bool {
+        !matches!(self, Encoding::Packed12 | Encoding::Bits8)
+    }
+
+    /// the highest resolution in bits it carries without
+    /// clamping, `None` for any
+    pub fn max_bits(self) -> Option<u8> {
+        match self {
+            Encoding::Packed12 => Some(12),
+            Encoding::Bits8 => Some(8),
+            Encoding::Raw | Encoding::DeltaVarint | Encoding::Rle => None,
+        }
+    }
+
+    /// most bytes `samples` readings of `resolution` bits take
+    pub fn max_bytes(self, samples: usize, resolution: u8) -> usize {
+        match self {
+            Encoding::Raw => samples * 2,
+            Encoding::Packed12 => samples / 2 * 3 + samples % 2 * 2,
+            Encoding::Bits8 => samples,
+            // the largest step between two readings
+            Encoding::DeltaVarint => samples * varint_len(zigzag((1 << resolution.min(15)) - 1)),
+            Encoding::Rle => samples * 3,
+        }
+    }
+
+    /// most samples a payload of `bytes` can hold
+    pub fn max_samples(self, bytes: usize) -> usize {
+        match self {
+            Encoding::Raw => bytes / 2,
+            Encoding::Packed12 => bytes / 3 * 2 + (bytes % 3 == 2) as usize,
+            Encoding::Bits8 | Encoding::DeltaVarint => bytes,
+            Encoding::Rle => bytes / 3 * u8::MAX as usize,
+        }
+    }
+
+    /// encode as many samples as fit in `out`, returns the number
+    /// of samples used and bytes written. An `out` of three bytes
+    /// or more always takes at least one sample
+    pub fn encode(self, samples: &[i16], out: &mut [u8]) -> (usize, usize) {
+        let (mut used, mut len) = (0, 0);
+        match self {
+            Encoding::Raw => {
+                for (s, b) in samples.iter().zip(out.chunks_exact_mut(2)) {
+                    b.copy_from_slice(&s.to_le_bytes());
+                    used += 1;
+                }
+                len = used * 2;
+            }
+            Encoding::Packed12 => {
+                let clamp = |s: i16| s.clamp(0, 0xfff) as u16;
+                while used < samples.len() {
+                    let a = clamp(samples[used]);
+                    let pair = samples.get(used + 1).copied().map(clamp);
+                    match pair {
+                        Some(b) if len + 3 <= out.len() => {
+                            out[len] = a as u8;
+                            out[len + 1] = (a >> 8) as u8 | (b << 4) as u8;
+                            out[len + 2] = (b >> 4) as u8;
+                            used += 2;
+                            len += 3;
+                        }
+                        // a lone sample can only end the payload
+                        _ if len + 2 <= out.len() => {
+                            out[len..len + 2].copy_from_slice(&a.to_le_bytes());
+                            used += 1;
+                            len += 2;
+                            break;
+                        }
+                        _ => break,
+                    }
+                }
+            }
+            Encoding::Bits8 => {
+                for (s, b) in samples.iter().zip(out.iter_mut()) {
+                    *b = (*s).clamp(0, u8::MAX as i16) as u8;
+                    used += 1;
+                }
+                len = used;
+            }
+            Encoding::DeltaVarint => {
+                let mut prev = 0;
+                for &s in samples {
+                    let mut z = zigzag(s as i32 - prev);
+                    if len + varint_len(z) > out.len() {
+                        break;
+                    }
+                    while z >= 0x80 {
+                        out[len] = z as u8 | 0x80;
+                        z >>= 7;
+                        len += 1;
+                    }
+                    out[len] = z as u8;
+                    len += 1;
+                    prev = s as i32;
+                    used += 1;
+                }
+            }
+            Encoding::Rle => {
+                while used < samples.len() && len + 3 <= out.len() {
+                    let value = samples[used];
+                    let run = samples[used..].iter()
+                        .take(u8::MAX as usize)
+                        .take_while(|&&s| s == value)
+                        .count();
+                    out[len] = run as u8;
+                    out[len + 1..len + 3].copy_from_slice(&value.to_le_bytes());
+                    used += run;
+                    len += 3;
+                }
+            }
+        }
+        (used, len)
+    }
+
+    /// decode a whole payload into `out`, returns the number
+    /// of samples written
+    pub fn decode(self, bytes: &[u8], out: &mut [i16]) -> Result<usize, DecodeErr> {
+        let mut n = 0;
+        let mut push = |s: i16| {
+            *out.get_mut(n).ok_or(DecodeErr::OutputFull)? = s;
+            n += 1;
+            Ok(())
+        };
+        match self {
+            Encoding::Raw => {
+                let mut samples = bytes.chunks_exact(2);
+                for b in &mut samples {
+                    push(i16::from_le_bytes([b[0], b[1]]))?;
+                }
+                if !samples.remainder().is_empty() {
+                    return Err(DecodeErr::Truncated);
+                }
+            }
+            Encoding::Packed12 => {
+                let mut groups = bytes.chunks_exact(3);
+                for g in &mut groups {
+                    push((g[0] as u16 | (g[1] as u16 & 0xf) << 8) as i16)?;
+                    push((g[1] as u16 >> 4 | (g[2] as u16) << 4) as i16)?;
+                }
+                match *groups.remainder() {
+                    [] => (),
+                    [lo, hi] if hi <= 0xf => push(i16::from_le_bytes([lo, hi]))?,
+                    [_, _] => return Err(DecodeErr::Invalid),
+                    _ => return Err(DecodeErr::Truncated),
+                }
+            }
+            Encoding::Bits8 => {
+                for &b in bytes {
+                    push(b as i16)?;
+                }
+            }
+            Encoding::DeltaVarint => {
+                let (mut prev, mut z, mut shift) = (0i32, 0u32, 0);
+                for &b in bytes {
+                    if shift > 14 {
+                        return Err(DecodeErr::Invalid);
+                    }
+                    z |= ((b & 0x7f) as u32) << shift;
+                    shift += 7;
+                    if b & 0x80 != 0 {
+                        continue;
+                    }
+                    let s = prev + unzigzag(z);
+                    let s = i16::try_from(s).map_err(|_| DecodeErr::Invalid)?;
+                    push(s)?;
+                    prev = s as i32;
+                    z = 0;
+                    shift = 0;
+                }
+                if shift != 0 {
+                    return Err(DecodeErr::Truncated);
+                }
+            }
+            Encoding::Rle => {
+                let mut runs = bytes.chunks_exact(3);
+                for run in &mut runs {
+                    if run[0] == 0 {
+                        return Err(DecodeErr::Invalid);
+                    }
+                    let value = i16::from_le_bytes([run[1], run[2]]);
+                    for _ in 0..run[0] {
+                        push(value)?;
+                    }
+                }
+                if !runs.remainder().is_empty() {
+                    return Err(DecodeErr::Truncated);
+                }
+            }
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// largest payload the firmware sends
+    const PAYLOAD: usize = 255;
+
+    fn signals() -> Vec<Vec<i16>> {
+        let noise = (0..1000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 20) as i16)
+            .collect();
+        vec![
+            Vec::new(),
+            vec![7],
+            vec![0, 4095, 1],
+            (0..1001).map(|i| (i / 3) as i16).collect(),
+            vec![2048; 700],
+            noise,
+        ]
+    }
+
+    fn in_range(encoding: Encoding, s: i16) -> bool {
+        match encoding {
+            Encoding::Packed12 => (0..=0xfff).contains(&s),
+            Encoding::Bits8 => (0..=0xff).contains(&s),
+            _ => true,
+        }
+    }
+
+    /// split into payloads like the firmware, then decode each
+    fn round_trip(encoding: Encoding, samples: &[i16], payload: usize) -> (Vec<i16>, usize) {
+        let mut decoded = Vec::new();
+        let mut rest = samples;
+        let mut sent = 0;
+        let mut buf = vec![0u8; payload];
+        while !rest.is_empty() {
+            let (used, len) = encoding.encode(rest, &mut buf);
+            assert!(used > 0, "{:?} made no progress", encoding);
+            let mut out = vec![0i16; encoding.max_samples(len)];
+            let n = encoding.decode(&buf[..len], &mut out).unwrap();
+            assert_eq!(n, used, "{:?}", encoding);
+            decoded.extend_from_slice(&out[..n]);
+            rest = &rest[used..];
+            sent += len;
+        }
+        (decoded, sent)
+    }
+
+    #[test]
+    fn lossless_in_range() {
+        for encoding in Encoding::ALL {
+            for signal in signals() {
+                let signal: Vec<_> = signal.into_iter()
+                    .filter(|&s| in_range(encoding, s))
+                    .collect();
+                for payload in [3, 4, 5, PAYLOAD] {
+                    let (decoded, _) = round_trip(encoding, &signal, payload);
+                    assert_eq!(decoded, signal, "{:?} in {} byte payloads", encoding, payload);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn full_range() {
+        let extremes = [i16::MIN, i16::MAX, i16::MIN, 0, -1, i16::MAX];
+        for encoding in Encoding::ALL.iter().copied().filter(|e| e.signed()) {
+            let (decoded, _) = round_trip(encoding, &extremes, PAYLOAD);
+            assert_eq!(decoded, extremes, "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn clamped() {
+        let samples = [-3, 5000, 300];
+        let (decoded, _) = round_trip(Encoding::Packed12, &samples, PAYLOAD);
+        assert_eq!(decoded, [0, 4095, 300]);
+        let (decoded, _) = round_trip(Encoding::Bits8, &samples, PAYLOAD);
+        assert_eq!(decoded, [0, 255, 255]);
+    }
+
+    #[test]
+    fn narrow() {
+        let carried: Vec<_> = Encoding::ALL.iter().filter(|e| !matches!(e.max_bits(), Some(b) if b < 12)).collect();
+        assert_eq!(carried, [&Encoding::Raw, &Encoding::Packed12, &Encoding::DeltaVarint, &Encoding::Rle]);
+        assert_eq!(Encoding::Bits8.max_bits(), Some(8));
+    }
+
+    #[test]
+    fn max_bytes() {
+        // every step as large as 12 bits allow
+        let samples: Vec<i16> = (0..8).map(|i| if i % 2 == 0 { 0 } else { 4095 }).collect();
+        let mut out = [0u8; 64];
+        for encoding in Encoding::ALL {
+            let bits = encoding.max_bits().unwrap_or(12);
+            let samples: Vec<_> = samples.iter().map(|&s| s >> (12 - bits)).collect();
+            for n in 1..=samples.len() {
+                let (used, len) = encoding.encode(&samples[..n], &mut out);
+                assert_eq!(used, n);
+                assert!(len <= encoding.max_bytes(n, bits), "{:?} {} samples", encoding, n);
+            }
+        }
+        assert_eq!(Encoding::DeltaVarint.max_bytes(8, 12), 16);
+        assert_eq!(Encoding::DeltaVarint.max_bytes(8, 14), 24);
+    }
+
+    #[test]
+    fn raw_is_little_endian() {
+        let mut buf = [0u8; 4];
+        assert_eq!(Encoding::Raw.encode(&[0x0102, -2], &mut buf), (2, 4));
+        assert_eq!(buf, [0x02, 0x01, 0xfe, 0xff]);
+    }
+
+    #[test]
+    fn smaller_than_raw() {
+        let slow: Vec<i16> = (0..1000).map(|i| 1000 + (i / 10) as i16).collect();
+        let flat = vec![1234i16; 1000];
+        let raw = 2 * slow.len();
+        let size = |encoding, samples: &[i16]| round_trip(encoding, samples, PAYLOAD).1;
+        assert_eq!(size(Encoding::Packed12, &slow), raw * 3 / 4);
+        assert_eq!(size(Encoding::Bits8, &slow), raw / 2);
+        // every payload starts over from zero
+        assert!(size(Encoding::DeltaVarint, &slow) < raw * 6 / 10);
+        assert!(size(Encoding::Rle, &flat) < raw / 50);
+    }
+
+    #[test]
+    fn bad_payloads() {
+        let mut out = [0i16; 4];
+        let cases: [(Encoding, &[u8], DecodeErr); 7] = [
+            (Encoding::Raw, &[1, 2, 3], DecodeErr::Truncated),
+            (Encoding::Packed12, &[1], DecodeErr::Truncated),
+            (Encoding::Packed12, &[1, 0x10], DecodeErr::Invalid),
+            (Encoding::DeltaVarint, &[0x80], DecodeErr::Truncated),
+            (Encoding::DeltaVarint, &[0x80, 0x80, 0x80, 0x01], DecodeErr::Invalid),
+            (Encoding::Rle, &[0, 1, 0], DecodeErr::Invalid),
+            (Encoding::Rle, &[5, 1, 0], DecodeErr::OutputFull),
+        ];
+        for (encoding, bytes, err) in cases {
+            assert_eq!(encoding.decode(bytes, &mut out), Err(err), "{:?} {:?}", encoding, bytes);
+        }
+    }
+}
@@ -1,9 +1,23 @@
-#![feature(array_methods)]
 #![cfg_attr(not(test), no_std)]
 use serde::{Deserialize, Serialize};
 use core::convert::TryFrom;
 
-#[derive(Serialize, Deserialize, Debug)]
+mod batch;
+mod encoding;
+pub mod nrf52;
+mod pin;
+mod rate;
+mod scan;
+mod state;
+pub use batch::{Batch, MAX_BATCH};
+pub use encoding::{DecodeErr, Encoding};
+pub use pin::PinId;
+pub use rate::{stream_max_rate, RateLimits};
+pub use scan::{Plan, Scanner};
+pub use state::{Action, Input};
+
+/// what the device is doing, changed only through `Mode::next`
+#[derive(Serialize, Deserialize, Debug, defmt::Format, Copy, Clone, PartialEq)]
 pub enum Mode {
     Idle,
     Continues(SampleKind),
@@ -19,45 +33,176 @@ pub enum SampleKind {
 #[derive(Serialize, Deserialize, Debug, defmt::Format, Copy, Clone, PartialEq)]
 pub enum ConfigErr {
     UnavailibleSampler(Sampler),
-    PinTaken(Pin),
-    InvalidPin(Pin),
+    PinTaken(PinId),
+    InvalidPin(PinId),
     InvalidRate(u32),
+    InvalidBaud(u32),
     Unimplemented,
     CommunicationProblem,
+    /// more than the device can sample with the enabled
+    /// channels, `max` is the highest rate that fits
+    RateTooHigh { max: u32 },
+    /// the device is sampling, send `Stop` first
+    Busy,
+    /// `ConfigAction::RemovePin` or `MovePin` for a pin that
+    /// is not sampled
+    PinNotEnabled(PinId),
+    /// a `Command::ConfigBatch` can hold at most `max` actions
+    BatchTooLong { max: u8 },
+    /// the board uses the pin for something else, like the uart
+    PinReserved(PinId),
+    /// the encoding clamps readings of the configured resolution,
+    /// it carries at most `bits`
+    EncodingTooNarrow { bits: u8 },
 }
 
-pub type Pin = u8;
 pub type Sampler = u8;
 #[derive(Serialize, Deserialize, Debug, defmt::Format, Copy, Clone, PartialEq)]
 pub enum ConfigAction {
     ResetPins,
     /// add pin to measure
-    DigitalPins(Pin),
+    DigitalPins(PinId),
     /// add pin to measure
-    AnalogPins(Pin),
+    AnalogPins(PinId),
+    /// samples per second on every channel, answered with
+    /// `Reply::Rate`
     AnalogRate(u32),
+    /// how samples are packed in `Reply::Data`
+    Encoding(Encoding),
+    /// stop measuring a pin, the ones after it move up
+    RemovePin(PinId),
+    /// move an enabled pin to position `to` in the sample
+    /// order, past the end moves it to the end
+    MovePin { pin: PinId, to: u8 },
+    /// measure the first pin against the second on one channel.
+    /// Readings are signed, see `Encoding::signed`. `RemovePin`
+    /// and `MovePin` take either pin of the pair
+    AnalogDiffPair(PinId, PinId),
+}
+
+/// firmware version
+#[derive(Serialize, Deserialize, Debug, defmt::Format, Copy, Clone, PartialEq)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
 }
 
-#[derive(Serialize, Deserialize, Debug, defmt::Format, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, defmt::Format, Copy, Clone, PartialEq)]
 pub enum Command {
-    /// Stop continues sampling
+    /// Stop continues sampling or a burst, allowed while busy
+    /// like `Info` and `Ping`. Other commands are answered with
+    /// `ConfigErr::Busy` until the device is idle again
     Stop,
-    /// start sampling while sending back data
+    /// start sampling while sending back `Reply::Stream`, a
+    /// `Reply::Rate` comes first if the configured rate is lowered
     Continues(SampleKind),
-    /// start to sample as fast as possible, this can not be interrupted
-    /// once stated as the device stops listening to uart
-    /// until it is done
+    /// take a burst at the configured rate or as fast as
+    /// possible without one. A `Stop` ends it early, what was
+    /// taken is sent as usual
     Burst(SampleKind),
-    /// configure sampling
+    /// configure sampling, answered with `Reply::Ok`
+    /// or `Reply::Err`
     Config(ConfigAction),
+    /// ask what the device is, answered with
+    /// `Reply::Info` followed by `Reply::Id`
+    Info,
+    /// switch the uart to this baud rate, answered
+    /// with `Reply::Ok` at the old rate. A `Ping` has
+    /// to follow at the new rate within `BAUD_CONFIRM`
+    /// or the device goes back to `DEFAULT_BAUD`
+    SetBaud(u32),
+    /// answered with `Reply::Pong`
+    Ping,
+    /// answered with `Reply::Status`, allowed while busy
+    GetStatus,
+    /// answered with `Reply::Config`, allowed while busy
+    GetConfig,
+    /// followed by this many `Command::Config` frames that are
+    /// applied all or nothing, the rate is checked against the
+    /// pins the batch ends with. Answered with the reply to every
+    /// action in order, or with `Reply::BatchFailed` followed by
+    /// the `Reply::Err` of the first action that failed and the
+    /// config left as it was. While busy the whole batch gets a
+    /// single `ConfigErr::Busy`
+    ConfigBatch(u8),
 }
 
+/// baud rate after a reset
+pub const DEFAULT_BAUD: u32 = 9600;
+/// milliseconds the device waits for a `Ping`
+/// after changing the baud rate
+pub const BAUD_CONFIRM: u64 = 1000;
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum Reply {
     Done(u32),
     Data(u32),
     Err(ConfigErr),
+    /// config change applied
     Ok,
+    Info(Version),
+    /// unique id of this device
+    Id(u32),
+    Pong,
+    /// continues samples, followed by a payload of this
+    /// many bytes starting with a `StreamHeader`
+    Stream(u32),
+    /// samples the device could not send in time and
+    /// dropped, sent before the stream continues
+    Overrun { lost: u32 },
+    /// how well the sample rate was kept, followed by a
+    /// `Timing` payload of this many bytes. Sent right
+    /// before `Done`
+    Timing(u32),
+    /// the rate in Hz the device will sample at, the requested
+    /// one rounded to its timer. Sent first when a stream has to
+    /// go slower for the uart, see `stream_max_rate`
+    Rate(u32),
+    /// followed by a `Status` payload of this many bytes
+    Status(u32),
+    /// followed by a `DeviceConfig` payload of this many bytes
+    Config(u32),
+    /// index of the action that failed a `Command::ConfigBatch`,
+    /// followed by its `Reply::Err`
+    BatchFailed(u8),
+}
+
+/// starts the payload of `Reply::Stream`, the samples follow
+/// in the configured encoding
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StreamHeader {
+    /// counts packets, wraps around
+    pub seq: u16,
+    /// index of the first sample in the packet, counted from
+    /// the start of sampling including the lost ones
+    pub index: u32,
+}
+
+/// samples in a full `Reply::Stream`, a packet ends early at a gap
+/// or when sampling stops
+pub const STREAM_PACKET: usize = 8;
+
+impl StreamHeader {
+    pub const SIZE: usize = 6;
+
+    pub fn serialize(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0u8; Self::SIZE];
+        buf[..2].copy_from_slice(&self.seq.to_le_bytes());
+        buf[2..].copy_from_slice(&self.index.to_le_bytes());
+        buf
+    }
+
+    /// the header and the encoded samples after it
+    pub fn split(payload: &[u8]) -> Option<(Self, &[u8])> {
+        if payload.len() < Self::SIZE {
+            return None;
+        }
+        let (header, samples) = payload.split_at(Self::SIZE);
+        let seq = u16::from_le_bytes([header[0], header[1]]);
+        let index = u32::from_le_bytes([header[2], header[3], header[4], header[5]]);
+        Some((Self { seq, index }, samples))
+    }
 }
 
 impl Reply {
@@ -98,11 +243,168 @@ impl Command {
 pub struct Abilities {
     /// pins that can be configured to
     /// listen on
-    pub adc_pins: &'static [Pin],
+    pub adc_pins: &'static [PinId],
+    /// pins that can be sampled as digital
+    /// inputs
+    pub digital_pins: &'static [PinId],
     /// resolution in bits
     pub adc_res: &'static [u8],
     /// voltage reference options
     pub adc_ref: &'static [&'static str],
+    /// uart baud rates `Command::SetBaud`
+    /// accepts
+    pub baud_rates: &'static [u32],
+    /// sample encodings the firmware can send
+    pub encodings: &'static [Encoding],
+    /// how fast it can sample
+    pub rate: RateLimits,
+}
+
+/// timing of one capture as measured on the device
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Timing {
+    pub samples: u32,
+    /// samples taken after their deadline had passed
+    pub missed: u32,
+    /// shortest time between two samples in microseconds
+    pub min_interval: u32,
+    /// longest time between two samples in microseconds
+    pub max_interval: u32,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self { samples: 0, missed: 0, min_interval: u32::MAX, max_interval: 0 }
+    }
+}
+
+impl Timing {
+    pub const SIZE: usize = 16;
+
+    /// count one sample, `interval` is the time since the one
+    /// before in microseconds
+    pub fn record(&mut self, interval: Option<u32>, late: bool) {
+        self.samples += 1;
+        self.missed += late as u32;
+        if let Some(interval) = interval {
+            self.min_interval = self.min_interval.min(interval);
+            self.max_interval = self.max_interval.max(interval);
+        }
+    }
+
+    /// difference between the longest and shortest interval,
+    /// none with less than two samples
+    pub fn jitter(&self) -> Option<u32> {
+        self.max_interval.checked_sub(self.min_interval)
+    }
+
+    pub fn serialize(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0u8; Self::SIZE];
+        let fields = [self.samples, self.missed, self.min_interval, self.max_interval];
+        for (chunk, field) in buf.chunks_exact_mut(4).zip(fields.iter()) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        buf
+    }
+
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < Self::SIZE {
+            return None;
+        }
+        let field = |i: usize| u32::from_le_bytes([payload[i], payload[i+1], payload[i+2], payload[i+3]]);
+        Some(Self {
+            samples: field(0),
+            missed: field(4),
+            min_interval: field(8),
+            max_interval: field(12),
+        })
+    }
+}
+
+/// what the device is doing, the payload of `Reply::Status`
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Status {
+    pub mode: Mode,
+    /// the last config change that was refused
+    pub last_error: Option<ConfigErr>,
+    /// milliseconds since the device started
+    pub uptime_ms: u64,
+    /// continues samples waiting to be sent
+    pub buffered: u16,
+    /// samples that fit in the buffer
+    pub capacity: u16,
+}
+
+impl Status {
+    /// largest payload a status can need
+    pub const MAX_SIZE: usize = 32;
+
+    pub fn serialize<'b>(&self, buf: &'b mut [u8; Self::MAX_SIZE]) -> &'b [u8] {
+        postcard::to_slice(self, buf).unwrap()
+    }
+
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        postcard::from_bytes(payload).ok()
+    }
+}
+
+/// the configuration in use, the payload of `Reply::Config`
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct DeviceConfig {
+    analog: [PinId; Self::MAX_PINS],
+    /// negative pin of a differential channel
+    minus: [Option<PinId>; Self::MAX_PINS],
+    analog_len: u8,
+    /// rate in Hz, none samples as fast as possible
+    pub rate: Option<u32>,
+    /// bits per sample
+    pub resolution: u8,
+    pub encoding: Encoding,
+}
+
+impl DeviceConfig {
+    /// most analog pins a config can list
+    pub const MAX_PINS: usize = 8;
+    /// largest payload a config can need
+    pub const MAX_SIZE: usize = 64;
+
+    /// a channel is its pin and for a differential one the negative
+    /// pin, channels past `MAX_PINS` are left out
+    pub fn new(analog: impl IntoIterator<Item = (PinId, Option<PinId>)>, rate: Option<u32>, resolution: u8, encoding: Encoding) -> Self {
+        let mut pins = [PinId::new(0, 0); Self::MAX_PINS];
+        let mut minus = [None; Self::MAX_PINS];
+        let mut len = 0;
+        for ((slot, minus), (pin, negative)) in pins.iter_mut().zip(minus.iter_mut()).zip(analog) {
+            *slot = pin;
+            *minus = negative;
+            len += 1;
+        }
+        Self { analog: pins, minus, analog_len: len, rate, resolution, encoding }
+    }
+
+    fn len(&self) -> usize {
+        (self.analog_len as usize).min(Self::MAX_PINS)
+    }
+
+    /// analog pins in the order they are sampled, the positive
+    /// one for a differential channel
+    pub fn analog(&self) -> &[PinId] {
+        &self.analog[..self.len()]
+    }
+
+    /// the negative pin of every channel in `analog`, none for
+    /// a single ended one
+    pub fn minus(&self) -> &[Option<PinId>] {
+        &self.minus[..self.len()]
+    }
+
+    pub fn serialize<'b>(&self, buf: &'b mut [u8; Self::MAX_SIZE]) -> &'b [u8] {
+        postcard::to_slice(self, buf).unwrap()
+    }
+
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        postcard::from_bytes(payload).ok()
+    }
 }
 
 #[cfg(test)]
@@ -112,12 +414,22 @@ mod tests {
     mod commands {
         use super::*;
 
-        const COMMANDS: [Command; 5] = [
+        const COMMANDS: [Command; 15] = [
             Command::Stop,
             Command::Continues(SampleKind::Analog),
             Command::Burst(SampleKind::Digital),
-            Command::Config(ConfigAction::AnalogPins(0u8)),
+            Command::Config(ConfigAction::AnalogPins(PinId::new(u8::MAX, u8::MAX))),
             Command::Config(ConfigAction::AnalogRate(0u32)),
+            Command::Config(ConfigAction::Encoding(Encoding::Rle)),
+            Command::Config(ConfigAction::RemovePin(PinId::new(u8::MAX, u8::MAX))),
+            Command::Config(ConfigAction::MovePin { pin: PinId::new(u8::MAX, u8::MAX), to: u8::MAX }),
+            Command::Config(ConfigAction::AnalogDiffPair(PinId::new(u8::MAX, u8::MAX), PinId::new(u8::MAX, u8::MAX))),
+            Command::ConfigBatch(u8::MAX),
+            Command::Info,
+            Command::SetBaud(u32::MAX),
+            Command::Ping,
+            Command::GetStatus,
+            Command::GetConfig,
         ];
 
         #[test]
@@ -140,10 +452,27 @@ mod tests {
     mod reply {
         use super::*;
 
-        const REPLIES: [Reply; 3] = [
+        const REPLIES: [Reply; 20] = [
             Reply::Ok,
             Reply::Err(ConfigErr::InvalidRate(u32::MAX)),
             Reply::Data(u32::MAX),
+            Reply::Info(Version { major: u8::MAX, minor: u8::MAX, patch: u8::MAX }),
+            Reply::Id(u32::MAX),
+            Reply::Pong,
+            Reply::Err(ConfigErr::InvalidBaud(u32::MAX)),
+            Reply::Stream(u32::MAX),
+            Reply::Overrun { lost: u32::MAX },
+            Reply::Timing(u32::MAX),
+            Reply::Rate(u32::MAX),
+            Reply::Err(ConfigErr::RateTooHigh { max: u32::MAX }),
+            Reply::Err(ConfigErr::Busy),
+            Reply::Status(u32::MAX),
+            Reply::Config(u32::MAX),
+            Reply::BatchFailed(u8::MAX),
+            Reply::Err(ConfigErr::PinNotEnabled(PinId::new(u8::MAX, u8::MAX))),
+            Reply::Err(ConfigErr::BatchTooLong { max: u8::MAX }),
+            Reply::Err(ConfigErr::PinReserved(PinId::new(u8::MAX, u8::MAX))),
+            Reply::Err(ConfigErr::EncodingTooNarrow { bits: u8::MAX }),
         ];
 
         #[test]
@@ -161,5 +490,66 @@ mod tests {
                 assert_eq!(&deserialized, rply);
             }
         }
+
+        #[test]
+        fn stream_header() {
+            let header = StreamHeader { seq: u16::MAX, index: 0x0102_0304 };
+            let mut payload = header.serialize().to_vec();
+            payload.push(7);
+            assert_eq!(StreamHeader::split(&payload), Some((header, &[7u8][..])));
+            assert_eq!(StreamHeader::split(&payload[..5]), None);
+        }
+
+        #[test]
+        fn timing() {
+            let mut timing = Timing::default();
+            assert_eq!(timing.jitter(), None);
+            timing.record(None, false);
+            timing.record(Some(1000), true);
+            timing.record(Some(1200), false);
+            assert_eq!(timing.samples, 3);
+            assert_eq!(timing.missed, 1);
+            assert_eq!(timing.jitter(), Some(200));
+
+            let buf = timing.serialize();
+            assert_eq!(Timing::parse(&buf), Some(timing));
+            assert_eq!(Timing::parse(&buf[..Timing::SIZE - 1]), None);
+        }
+
+        #[test]
+        fn status() {
+            let largest = Status {
+                mode: Mode::Continues(SampleKind::Digital),
+                last_error: Some(ConfigErr::InvalidBaud(u32::MAX)),
+                uptime_ms: u64::MAX,
+                buffered: u16::MAX,
+                capacity: u16::MAX,
+            };
+            let mut buf = [0u8; Status::MAX_SIZE];
+            let payload = largest.serialize(&mut buf);
+            assert_eq!(Status::parse(payload), Some(largest));
+            assert_eq!(Status::parse(&payload[..payload.len() - 1]), None);
+        }
+
+        #[test]
+        fn config() {
+            let pin = |p| PinId::new(0, p);
+            let channels = vec![(pin(30), None), (pin(2), Some(pin(3))), (pin(31), None)];
+            let config = DeviceConfig::new(channels, Some(u32::MAX), 12, Encoding::Packed12);
+            assert_eq!(config.analog(), &[pin(30), pin(2), pin(31)]);
+            assert_eq!(config.minus(), &[None, Some(pin(3)), None]);
+            let mut buf = [0u8; DeviceConfig::MAX_SIZE];
+            let payload = config.serialize(&mut buf);
+            assert_eq!(DeviceConfig::parse(payload), Some(config));
+
+            // the largest config fits
+            let pairs = (0..8).map(|p| (PinId::new(u8::MAX, p), Some(PinId::new(u8::MAX, p + 8))));
+            let config = DeviceConfig::new(pairs, Some(u32::MAX), 14, Encoding::DeltaVarint);
+            assert_eq!(DeviceConfig::parse(config.serialize(&mut buf)), Some(config));
+
+            let full = DeviceConfig::new((0..20).map(|p| (PinId::new(1, p), None)), None, 8, Encoding::Raw);
+            assert_eq!(full.analog().len(), DeviceConfig::MAX_PINS);
+            assert_eq!(full.analog()[7], PinId::new(1, 7));
+        }
     }
 }
@@ -0,0 +1,44 @@
+//! What the nrf52 scope can do. The firmware describes itself with
+//! these and the viewer checks against the same, bump the minor of
+//! `VERSION` when they change
+
+use crate::{Abilities, Encoding, PinId, RateLimits, Version};
+
+pub const VERSION: Version = Version {
+    major: 0,
+    minor: 1,
+    patch: 0,
+};
+
+/// named after the arduino header of the nRF52 DK, P0.05 is AIN3
+/// but the uart cts. The firmware board description has to match
+pub const ADC_PINS: &[PinId] = &[
+    PinId::new(0, 2).analog(0).named("AREF"),
+    PinId::new(0, 3).analog(1).named("A0"),
+    PinId::new(0, 4).analog(2).named("A1"),
+    PinId::new(0, 28).analog(4).named("A2"),
+    PinId::new(0, 29).analog(5).named("A3"),
+    PinId::new(0, 30).analog(6).named("A4"),
+    PinId::new(0, 31).analog(7).named("A5"),
+];
+
+/// bits per sample the saadc is set up with
+pub const RESOLUTION: u8 = 12;
+
+/// bursts are timed by TIMER1 at 16 MHz, each sample takes 3 us
+/// acquisition and 2 us conversion which gives the 200 kS/s of the
+/// saadc
+pub const RATE_LIMITS: RateLimits = RateLimits {
+    tick_hz: 16_000_000,
+    conversion_us: &[(8, 5), (10, 5), (12, 5), (14, 5)],
+};
+
+pub const ABILITIES: Abilities = Abilities {
+    adc_pins: ADC_PINS,
+    digital_pins: &[], // digital sampling is not implemented yet
+    adc_res: &[8, 10, 12, 14],
+    adc_ref: &["internal (0.6 V)", "VDD/4"],
+    baud_rates: &[9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600, 1000000],
+    encodings: &Encoding::ALL,
+    rate: RATE_LIMITS,
+};
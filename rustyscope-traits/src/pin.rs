@@ -0,0 +1,153 @@
+//! Which pin is meant. Only the port and number go over the wire,
+//! the analog input and the name come from the board description
+
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// a pin of the device, two ids are the same pin when port and
+/// number match. `ain` and `name` only describe it
+#[derive(Debug, defmt::Format, Copy, Clone)]
+pub struct PinId {
+    pub port: u8,
+    pub pin: u8,
+    /// analog input the pin is connected to
+    pub ain: Option<u8>,
+    /// what the board calls it, empty without a name
+    pub name: &'static str,
+}
+
+impl PinId {
+    pub const fn new(port: u8, pin: u8) -> Self {
+        Self { port, pin, ain: None, name: "" }
+    }
+
+    pub const fn analog(self, ain: u8) -> Self {
+        Self { ain: Some(ain), ..self }
+    }
+
+    pub const fn named(self, name: &'static str) -> Self {
+        Self { name, ..self }
+    }
+
+    /// `==` for const contexts
+    pub const fn same(&self, other: &PinId) -> bool {
+        self.port == other.port && self.pin == other.pin
+    }
+
+    /// the description of this pin in `known`, itself if it is not
+    /// in there. Ids that went over the wire lost theirs
+    pub fn resolve(self, known: &[PinId]) -> Self {
+        known.iter().copied().find(|p| p.same(&self)).unwrap_or(self)
+    }
+
+    /// read a pin as a user writes it: `30` for a pin on port 0,
+    /// `P0.30`, `P0.30/AIN6`, `AIN6` or its name. The last two
+    /// only find pins in `known`
+    pub fn parse(text: &str, known: &[PinId]) -> Option<Self> {
+        let text = text.trim();
+        let text = text.split('/').next().unwrap_or(text);
+        if let Ok(pin) = text.parse() {
+            return Some(Self::new(0, pin).resolve(known));
+        }
+        if let Some(rest) = text.strip_prefix('P').or_else(|| text.strip_prefix('p')) {
+            if let Some(dot) = rest.find('.') {
+                let port = rest[..dot].parse().ok()?;
+                let pin = rest[dot + 1..].parse().ok()?;
+                return Some(Self::new(port, pin).resolve(known));
+            }
+        }
+        if text.len() > 3 && text.is_char_boundary(3) && text[..3].eq_ignore_ascii_case("ain") {
+            let ain = text[3..].parse().ok()?;
+            return known.iter().copied().find(|p| p.ain == Some(ain));
+        }
+        known.iter().copied().find(|p| !p.name.is_empty() && p.name.eq_ignore_ascii_case(text))
+    }
+}
+
+impl PartialEq for PinId {
+    fn eq(&self, other: &Self) -> bool {
+        self.same(other)
+    }
+}
+
+impl Eq for PinId {}
+
+impl Hash for PinId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.port, self.pin).hash(state)
+    }
+}
+
+/// `P0.30/AIN6`, the name is left out
+impl fmt::Display for PinId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "P{}.{:02}", self.port, self.pin)?;
+        if let Some(ain) = self.ain {
+            write!(f, "/AIN{}", ain)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for PinId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.port, self.pin).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PinId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (port, pin) = <(u8, u8)>::deserialize(deserializer)?;
+        Ok(Self::new(port, pin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KNOWN: [PinId; 2] = [
+        PinId::new(0, 2).analog(0).named("AREF"),
+        PinId::new(0, 30).analog(6).named("A4"),
+    ];
+
+    #[test]
+    fn shown() {
+        assert_eq!(KNOWN[1].to_string(), "P0.30/AIN6");
+        assert_eq!(PinId::new(1, 5).to_string(), "P1.05");
+    }
+
+    #[test]
+    fn same_pin() {
+        assert_eq!(PinId::new(0, 30), KNOWN[1]);
+        assert_ne!(PinId::new(1, 30), KNOWN[1]);
+        let resolved = PinId::new(0, 30).resolve(&KNOWN);
+        assert_eq!((resolved.ain, resolved.name), (Some(6), "A4"));
+        assert_eq!(PinId::new(0, 7).resolve(&KNOWN).ain, None);
+    }
+
+    #[test]
+    fn two_bytes_on_the_wire() {
+        let mut buf = [0u8; 8];
+        let bytes = postcard::to_slice(&KNOWN[1], &mut buf).unwrap();
+        assert_eq!(bytes, &[0, 30]);
+        let back: PinId = postcard::from_bytes(bytes).unwrap();
+        assert_eq!(back, KNOWN[1]);
+        assert_eq!(back.name, "");
+    }
+
+    #[test]
+    fn parsed() {
+        for text in ["30", "P0.30", "p0.30", "P0.30/AIN6", "AIN6", "ain6", "A4", "a4"].iter() {
+            let pin = PinId::parse(text, &KNOWN).unwrap();
+            assert_eq!(pin, KNOWN[1], "{}", text);
+            assert_eq!(pin.name, "A4", "{}", text);
+        }
+        assert_eq!(PinId::parse("P1.05", &KNOWN), Some(PinId::new(1, 5)));
+        assert_eq!(PinId::parse("7", &KNOWN), Some(PinId::new(0, 7)));
+        assert_eq!(PinId::parse("AIN3", &KNOWN), None);
+        assert_eq!(PinId::parse("A9", &KNOWN), None);
+        assert_eq!(PinId::parse("P0.x", &KNOWN), None);
+    }
+}
@@ -0,0 +1,135 @@
+//! How fast a device can sample, shared so the firmware and the host
+//! agree on what `ConfigAction::AnalogRate` ends up as
+
+use crate::{ConfigErr, Encoding, Reply, StreamHeader, STREAM_PACKET};
+
+/// what limits the sample rate of a device
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RateLimits {
+    /// ticks per second of the timer samples are scheduled on
+    pub tick_hz: u32,
+    /// microseconds one sample takes at a resolution in bits
+    pub conversion_us: &'static [(u8, u32)],
+}
+
+impl RateLimits {
+    /// shortest period in ticks that leaves time to sample every
+    /// channel once, `None` for an unsupported resolution
+    fn min_ticks(&self, channels: usize, resolution: u8) -> Option<u64> {
+        let (_, us) = self.conversion_us.iter().find(|(bits, _)| *bits == resolution)?;
+        // no pins yet still takes one sample worth of time
+        let busy = channels.max(1) as u64 * *us as u64;
+        let ticks = (self.tick_hz as u64 * busy).saturating_sub(1) / 1_000_000 + 1;
+        Some(ticks)
+    }
+
+    /// highest rate per channel in Hz
+    pub fn max_rate(&self, channels: usize, resolution: u8) -> Option<u32> {
+        let ticks = self.min_ticks(channels, resolution)?;
+        Some((self.tick_hz as u64 / ticks) as u32)
+    }
+
+    /// ticks between two rounds of samples, `rate` is rounded
+    /// to the nearest whole tick
+    pub fn period(&self, rate: u32, channels: usize, resolution: u8) -> Result<u32, ConfigErr> {
+        let max = self.max_rate(channels, resolution).ok_or(ConfigErr::InvalidRate(rate))?;
+        if rate == 0 {
+            return Err(ConfigErr::InvalidRate(rate));
+        }
+        if rate > max {
+            return Err(ConfigErr::RateTooHigh { max });
+        }
+        Ok((self.tick_hz + rate / 2) / rate)
+    }
+
+    /// whether every channel can still be sampled within a period
+    /// of `ticks`, used when channels are added after the rate
+    pub fn fits(&self, ticks: u32, channels: usize, resolution: u8) -> Result<(), ConfigErr> {
+        let min = self.min_ticks(channels, resolution).ok_or(ConfigErr::InvalidRate(self.rate(ticks)))?;
+        if (ticks as u64) < min {
+            return Err(ConfigErr::RateTooHigh { max: (self.tick_hz as u64 / min) as u32 });
+        }
+        Ok(())
+    }
+
+    /// rate in Hz a period of `ticks` gives, rounded
+    pub fn rate(&self, ticks: u32) -> u32 {
+        (self.tick_hz + ticks / 2) / ticks.max(1)
+    }
+}
+
+/// highest rate per channel in Hz a stream keeps up with at `baud`.
+/// The uart and not the timer is what limits it, a byte takes 10 bits
+/// on the wire and every packet carries a reply and a header
+pub fn stream_max_rate(baud: u32, channels: usize, resolution: u8, encoding: Encoding) -> u32 {
+    let packet = Reply::SIZE + StreamHeader::SIZE + encoding.max_bytes(STREAM_PACKET, resolution);
+    let samples = baud as u64 / 10 * STREAM_PACKET as u64 / packet as u64;
+    (samples / channels.max(1) as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 32.768 kHz rtc and 20 us a sample
+    const NRF: RateLimits = RateLimits {
+        tick_hz: 32_768,
+        conversion_us: &[(8, 20), (10, 20), (12, 20), (14, 20)],
+    };
+
+    #[test]
+    fn max_rate() {
+        assert_eq!(NRF.max_rate(0, 12), Some(32_768));
+        assert_eq!(NRF.max_rate(1, 12), Some(32_768));
+        assert_eq!(NRF.max_rate(2, 12), Some(16_384));
+        // 160 us is 5.2 ticks so 6 are needed
+        assert_eq!(NRF.max_rate(8, 12), Some(5_461));
+        assert_eq!(NRF.max_rate(1, 9), None);
+    }
+
+    #[test]
+    fn rounded_to_tick() {
+        let ticks = NRF.period(1000, 1, 12).unwrap();
+        assert_eq!(ticks, 33);
+        assert_eq!(NRF.rate(ticks), 993);
+        assert_eq!(NRF.rate(NRF.period(1, 1, 12).unwrap()), 1);
+        assert_eq!(NRF.rate(NRF.period(16_384, 2, 12).unwrap()), 16_384);
+    }
+
+    #[test]
+    fn max_is_accepted() {
+        for channels in 0..=8 {
+            let max = NRF.max_rate(channels, 12).unwrap();
+            let ticks = NRF.period(max, channels, 12).unwrap();
+            assert!(ticks as u64 >= NRF.min_ticks(channels, 12).unwrap());
+            // the reported rate is rounded, max is not
+            assert!(NRF.rate(ticks) <= max + 1);
+        }
+    }
+
+    #[test]
+    fn refused() {
+        assert_eq!(NRF.period(0, 1, 12), Err(ConfigErr::InvalidRate(0)));
+        assert_eq!(NRF.period(40_000, 1, 12), Err(ConfigErr::RateTooHigh { max: 32_768 }));
+        assert_eq!(NRF.period(6_000, 8, 12), Err(ConfigErr::RateTooHigh { max: 5_461 }));
+        assert_eq!(NRF.period(100, 1, 9), Err(ConfigErr::InvalidRate(100)));
+    }
+
+    #[test]
+    fn stream() {
+        // 28 bytes a packet of 8 raw samples
+        assert_eq!(stream_max_rate(9600, 1, 12, Encoding::Raw), 274);
+        assert_eq!(stream_max_rate(9600, 2, 12, Encoding::Raw), 137);
+        assert_eq!(stream_max_rate(9600, 0, 12, Encoding::Raw), 274);
+        assert_eq!(stream_max_rate(9600, 1, 12, Encoding::Packed12), 320);
+        assert_eq!(stream_max_rate(1_000_000, 1, 8, Encoding::Bits8), 40_000);
+    }
+
+    #[test]
+    fn channel_added() {
+        let ticks = NRF.period(16_384, 2, 12).unwrap();
+        assert_eq!(NRF.fits(ticks, 2, 12), Ok(()));
+        assert_eq!(NRF.fits(ticks, 3, 12), Ok(()));
+        assert_eq!(NRF.fits(ticks, 4, 12), Err(ConfigErr::RateTooHigh { max: 10_922 }));
+    }
+}
@@ -0,0 +1,207 @@
+//! Hardware timed bursts: a timer starts a scan of every channel each
+//! period and EasyDMA writes the results straight into the capture
+//! buffer. The planning lives here so it can be tested on the host
+
+use crate::{ConfigErr, RateLimits, Timing};
+
+/// the peripherals that take a burst
+pub trait Scanner {
+    /// ticks per second of the timer that starts each scan
+    const TIMER_HZ: u32;
+    /// most samples one EasyDMA transfer can hold
+    const MAX_TRANSFER: usize;
+    /// scan every enabled channel each `period` ticks until `buf`
+    /// is full, the first scan starts right away.
+    ///
+    /// # Safety
+    /// `buf` is written in the background, it has to stay in place
+    /// and untouched until `stop` returns
+    unsafe fn start(&mut self, period: u32, buf: &mut [i16]);
+    /// whether the buffer given to `start` is full
+    fn done(&self) -> bool;
+    /// end the scans, returns the number of samples written
+    fn stop(&mut self) -> usize;
+}
+
+/// how a burst will be taken
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Plan {
+    /// timer ticks between the start of two scans
+    pub period: u32,
+    /// number of scans
+    pub rounds: usize,
+    pub channels: usize,
+}
+
+impl Plan {
+    /// as many whole rounds as fit `capacity` samples and a single
+    /// transfer, `ticks` is the period the rate was rounded to. Fails
+    /// without channels or when not even one scan fits
+    pub fn new<S: Scanner>(ticks: u32, channels: usize, capacity: usize) -> Result<Self, ConfigErr> {
+        if channels == 0 || ticks == 0 {
+            return Err(ConfigErr::InvalidRate(0));
+        }
+        let rounds = capacity.min(S::MAX_TRANSFER) / channels;
+        if rounds == 0 {
+            return Err(ConfigErr::UnavailibleSampler(channels as u8));
+        }
+        Ok(Self { period: ticks, rounds, channels })
+    }
+
+    /// fastest plan `limits` allow for `channels`
+    pub fn fastest<S: Scanner>(limits: &RateLimits, channels: usize, resolution: u8, capacity: usize)
+        -> Result<Self, ConfigErr> {
+        let max = limits.max_rate(channels, resolution).ok_or(ConfigErr::InvalidRate(0))?;
+        let ticks = limits.period(max, channels, resolution)?;
+        Self::new::<S>(ticks, channels, capacity)
+    }
+
+    /// samples the burst holds
+    pub fn samples(&self) -> usize {
+        self.rounds * self.channels
+    }
+
+    /// check on a running burst and end it early if `abort` is set.
+    /// Once it is over returns the part of the plan that was taken,
+    /// a scan cut short by the abort is left out
+    pub fn poll<S: Scanner>(&self, scanner: &mut S, abort: bool) -> Option<Self> {
+        if !abort && !scanner.done() {
+            return None;
+        }
+        let written = scanner.stop().min(self.samples());
+        Some(Self { rounds: written / self.channels, ..*self })
+    }
+
+    fn period_us(&self, tick_hz: u32) -> u32 {
+        (self.period as u64 * 1_000_000 / tick_hz as u64) as u32
+    }
+
+    /// microseconds from the first to the end of the last scan period
+    pub fn duration_us(&self, tick_hz: u32) -> u64 {
+        self.rounds as u64 * self.period as u64 * 1_000_000 / tick_hz as u64
+    }
+
+    /// the timer spaces every scan exactly, no scan can be late
+    pub fn timing(&self, tick_hz: u32) -> Timing {
+        let interval = self.period_us(tick_hz);
+        Timing {
+            samples: self.rounds as u32,
+            missed: 0,
+            min_interval: interval,
+            max_interval: interval,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// takes one sample each time it is asked if it is done
+    #[derive(Default)]
+    struct Fake {
+        period: u32,
+        len: usize,
+        written: usize,
+        stopped: bool,
+    }
+
+    impl Scanner for Fake {
+        const TIMER_HZ: u32 = 16_000_000;
+        const MAX_TRANSFER: usize = 100;
+
+        unsafe fn start(&mut self, period: u32, buf: &mut [i16]) {
+            self.period = period;
+            self.len = buf.len();
+        }
+
+        fn done(&self) -> bool {
+            self.written == self.len
+        }
+
+        fn stop(&mut self) -> usize {
+            self.stopped = true;
+            self.written
+        }
+    }
+
+    impl Fake {
+        fn tick(&mut self) {
+            self.written = (self.written + 1).min(self.len);
+        }
+    }
+
+    const LIMITS: RateLimits = RateLimits {
+        tick_hz: Fake::TIMER_HZ,
+        conversion_us: &[(12, 5)],
+    };
+
+    #[test]
+    fn whole_rounds() {
+        let plan = Plan::new::<Fake>(160, 3, 50).unwrap();
+        assert_eq!(plan.rounds, 16);
+        assert_eq!(plan.samples(), 48);
+        // a single transfer caps the burst
+        assert_eq!(Plan::new::<Fake>(160, 3, 2000).unwrap().rounds, 33);
+    }
+
+    #[test]
+    fn refused() {
+        assert_eq!(Plan::new::<Fake>(160, 0, 50), Err(ConfigErr::InvalidRate(0)));
+        assert_eq!(Plan::new::<Fake>(0, 1, 50), Err(ConfigErr::InvalidRate(0)));
+        assert_eq!(Plan::new::<Fake>(160, 8, 5), Err(ConfigErr::UnavailibleSampler(8)));
+    }
+
+    #[test]
+    fn fastest() {
+        // 200 kS/s shared by the channels
+        let plan = Plan::fastest::<Fake>(&LIMITS, 1, 12, 100).unwrap();
+        assert_eq!(plan.period, 80);
+        let plan = Plan::fastest::<Fake>(&LIMITS, 4, 12, 100).unwrap();
+        assert_eq!(plan.period, 320);
+        assert_eq!(LIMITS.max_rate(4, 12), Some(50_000));
+    }
+
+    #[test]
+    fn timing() {
+        let plan = Plan::new::<Fake>(16_000, 2, 100).unwrap();
+        assert_eq!(plan.duration_us(Fake::TIMER_HZ), 50_000);
+        let timing = plan.timing(Fake::TIMER_HZ);
+        assert_eq!(timing.samples, 50);
+        assert_eq!(timing.jitter(), Some(0));
+        assert_eq!(timing.min_interval, 1000);
+    }
+
+    #[test]
+    fn runs_to_the_end() {
+        let mut scanner = Fake::default();
+        let plan = Plan::new::<Fake>(320, 2, 7).unwrap();
+        let mut buf = [0i16; 7];
+        unsafe { scanner.start(plan.period, &mut buf[..plan.samples()]) };
+        assert_eq!((scanner.period, scanner.len), (320, 6));
+        for _ in 0..5 {
+            scanner.tick();
+            assert_eq!(plan.poll(&mut scanner, false), None);
+        }
+        scanner.tick();
+        assert_eq!(plan.poll(&mut scanner, false), Some(plan));
+        assert!(scanner.stopped);
+    }
+
+    #[test]
+    fn aborted() {
+        let mut scanner = Fake::default();
+        let plan = Plan::new::<Fake>(320, 2, 100).unwrap();
+        let mut buf = [0i16; 100];
+        unsafe { scanner.start(plan.period, &mut buf[..plan.samples()]) };
+        for _ in 0..7 {
+            scanner.tick();
+        }
+        // the fourth scan was cut short
+        let taken = plan.poll(&mut scanner, true).unwrap();
+        assert_eq!(taken.rounds, 3);
+        assert_eq!(taken.samples(), 6);
+        assert_eq!(taken.timing(Fake::TIMER_HZ).samples, 3);
+    }
+}
@@ -0,0 +1,283 @@
+//! The protocol as a state machine: what the device does with every
+//! command depending on what it is doing. It only decides, the
+//! firmware carries out the `Action` so this can be tested on the host
+
+use crate::{Command, ConfigAction, ConfigErr, Mode, Reply, SampleKind};
+
+/// what can change the mode
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Input {
+    Command(Command),
+    /// the sampler sent the last of a burst
+    BurstDone,
+}
+
+/// what the device has to do after a transition
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// nothing to send, `Stop` and starting to sample are not answered
+    Nothing,
+    Reply(Reply),
+    /// apply the change and answer with the outcome
+    ApplyConfig(ConfigAction),
+    /// apply the `Batch` read after the command, see
+    /// `Command::ConfigBatch` for the answer
+    ApplyBatch,
+    /// send `Reply::Info` followed by `Reply::Id`
+    SendInfo,
+    SendStatus,
+    SendConfig,
+    /// switch the uart, see `Command::SetBaud`
+    ChangeBaud(u32),
+}
+
+impl Mode {
+    /// the mode after `input` and what to do about it. While sampling
+    /// only `Stop` and queries are accepted, the rest gets `ConfigErr::Busy`.
+    /// Digital sampling is refused with `ConfigErr::Unimplemented`
+    pub fn next(self, input: Input) -> (Mode, Action) {
+        let command = match input {
+            Input::BurstDone => {
+                let next = match self {
+                    Mode::Burst(_) => Mode::Idle,
+                    other => other,
+                };
+                return (next, Action::Nothing);
+            }
+            Input::Command(command) => command,
+        };
+
+        let busy = self != Mode::Idle;
+        match command {
+            Command::Stop => (Mode::Idle, Action::Nothing),
+            Command::Info => (self, Action::SendInfo),
+            Command::Ping => (self, Action::Reply(Reply::Pong)),
+            Command::GetStatus => (self, Action::SendStatus),
+            Command::GetConfig => (self, Action::SendConfig),
+            _ if busy => (self, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            Command::Continues(SampleKind::Digital) | Command::Burst(SampleKind::Digital) => {
+                (self, Action::Reply(Reply::Err(ConfigErr::Unimplemented)))
+            }
+            Command::Continues(kind) => (Mode::Continues(kind), Action::Nothing),
+            Command::Burst(kind) => (Mode::Burst(kind), Action::Nothing),
+            Command::Config(change) => (self, Action::ApplyConfig(change)),
+            Command::ConfigBatch(_) => (self, Action::ApplyBatch),
+            Command::SetBaud(rate) => (self, Action::ChangeBaud(rate)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SampleKind::{Analog, Digital};
+
+    const IDLE: Mode = Mode::Idle;
+    const CA: Mode = Mode::Continues(Analog);
+    const CD: Mode = Mode::Continues(Digital);
+    const BA: Mode = Mode::Burst(Analog);
+    const BD: Mode = Mode::Burst(Digital);
+
+    const RATE: ConfigAction = ConfigAction::AnalogRate(1000);
+    const STOP: Input = Input::Command(Command::Stop);
+    const CONT: Input = Input::Command(Command::Continues(Analog));
+    const CONT_D: Input = Input::Command(Command::Continues(Digital));
+    const BURST: Input = Input::Command(Command::Burst(Analog));
+    const BURST_D: Input = Input::Command(Command::Burst(Digital));
+    const CFG: Input = Input::Command(Command::Config(RATE));
+    const BATCH: Input = Input::Command(Command::ConfigBatch(2));
+    const INFO: Input = Input::Command(Command::Info);
+    const BAUD: Input = Input::Command(Command::SetBaud(115_200));
+    const PING: Input = Input::Command(Command::Ping);
+    const STATUS: Input = Input::Command(Command::GetStatus);
+    const CONFIG: Input = Input::Command(Command::GetConfig);
+    const DONE: Input = Input::BurstDone;
+
+    const MODES: [Mode; 5] = [IDLE, CA, CD, BA, BD];
+    const INPUTS: [Input; 13] = [STOP, CONT, CONT_D, BURST, BURST_D, CFG, BATCH, INFO, BAUD, PING, STATUS, CONFIG, DONE];
+
+    /// fails to compile once a command is added that `INPUTS` misses
+    #[allow(dead_code)]
+    fn covered(command: Command) {
+        match command {
+            Command::Stop | Command::Continues(_) | Command::Burst(_) | Command::Config(_)
+            | Command::ConfigBatch(_) | Command::Info | Command::SetBaud(_) | Command::Ping | Command::GetStatus
+            | Command::GetConfig => (),
+        }
+    }
+
+    /// from, input, to and what the device does
+    fn table() -> Vec<(Mode, Input, Mode, Action)> {
+        vec![
+            (IDLE, STOP, IDLE, Action::Nothing),
+            (IDLE, CONT, CA, Action::Nothing),
+            (IDLE, CONT_D, IDLE, Action::Reply(Reply::Err(ConfigErr::Unimplemented))),
+            (IDLE, BURST, BA, Action::Nothing),
+            (IDLE, BURST_D, IDLE, Action::Reply(Reply::Err(ConfigErr::Unimplemented))),
+            (IDLE, CFG, IDLE, Action::ApplyConfig(RATE)),
+            (IDLE, BATCH, IDLE, Action::ApplyBatch),
+            (IDLE, INFO, IDLE, Action::SendInfo),
+            (IDLE, BAUD, IDLE, Action::ChangeBaud(115_200)),
+            (IDLE, PING, IDLE, Action::Reply(Reply::Pong)),
+            (IDLE, STATUS, IDLE, Action::SendStatus),
+            (IDLE, CONFIG, IDLE, Action::SendConfig),
+            (IDLE, DONE, IDLE, Action::Nothing),
+
+            (CA, STOP, IDLE, Action::Nothing),
+            (CA, CONT, CA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CA, CONT_D, CA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CA, BURST, CA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CA, BURST_D, CA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CA, CFG, CA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CA, BATCH, CA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CA, INFO, CA, Action::SendInfo),
+            (CA, BAUD, CA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CA, PING, CA, Action::Reply(Reply::Pong)),
+            (CA, STATUS, CA, Action::SendStatus),
+            (CA, CONFIG, CA, Action::SendConfig),
+            (CA, DONE, CA, Action::Nothing),
+
+            // digital modes are never entered, a device in one still
+            // has to get out
+            (CD, STOP, IDLE, Action::Nothing),
+            (CD, CONT, CD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CD, CONT_D, CD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CD, BURST, CD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CD, BURST_D, CD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CD, CFG, CD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CD, BATCH, CD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CD, INFO, CD, Action::SendInfo),
+            (CD, BAUD, CD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (CD, PING, CD, Action::Reply(Reply::Pong)),
+            (CD, STATUS, CD, Action::SendStatus),
+            (CD, CONFIG, CD, Action::SendConfig),
+            (CD, DONE, CD, Action::Nothing),
+
+            (BA, STOP, IDLE, Action::Nothing),
+            (BA, CONT, BA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BA, CONT_D, BA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BA, BURST, BA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BA, BURST_D, BA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BA, CFG, BA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BA, BATCH, BA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BA, INFO, BA, Action::SendInfo),
+            (BA, BAUD, BA, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BA, PING, BA, Action::Reply(Reply::Pong)),
+            (BA, STATUS, BA, Action::SendStatus),
+            (BA, CONFIG, BA, Action::SendConfig),
+            (BA, DONE, IDLE, Action::Nothing),
+
+            (BD, STOP, IDLE, Action::Nothing),
+            (BD, CONT, BD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BD, CONT_D, BD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BD, BURST, BD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BD, BURST_D, BD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BD, CFG, BD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BD, BATCH, BD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BD, INFO, BD, Action::SendInfo),
+            (BD, BAUD, BD, Action::Reply(Reply::Err(ConfigErr::Busy))),
+            (BD, PING, BD, Action::Reply(Reply::Pong)),
+            (BD, STATUS, BD, Action::SendStatus),
+            (BD, CONFIG, BD, Action::SendConfig),
+            (BD, DONE, IDLE, Action::Nothing),
+        ]
+    }
+
+    #[test]
+    fn every_transition() {
+        let table = table();
+        assert_eq!(table.len(), MODES.len() * INPUTS.len());
+        for mode in MODES.iter() {
+            for input in INPUTS.iter() {
+                let rows = table.iter().filter(|(m, i, ..)| m == mode && i == input).count();
+                assert_eq!(rows, 1, "{:?} in {:?} has to be in the table once", input, mode);
+            }
+        }
+        for (from, input, to, action) in table {
+            assert_eq!(from.next(input), (to, action), "{:?} in {:?}", input, from);
+        }
+    }
+
+    /// the firmware in short: `handle_commands` feeds the commands
+    /// and carries out the action, `sample_loop` reports the end of a
+    /// burst. Everything the device sends is kept in `sent`
+    struct Device {
+        mode: Mode,
+        sent: Vec<Action>,
+    }
+
+    impl Device {
+        fn new() -> Self {
+            Self { mode: Mode::Idle, sent: Vec::new() }
+        }
+
+        fn command(&mut self, command: Command) {
+            let (next, action) = self.mode.next(Input::Command(command));
+            self.mode = next;
+            if action != Action::Nothing {
+                self.sent.push(action);
+            }
+        }
+
+        /// one pass of the sampler, a burst sends its data and is done
+        fn sample(&mut self) {
+            if let Mode::Burst(_) = self.mode {
+                self.sent.push(Action::Reply(Reply::Done(0)));
+                self.mode = self.mode.next(Input::BurstDone).0;
+            }
+        }
+
+        fn take(&mut self) -> Vec<Action> {
+            std::mem::take(&mut self.sent)
+        }
+    }
+
+    #[test]
+    fn digital_is_refused() {
+        let mut device = Device::new();
+        device.command(Command::Burst(Digital));
+        device.command(Command::Continues(Digital));
+        device.sample();
+        let unimplemented = || Action::Reply(Reply::Err(ConfigErr::Unimplemented));
+        assert_eq!(device.take(), [unimplemented(), unimplemented()]);
+        assert_eq!(device.mode, IDLE);
+    }
+
+    #[test]
+    fn burst_session() {
+        let mut device = Device::new();
+        device.command(Command::Config(RATE));
+        device.command(Command::Burst(Analog));
+        // the host is impatient
+        device.command(Command::Config(RATE));
+        device.command(Command::GetStatus);
+        device.sample();
+        device.command(Command::Config(RATE));
+        assert_eq!(device.take(), [
+            Action::ApplyConfig(RATE),
+            Action::Reply(Reply::Err(ConfigErr::Busy)),
+            Action::SendStatus,
+            Action::Reply(Reply::Done(0)),
+            Action::ApplyConfig(RATE),
+        ]);
+        assert_eq!(device.mode, IDLE);
+    }
+
+    #[test]
+    fn stream_session() {
+        let mut device = Device::new();
+        device.command(Command::Continues(Analog));
+        device.sample();
+        device.command(Command::Ping);
+        device.command(Command::SetBaud(115_200));
+        assert_eq!(device.mode, CA);
+        device.command(Command::Stop);
+        device.command(Command::SetBaud(115_200));
+        assert_eq!(device.take(), [
+            Action::Reply(Reply::Pong),
+            Action::Reply(Reply::Err(ConfigErr::Busy)),
+            Action::ChangeBaud(115_200),
+        ]);
+        assert_eq!(device.mode, IDLE);
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+const GAIN: f32 = 1.0/4.0;
+const REFV: f32 = 3.3/4.;
+pub const MAX_VOLT: f32 = REFV/GAIN;
+
+/// what the device samples on one channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub name: String,
+    /// the pins of a differential channel, `P0.30/AIN6 - P0.31/AIN7`
+    pub pair: Option<String>,
+}
+
+/// convert a raw adc reading to volts, readings are signed as
+/// noise can take them below zero. A differential reading spans
+/// both signs so a step is worth twice as much
+pub fn to_volt(raw: i16, differential: bool) -> f32 {
+    let volt = raw as f32 / (u16::MAX as f32) * MAX_VOLT*4.0;
+    if differential { 2.0*volt } else { volt }
+}
+
+/// convert interleaved adc readings to volts, `first` is the
+/// index of the channel the first sample is from
+pub fn to_volts(samples: &[i16], channels: &[Channel], first: usize) -> Vec<f32> {
+    let n = channels.len().max(1);
+    samples.iter()
+        .enumerate()
+        .map(|(i, &s)| {
+            let differential = channels.get((first + i) % n).is_some_and(|c| c.pair.is_some());
+            to_volt(s, differential)
+        })
+        .collect()
+}
+
+/// the samples of one channel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trace {
+    pub name: String,
+    /// the pins of a differential channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pair: Option<String>,
+    /// time of the first sample in seconds
+    pub t0: f32,
+    /// time between samples in seconds
+    pub dt: f32,
+    /// lost samples are NaN, stored as null
+    #[serde(with = "nan_as_null")]
+    pub values: Vec<f32>,
+}
+
+/// json has no NaN
+mod nan_as_null {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(values: &[f32], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(values.iter().map(|v| if v.is_nan() { None } else { Some(v) }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<f32>, D::Error> {
+        let values: Vec<Option<f32>> = Deserialize::deserialize(d)?;
+        Ok(values.into_iter().map(|v| v.unwrap_or(f32::NAN)).collect())
+    }
+}
+
+/// samples that never arrived, their values are NaN
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Gap {
+    /// in seconds
+    pub start: f32,
+    pub end: f32,
+    /// samples lost over all channels
+    pub lost: u32,
+}
+
+impl Trace {
+    pub fn time(&self) -> Vec<f32> {
+        (0..self.values.len())
+            .map(|i| self.t0 + (i as f32)*self.dt)
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Capture {
+    /// duration of the capture in seconds
+    pub duration: f32,
+    pub traces: Vec<Trace>,
+    #[serde(default)]
+    pub gaps: Vec<Gap>,
+}
+
+impl Capture {
+    /// split interleaved burst samples into one trace per
+    /// channel, channels are sampled round robin in the
+    /// order of `channels`. A round cut short at the end is left
+    /// out, like the device does, so all traces are equally long
+    pub fn from_burst(data: &[f32], duration: f32, channels: &[Channel]) -> Self {
+        let n = channels.len().max(1);
+        let dt = duration/(data.len().max(1) as f32);
+        let data = &data[..data.len() / n * n];
+        let traces = channels.iter()
+            .enumerate()
+            .map(|(i, channel)| Trace {
+                name: channel.name.clone(),
+                pair: channel.pair.clone(),
+                t0: (i as f32)*dt,
+                dt: dt*(n as f32),
+                values: data.iter().skip(i).step_by(n).copied().collect(),
+            })
+            .collect();
+
+        Self { duration, traces, gaps: Vec::new() }
+    }
+}
+
+/// store a capture as json
+pub fn save(path: &Path, capture: &Capture) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(file, capture).map_err(io::Error::other)
+}
+
+/// read a capture stored by `save`
+pub fn load(path: &Path) -> io::Result<Capture> {
+    let text = fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// a time and a value column per trace, shorter traces leave their
+/// columns empty at the end and lost samples leave the value empty.
+/// The value column of a differential trace names its pins
+pub fn write_csv(path: &Path, capture: &Capture) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let header: Vec<_> = capture.traces.iter()
+        .map(|t| match &t.pair {
+            Some(pair) => format!("{} time,{} ({})", t.name, t.name, pair),
+            None => format!("{} time,{}", t.name, t.name),
+        })
+        .collect();
+    writeln!(file, "{}", header.join(","))?;
+
+    let rows = capture.traces.iter().map(|t| t.values.len()).max().unwrap_or(0);
+    for i in 0..rows {
+        let row: Vec<_> = capture.traces.iter()
+            .map(|t| match t.values.get(i) {
+                Some(v) if v.is_nan() => format!("{},", t.t0 + i as f32 * t.dt),
+                Some(v) => format!("{},{}", t.t0 + i as f32 * t.dt, v),
+                None => ",".to_owned(),
+            })
+            .collect();
+        writeln!(file, "{}", row.join(","))?;
+    }
+    file.flush()
+}
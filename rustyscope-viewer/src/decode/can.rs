@@ -0,0 +1,374 @@
+use super::{Digital, Frame, Inputs, Options};
+
+/// where in the bit the level is read as a fraction of the bit time
+const SAMPLE_POINT: f32 = 0.75;
+/// recessive bits before a falling edge counts as a start of frame,
+/// longer than any stuffed run inside a frame
+const IDLE_BITS: f32 = 7.0;
+const CRC_POLY: u16 = 0x4599;
+
+/// `can:rx=<ch>,bitrate=500000,tolerance=0.1`, the logic level on the
+/// receive pin of a transceiver, dominant low, every field of a frame
+/// is shown separately
+#[derive(Debug, Clone)]
+pub struct Can {
+    rx: String,
+    bitrate: u32,
+    /// allowed distance of an edge from the bit boundary as a
+    /// fraction of the bit time
+    tolerance: f32,
+}
+
+impl Can {
+    pub fn from_options(options: &mut Options) -> Result<Self, String> {
+        let can = Self {
+            rx: options.channel("rx")?,
+            bitrate: options.get("bitrate", 500_000)?,
+            tolerance: options.get("tolerance", 0.1)?,
+        };
+        if can.bitrate == 0 {
+            return Err("bitrate can not be zero".to_owned());
+        }
+        Ok(can)
+    }
+
+    fn name(&self) -> String {
+        format!("can {}", self.rx)
+    }
+
+    pub fn decode(&self, inputs: &Inputs) -> Result<Vec<Frame>, String> {
+        let rx = inputs.digital(&self.rx)?;
+        let period = 1.0 / self.bitrate as f32;
+        rx.resolves(period, "a bit")?;
+
+        let mut frames = Vec::new();
+        let mut idle_since = rx.t0;
+        let mut i = 0;
+        while let Some(edge) = rx.edges.get(i) {
+            if edge.rising || edge.time - idle_since < IDLE_BITS * period {
+                if edge.rising {
+                    idle_since = edge.time;
+                }
+                i += 1;
+                continue;
+            }
+
+            let mut reader = Reader::new(&rx, edge.time, period, self.tolerance);
+            if let Err(e) = self.fields(&mut reader, &mut frames) {
+                frames.push(reader.frame(self, reader.field.to_owned(), Some(e)));
+            }
+            // carry on after the frame with the line high since its
+            // last rising edge
+            i = rx.edges.partition_point(|e| e.time < reader.time);
+            idle_since = rx.edges[..i].iter()
+                .rev()
+                .find(|e| e.rising)
+                .map(|e| e.time)
+                .unwrap_or(rx.t0);
+        }
+        Ok(frames)
+    }
+
+    /// read one frame starting at its start of frame bit, pushing a
+    /// frame per field until the end or the first error
+    fn fields(&self, r: &mut Reader, frames: &mut Vec<Frame>) -> Result<(), String> {
+        r.start_field("SOF");
+        r.stuffing = true;
+        r.crc_on = true;
+        if r.bit()? {
+            return Err("form error".to_owned());
+        }
+
+        r.field = "ID";
+        let mut id = r.bits(11)?;
+        let srr_rtr = r.bit()?;
+        let ide = r.bit()?;
+        let (mut text, rtr) = if ide {
+            id = id << 18 | r.bits(18)?;
+            let rtr = r.bit()?;
+            // r1 and r0
+            r.bits(2)?;
+            (format!("ID 0x{:08X} EXT", id), rtr)
+        } else {
+            // r0
+            r.bit()?;
+            (format!("ID 0x{:03X}", id), srr_rtr)
+        };
+        if rtr {
+            text.push_str(" RTR");
+        }
+        frames.push(r.frame(self, text, None));
+
+        r.start_field("DLC");
+        let dlc = r.bits(4)?;
+        frames.push(r.frame(self, format!("DLC {}", dlc), None));
+
+        if !rtr {
+            for _ in 0..dlc.min(8) {
+                r.start_field("data");
+                let byte = r.bits(8)?;
+                frames.push(r.frame(self, format!("0x{:02X}", byte), None));
+            }
+        }
+
+        r.start_field("CRC");
+        let expected = r.crc;
+        r.crc_on = false;
+        let crc = r.bits(15)?;
+        r.stuffing = false;
+        let error = (crc != expected as u32)
+            .then(|| format!("crc error, expected 0x{:04X}", expected));
+        frames.push(r.frame(self, format!("CRC 0x{:04X}", crc), error));
+
+        r.start_field("CRC delimiter");
+        if !r.bit()? {
+            return Err("form error".to_owned());
+        }
+
+        r.start_field("ACK");
+        let acked = !r.bit()?;
+        if !r.bit()? {
+            return Err("form error".to_owned());
+        }
+        let (text, error) = if acked { ("ACK", None) } else { ("NAK", Some("no ack".to_owned())) };
+        frames.push(r.frame(self, text.to_owned(), error));
+
+        r.start_field("EOF");
+        for _ in 0..7 {
+            if !r.bit()? {
+                return Err("form error".to_owned());
+            }
+        }
+        frames.push(r.frame(self, "EOF".to_owned(), None));
+        Ok(())
+    }
+}
+
+/// walks the bits of one frame, resynchronising on every edge close
+/// to a bit boundary and removing stuff bits
+struct Reader<'a> {
+    rx: &'a Digital,
+    period: f32,
+    tolerance: f32,
+    /// start of the next bit
+    time: f32,
+    field: &'static str,
+    field_start: f32,
+    violation: bool,
+    stuffing: bool,
+    last: bool,
+    run: u8,
+    crc_on: bool,
+    crc: u16,
+}
+
+impl<'a> Reader<'a> {
+    fn new(rx: &'a Digital, start: f32, period: f32, tolerance: f32) -> Self {
+        Self {
+            rx,
+            period,
+            tolerance,
+            time: start,
+            field: "SOF",
+            field_start: start,
+            violation: false,
+            stuffing: false,
+            last: false,
+            run: 0,
+            crc_on: false,
+            crc: 0,
+        }
+    }
+
+    fn start_field(&mut self, field: &'static str) {
+        self.field = field;
+        self.field_start = self.time;
+        self.violation = false;
+    }
+
+    fn frame(&self, can: &Can, text: String, error: Option<String>) -> Frame {
+        Frame {
+            decoder: can.name(),
+            start: self.field_start,
+            end: self.time,
+            text,
+            error,
+            timing_violation: self.violation,
+        }
+    }
+
+    /// a bit as it is on the wire
+    fn raw(&mut self) -> Result<bool, String> {
+        let expected = self.time;
+        let half = self.period / 2.0;
+        let idx = self.rx.edges.partition_point(|e| e.time < expected - half);
+        let start = match self.rx.edges.get(idx) {
+            Some(e) if e.time < expected + half => {
+                if (e.time - expected).abs() > self.tolerance * self.period {
+                    self.violation = true;
+                }
+                e.time
+            }
+            _ => expected,
+        };
+        if start + self.period > self.rx.end() {
+            return Err("capture ended".to_owned());
+        }
+        self.time = start + self.period;
+        Ok(self.rx.at(start + SAMPLE_POINT * self.period))
+    }
+
+    /// a bit with the stuff bit after five equal ones removed
+    fn bit(&mut self) -> Result<bool, String> {
+        let bit = self.raw()?;
+        if self.crc_on {
+            let next = bit ^ (self.crc >> 14 & 1 == 1);
+            self.crc = self.crc << 1 & 0x7fff;
+            if next {
+                self.crc ^= CRC_POLY;
+            }
+        }
+        if self.stuffing {
+            if self.run > 0 && bit == self.last {
+                self.run += 1;
+            } else {
+                self.last = bit;
+                self.run = 1;
+            }
+            if self.run == 5 {
+                if self.raw()? == bit {
+                    return Err("stuff error".to_owned());
+                }
+                self.last = !bit;
+                self.run = 1;
+            }
+        }
+        Ok(bit)
+    }
+
+    /// most significant bit first
+    fn bits(&mut self, n: u8) -> Result<u32, String> {
+        (0..n).try_fold(0, |v, _| Ok(v << 1 | self.bit()? as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{capture, decode, hold, texts};
+    use super::super::Frame;
+
+    const BIT: f32 = 1e-4;
+    const DT: f32 = BIT / 10.0;
+    const SPEC: &str = "can:rx=rx,bitrate=10000";
+
+    /// remainder of the bits followed by 15 zeros divided by
+    /// x^15 + x^14 + x^10 + x^8 + x^7 + x^4 + x^3 + 1
+    fn crc(bits: &[bool]) -> u16 {
+        let mut rest: Vec<bool> = bits.iter().copied().chain([false; 15]).collect();
+        for i in 0..bits.len() {
+            if rest[i] {
+                for j in 0..16 {
+                    rest[i + j] ^= 0xC599 >> (15 - j) & 1 == 1;
+                }
+            }
+        }
+        rest[bits.len()..].iter().fold(0, |crc, &b| crc << 1 | b as u16)
+    }
+
+    fn push(bits: &mut Vec<bool>, value: u32, n: u8) {
+        bits.extend((0..n).rev().map(|i| value >> i & 1 == 1));
+    }
+
+    /// a complement after every five equal bits, which counts towards
+    /// the next run
+    fn stuff(bits: &[bool]) -> Vec<bool> {
+        let mut wire = Vec::new();
+        let mut run = 0;
+        for &bit in bits {
+            if wire.last() == Some(&bit) {
+                run += 1;
+            } else {
+                run = 1;
+            }
+            wire.push(bit);
+            if run == 5 {
+                wire.push(!bit);
+                run = 1;
+            }
+        }
+        wire
+    }
+
+    /// the bits of a standard data frame from the start of frame to
+    /// the end of the crc, before stuffing
+    fn frame(id: u32, data: &[u8]) -> (Vec<bool>, u16) {
+        let mut bits = vec![false];
+        push(&mut bits, id, 11);
+        // rtr, ide and r0
+        bits.extend([false, false, false]);
+        push(&mut bits, data.len() as u32, 4);
+        for &byte in data {
+            push(&mut bits, byte as u32, 8);
+        }
+        let crc = crc(&bits);
+        push(&mut bits, crc as u32, 15);
+        (bits, crc)
+    }
+
+    /// idle, the stuffed bits, crc delimiter, ack, ack delimiter,
+    /// end of frame and idle again
+    fn line(wire: &[bool]) -> Vec<bool> {
+        let mut rx = Vec::new();
+        hold(&mut rx, DT, true, 11.0 * BIT);
+        for &bit in wire.iter().chain(&[true, false, true]) {
+            hold(&mut rx, DT, bit, BIT);
+        }
+        hold(&mut rx, DT, true, 18.0 * BIT);
+        rx
+    }
+
+    fn decoded(wire: &[bool]) -> Vec<Frame> {
+        let rx = line(wire);
+        decode(SPEC, &capture(DT, &[("rx", &rx)])).unwrap()
+    }
+
+    #[test]
+    fn stuffed() {
+        // the zero id and the 0xFF need stuff bits
+        let (bits, crc) = frame(0x000, &[0xFF, 0x12]);
+        let wire = stuff(&bits);
+        assert!(wire.len() > bits.len() + 3);
+
+        let frames = decoded(&wire);
+        let crc = format!("CRC 0x{:04X}", crc);
+        assert_eq!(texts(&frames), ["ID 0x000", "DLC 2", "0xFF", "0x12", crc.as_str(), "ACK", "EOF"]);
+        assert!(frames.iter().all(|f| f.error.is_none() && !f.timing_violation));
+    }
+
+    #[test]
+    fn crc_error() {
+        let (mut bits, crc) = frame(0x123, &[0x55]);
+        let last = bits.len() - 1;
+        bits[last] = !bits[last];
+
+        let frames = decoded(&stuff(&bits));
+        let crc_frame = frames.iter().find(|f| f.text.starts_with("CRC")).unwrap();
+        let expected = format!("crc error, expected 0x{:04X}", crc);
+        assert_eq!(crc_frame.error.as_deref(), Some(expected.as_str()));
+    }
+
+    #[test]
+    fn stuff_error() {
+        // six dominant bits in the id without a stuff bit
+        let (bits, _) = frame(0x000, &[]);
+        let frames = decoded(&bits);
+        assert_eq!(frames.last().unwrap().error.as_deref(), Some("stuff error"));
+    }
+
+    #[test]
+    fn too_slow() {
+        let rx = line(&stuff(&frame(0x123, &[]).0));
+        let error = decode("can:rx=rx", &capture(DT, &[("rx", &rx)])).unwrap_err();
+        assert!(error.starts_with("a bit of 2.000 us"), "{}", error);
+    }
+}
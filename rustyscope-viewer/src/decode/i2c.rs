@@ -0,0 +1,227 @@
+use super::{Digital, Frame, Inputs, Options};
+
+/// `i2c:scl=<ch>,sda=<ch>`
+#[derive(Debug, Clone)]
+pub struct I2c {
+    scl: String,
+    sda: String,
+}
+
+enum Line {
+    Scl,
+    Sda,
+}
+
+impl I2c {
+    pub fn from_options(options: &mut Options) -> Result<Self, String> {
+        Ok(Self {
+            scl: options.channel("scl")?,
+            sda: options.channel("sda")?,
+        })
+    }
+
+    fn frame(&self, start: f32, end: f32, text: String, error: Option<String>) -> Frame {
+        Frame {
+            decoder: format!("i2c {}/{}", self.scl, self.sda),
+            start,
+            end,
+            text,
+            error,
+            timing_violation: false,
+        }
+    }
+
+    pub fn decode(&self, inputs: &Inputs) -> Result<Vec<Frame>, String> {
+        let scl = inputs.digital(&self.scl)?;
+        let sda = inputs.digital(&self.sda)?;
+        if let Some(pulse) = scl.shortest_pulse() {
+            scl.resolves(pulse, "the shortest clock pulse")?;
+        }
+        let mut events: Vec<_> = scl.edges.iter().map(|e| (e, Line::Scl))
+            .chain(sda.edges.iter().map(|e| (e, Line::Sda)))
+            .collect();
+        events.sort_by(|a, b| a.0.time.total_cmp(&b.0.time));
+
+        let mut frames = Vec::new();
+        let mut transfer = None;
+        for (edge, line) in events {
+            // scl has to be high on both sides of an sda edge for a
+            // start or stop, otherwise it is just data changing
+            let scl_high = scl.at(edge.time - sda.dt / 2.0) && scl.at(edge.time + sda.dt / 2.0);
+            match line {
+                Line::Sda if scl_high => {
+                    let text = if edge.rising { "STOP" } else { "START" };
+                    if let Some(t) = transfer.take() {
+                        frames.extend(unfinished(self, t, edge.time));
+                    }
+                    frames.push(self.frame(edge.time, edge.time, text.to_owned(), None));
+                    if !edge.rising {
+                        transfer = Some(Transfer::new(edge.time));
+                    }
+                }
+                Line::Sda => (),
+                Line::Scl if !edge.rising => (),
+                Line::Scl => {
+                    if let Some(t) = transfer.as_mut() {
+                        if let Some(frame) = t.bit(self, &sda, edge.time) {
+                            frames.push(frame);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(frames)
+    }
+}
+
+/// state between a start and a stop condition
+struct Transfer {
+    bits: Vec<bool>,
+    byte_start: f32,
+    first_byte: bool,
+}
+
+impl Transfer {
+    fn new(start: f32) -> Self {
+        Self { bits: Vec::new(), byte_start: start, first_byte: true }
+    }
+
+    /// clock in a bit on the rising edge of scl, returns a frame
+    /// after 8 data bits and the ack bit
+    fn bit(&mut self, i2c: &I2c, sda: &Digital, time: f32) -> Option<Frame> {
+        if self.bits.is_empty() {
+            self.byte_start = time;
+        }
+        self.bits.push(sda.at(time));
+        if self.bits.len() < 9 {
+            return None;
+        }
+
+        let byte = self.bits[..8].iter().fold(0u32, |b, bit| b << 1 | *bit as u32);
+        let ack = if self.bits[8] { "NAK" } else { "ACK" };
+        let text = if self.first_byte {
+            let dir = if byte & 1 == 1 { "R" } else { "W" };
+            format!("addr 0x{:02X} {} {}", byte >> 1, dir, ack)
+        } else {
+            format!("0x{:02X} {}", byte, ack)
+        };
+        self.first_byte = false;
+        self.bits.clear();
+        Some(i2c.frame(self.byte_start, time, text, None))
+    }
+}
+
+/// a byte cut short by a start or stop condition, a single bit is
+/// the clock pulse that sets up the stop or repeated start
+fn unfinished(i2c: &I2c, t: Transfer, time: f32) -> Option<Frame> {
+    if t.bits.len() <= 1 {
+        return None;
+    }
+    let text = format!("{} bits", t.bits.len());
+    Some(i2c.frame(t.byte_start, time, text, Some("incomplete byte".to_owned())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{capture, decode, texts};
+
+    /// scl and sda, every step held for four samples
+    #[derive(Default)]
+    struct Bus {
+        scl: Vec<bool>,
+        sda: Vec<bool>,
+    }
+
+    impl Bus {
+        fn step(&mut self, scl: bool, sda: bool) {
+            for _ in 0..4 {
+                self.scl.push(scl);
+                self.sda.push(sda);
+            }
+        }
+
+        fn start(&mut self) {
+            self.step(true, true);
+            self.step(true, false);
+        }
+
+        /// sda changes while scl is low
+        fn byte(&mut self, value: u8, ack: bool) {
+            let bits = (0..8).rev().map(|i| value >> i & 1 == 1).chain(Some(!ack));
+            for bit in bits {
+                let last = *self.sda.last().unwrap();
+                self.step(false, last);
+                self.step(false, bit);
+                self.step(true, bit);
+            }
+        }
+
+        fn stop(&mut self) {
+            self.step(false, *self.sda.last().unwrap());
+            self.step(false, false);
+            self.step(true, false);
+            self.step(true, true);
+            self.step(true, true);
+        }
+
+        fn decode(&self) -> Vec<String> {
+            let capture = capture(1e-6, &[("scl", &self.scl), ("sda", &self.sda)]);
+            let frames = decode("i2c:scl=scl,sda=sda", &capture).unwrap();
+            texts(&frames).into_iter().map(str::to_owned).collect()
+        }
+    }
+
+    #[test]
+    fn ack_and_nak() {
+        let mut bus = Bus::default();
+        bus.start();
+        bus.byte(0x50 << 1, true);
+        bus.byte(0x12, false);
+        bus.stop();
+        assert_eq!(bus.decode(), ["START", "addr 0x50 W ACK", "0x12 NAK", "STOP"]);
+    }
+
+    #[test]
+    fn repeated_start() {
+        let mut bus = Bus::default();
+        bus.start();
+        bus.byte(0x50 << 1, true);
+        bus.byte(0x00, true);
+        // scl high with sda high, then sda falls
+        bus.step(false, true);
+        bus.step(true, true);
+        bus.step(true, false);
+        bus.byte(0x50 << 1 | 1, true);
+        bus.byte(0xAB, false);
+        bus.stop();
+        assert_eq!(bus.decode(),
+            ["START", "addr 0x50 W ACK", "0x00 ACK", "START", "addr 0x50 R ACK", "0xAB NAK", "STOP"]);
+    }
+
+    #[test]
+    fn cut_short() {
+        let mut bus = Bus::default();
+        bus.start();
+        bus.byte(0x50 << 1, true);
+        // three bits then a stop
+        for bit in [true, false, true] {
+            let last = *bus.sda.last().unwrap();
+            bus.step(false, last);
+            bus.step(false, bit);
+            bus.step(true, bit);
+        }
+        bus.stop();
+        let capture = capture(1e-6, &[("scl", &bus.scl), ("sda", &bus.sda)]);
+        let frames = decode("i2c:scl=scl,sda=sda", &capture).unwrap();
+        let incomplete = frames.iter().find(|f| f.error.is_some()).unwrap();
+        assert_eq!(incomplete.error.as_deref(), Some("incomplete byte"));
+    }
+
+    #[test]
+    fn too_slow() {
+        // a clock pulse every sample is likely missing some
+        let scl = [true, false, true, false, true, true];
+        let sda = [true; 6];
+        assert!(decode("i2c:scl=scl,sda=sda", &capture(1e-6, &[("scl", &scl), ("sda", &sda)])).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use std::str::FromStr;
+
+use super::{byte_text, within, Edge, Frame, Inputs, Options};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Convention {
+    /// IEEE 802.3, a rising edge in the middle of the bit is a one
+    Ieee,
+    /// G. E. Thomas, a falling edge in the middle of the bit is a one
+    Thomas,
+}
+
+impl FromStr for Convention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ieee" => Ok(Convention::Ieee),
+            "thomas" => Ok(Convention::Thomas),
+            _ => Err(format!("unknown convention: {}", s)),
+        }
+    }
+}
+
+/// `manchester:data=<ch>,rate=<bits/s>,convention=ieee,tolerance=0.25`,
+/// a frame starts at the first edge after the line was idle and that
+/// edge has to be in the middle of a bit, bytes are most significant
+/// bit first
+#[derive(Debug, Clone)]
+pub struct Manchester {
+    data: String,
+    rate: f32,
+    convention: Convention,
+    /// allowed deviation of the edges from the bit clock as a fraction
+    tolerance: f32,
+}
+
+impl Manchester {
+    pub fn from_options(options: &mut Options) -> Result<Self, String> {
+        let manchester = Self {
+            data: options.channel("data")?,
+            rate: options.get("rate", 0.0)?,
+            convention: options.get("convention", Convention::Ieee)?,
+            tolerance: options.get("tolerance", 0.25)?,
+        };
+        if manchester.rate <= 0.0 {
+            return Err("manchester needs a positive rate".to_owned());
+        }
+        Ok(manchester)
+    }
+
+    fn frame(&self, start: f32, end: f32, text: String, error: Option<String>, timing_violation: bool) -> Frame {
+        Frame {
+            decoder: format!("manchester {}", self.data),
+            start,
+            end,
+            text,
+            error,
+            timing_violation,
+        }
+    }
+
+    fn bit(&self, edge: &Edge) -> bool {
+        edge.rising == (self.convention == Convention::Ieee)
+    }
+
+    pub fn decode(&self, inputs: &Inputs) -> Result<Vec<Frame>, String> {
+        let data = inputs.digital(&self.data)?;
+        let period = 1.0 / self.rate;
+        data.resolves(period / 2.0, "half a bit")?;
+        let tol = self.tolerance;
+
+        let mut frames = Vec::new();
+        let mut byte = Byte::default();
+        let mut mid: Option<Edge> = None;
+        for edge in &data.edges {
+            let Some(last) = mid else {
+                byte.push(self.bit(edge), edge.time - period / 2.0, false);
+                mid = Some(*edge);
+                continue;
+            };
+
+            let interval = edge.time - last.time;
+            if interval < 0.75 * period {
+                // a transition on the bit boundary
+                byte.violation |= !within(interval, period / 2.0, tol);
+                continue;
+            }
+            if interval > 1.5 * period {
+                // idle line ends the frame, this edge starts a new one
+                if let Some(frame) = byte.unfinished(self, last.time + period / 2.0) {
+                    frames.push(frame);
+                }
+                byte = Byte::default();
+                byte.push(self.bit(edge), edge.time - period / 2.0, false);
+                mid = Some(*edge);
+                continue;
+            }
+
+            let violation = !within(interval, period, tol);
+            byte.push(self.bit(edge), edge.time - period / 2.0, violation);
+            mid = Some(*edge);
+            if byte.len == 8 {
+                let end = edge.time + period / 2.0;
+                frames.push(self.frame(byte.start, end, byte_text(byte.value), None, byte.violation));
+                byte = Byte::default();
+            }
+        }
+        if let Some(frame) = mid.and_then(|last| byte.unfinished(self, last.time + period / 2.0)) {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+}
+
+#[derive(Default)]
+struct Byte {
+    len: u8,
+    value: u32,
+    start: f32,
+    violation: bool,
+}
+
+impl Byte {
+    fn push(&mut self, bit: bool, start: f32, violation: bool) {
+        if self.len == 0 {
+            self.start = start;
+        }
+        self.len += 1;
+        self.value = self.value << 1 | bit as u32;
+        self.violation |= violation;
+    }
+
+    /// the bits left over at the end of a frame
+    fn unfinished(&self, manchester: &Manchester, end: f32) -> Option<Frame> {
+        if self.len == 0 {
+            return None;
+        }
+        let text = format!("0b{:0w$b}", self.value, w = self.len as usize);
+        let error = Some("incomplete byte".to_owned());
+        Some(manchester.frame(self.start, end, text, error, self.violation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{capture, decode, hold, texts};
+
+    const BIT: f32 = 1e-3;
+    const DT: f32 = BIT / 20.0;
+
+    /// IEEE 802.3, low then high for a one
+    fn send(data: &mut Vec<bool>, bits: &[bool]) {
+        for &bit in bits {
+            hold(data, DT, !bit, BIT / 2.0);
+            hold(data, DT, bit, BIT / 2.0);
+        }
+    }
+
+    fn bits(value: u8, n: u8) -> Vec<bool> {
+        (0..n).rev().map(|i| value >> i & 1 == 1).collect()
+    }
+
+    fn line() -> Vec<bool> {
+        let mut data = Vec::new();
+        hold(&mut data, DT, false, 5.0 * BIT);
+        send(&mut data, &bits(0xA5, 8));
+        // ends high, so the next frame starts with a zero
+        hold(&mut data, DT, true, 5.0 * BIT);
+        send(&mut data, &bits(0b0101, 4));
+        hold(&mut data, DT, true, 5.0 * BIT);
+        data
+    }
+
+    #[test]
+    fn ieee() {
+        let data = line();
+        let frames = decode("manchester:data=data,rate=1000", &capture(DT, &[("data", &data)])).unwrap();
+        assert_eq!(texts(&frames), ["0xA5", "0b0101"]);
+        assert_eq!(frames[0].error, None);
+        assert_eq!(frames[1].error.as_deref(), Some("incomplete byte"));
+        assert!((frames[0].start - 5.0 * BIT).abs() < DT);
+    }
+
+    #[test]
+    fn thomas() {
+        let data = line();
+        let spec = "manchester:data=data,rate=1000,convention=thomas";
+        let frames = decode(spec, &capture(DT, &[("data", &data)])).unwrap();
+        assert_eq!(texts(&frames), ["0x5A 'Z'", "0b1010"]);
+    }
+
+    #[test]
+    fn too_slow() {
+        let data = line();
+        assert!(decode("manchester:data=data,rate=5000", &capture(DT, &[("data", &data)])).is_err());
+    }
+}
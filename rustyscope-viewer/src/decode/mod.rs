@@ -0,0 +1,400 @@
+//! Protocol decoders working on digital channels or on analog
+//! channels turned digital by a threshold with hysteresis
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::capture::{Capture, Trace};
+use crate::measure::si;
+
+mod can;
+mod i2c;
+mod manchester;
+mod onewire;
+mod spi;
+mod uart;
+mod ws2812;
+
+/// samples needed in the shortest bit or pulse of a protocol, with
+/// fewer the edges land too far off to tell bits apart
+const MIN_SAMPLES: f32 = 4.0;
+
+/// one decoded unit, a byte, a word or a bus condition
+#[derive(Serialize, Debug, Clone)]
+pub struct Frame {
+    /// the decoder that produced this frame
+    pub decoder: String,
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+    pub error: Option<String>,
+    /// some timing was outside the tolerance of the protocol
+    pub timing_violation: bool,
+}
+
+/// switch to high above `high` and to low below `low`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Threshold {
+    pub channel: String,
+    pub low: f32,
+    pub high: f32,
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    /// `<channel>=<level>` or `<channel>=<low>:<high>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, levels) = s.split_once('=')
+            .ok_or_else(|| format!("expected <channel>=<low>:<high> got: {}", s))?;
+        let num = |a: &str| a.parse::<f32>().map_err(|e| format!("{}: {}", a, e));
+        let (low, high) = match levels.split_once(':') {
+            Some((low, high)) => (num(low)?, num(high)?),
+            None => (num(levels)?, num(levels)?),
+        };
+        Ok(Self { channel: channel.to_owned(), low, high })
+    }
+}
+
+/// a stretch where the signal stays at one level
+#[derive(Debug, Clone, Copy)]
+pub struct Pulse {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl Pulse {
+    pub fn width(&self) -> f32 {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Edge {
+    /// halfway between the samples around the transition
+    pub time: f32,
+    pub rising: bool,
+}
+
+/// a channel reduced to high and low
+#[derive(Debug, Clone)]
+pub struct Digital {
+    pub t0: f32,
+    pub dt: f32,
+    pub levels: Vec<bool>,
+    pub edges: Vec<Edge>,
+}
+
+impl Digital {
+    /// without a threshold the midpoint between min and max is used
+    /// with 10% of the peak to peak voltage as hysteresis
+    pub fn from_trace(trace: &Trace, threshold: Option<&Threshold>) -> Self {
+        let (low, high) = match threshold {
+            Some(t) => (t.low, t.high),
+            None => {
+                let min = trace.values.iter().copied().fold(f32::INFINITY, f32::min);
+                let max = trace.values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let mid = (min + max) / 2.0;
+                let band = (max - min) * 0.05;
+                (mid - band, mid + band)
+            }
+        };
+
+        let mut level = trace.values.first().map(|v| *v > (low + high) / 2.0).unwrap_or(false);
+        let levels: Vec<bool> = trace.values.iter()
+            .map(|v| {
+                if *v > high {
+                    level = true;
+                } else if *v < low {
+                    level = false;
+                }
+                level
+            })
+            .collect();
+        let edges = levels.windows(2)
+            .enumerate()
+            .filter(|(_, w)| w[0] != w[1])
+            .map(|(i, w)| Edge {
+                time: trace.t0 + (i as f32 + 0.5) * trace.dt,
+                rising: w[1],
+            })
+            .collect();
+
+        Self { t0: trace.t0, dt: trace.dt, levels, edges }
+    }
+
+    pub fn end(&self) -> f32 {
+        self.t0 + self.levels.len() as f32 * self.dt
+    }
+
+    /// level of the last sample taken at or before `time`
+    pub fn at(&self, time: f32) -> bool {
+        let idx = ((time - self.t0) / self.dt).floor().max(0.0) as usize;
+        self.levels.get(idx.min(self.levels.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// first edge at or after `time`
+    pub fn next_edge(&self, time: f32, rising: bool) -> Option<Edge> {
+        let idx = self.edges.partition_point(|e| e.time < time);
+        self.edges[idx..].iter().find(|e| e.rising == rising).copied()
+    }
+
+    /// every complete pulse at `high`, pulses cut off by the start or
+    /// end of the capture are skipped
+    pub fn pulses(&self, high: bool) -> Vec<Pulse> {
+        self.edges.windows(2)
+            .filter(|w| w[0].rising == high)
+            .map(|w| Pulse { start: w[0].time, end: w[1].time })
+            .collect()
+    }
+
+    /// error when the samples are too far apart for `shortest`, the
+    /// shortest time the protocol has to resolve
+    pub fn resolves(&self, shortest: f32, what: &str) -> Result<(), String> {
+        if self.dt * MIN_SAMPLES <= shortest {
+            return Ok(());
+        }
+        Err(format!("{} of {} needs a sample every {} or less, this capture has one every {}",
+            what, si(shortest, "s"), si(shortest / MIN_SAMPLES, "s"), si(self.dt, "s")))
+    }
+
+    /// the shortest high or low pulse, for clocks without a nominal rate,
+    /// in whole samples as edges fall between them
+    pub fn shortest_pulse(&self) -> Option<f32> {
+        self.edges.windows(2)
+            .map(|w| ((w[1].time - w[0].time) / self.dt).round() * self.dt)
+            .min_by(f32::total_cmp)
+    }
+}
+
+/// whether `actual` is no further than a fraction `tolerance`
+/// away from `nominal`
+pub fn within(actual: f32, nominal: f32, tolerance: f32) -> bool {
+    (actual - nominal).abs() <= nominal * tolerance
+}
+
+/// `key=value` pairs of a decoder specification
+pub struct Options(HashMap<String, String>);
+
+impl Options {
+    fn parse(s: &str) -> Result<Self, String> {
+        s.split(',')
+            .filter(|s| !s.is_empty())
+            .map(|kv| kv.split_once('=')
+                .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()))
+                .ok_or_else(|| format!("expected <key>=<value> got: {}", kv)))
+            .collect::<Result<_, _>>()
+            .map(Options)
+    }
+
+    pub fn channel(&mut self, key: &str) -> Result<String, String> {
+        self.0.remove(key).ok_or_else(|| format!("missing channel: {}", key))
+    }
+
+    pub fn optional_channel(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+
+    pub fn get<T: FromStr>(&mut self, key: &str, default: T) -> Result<T, String> {
+        match self.0.remove(key) {
+            None => Ok(default),
+            Some(v) => v.parse().map_err(|_| format!("invalid value for {}: {}", key, v)),
+        }
+    }
+
+    /// error on any option the decoder did not use
+    fn finish(self) -> Result<(), String> {
+        match self.0.keys().next() {
+            Some(key) => Err(format!("unknown option: {}", key)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Decoder {
+    Uart(uart::Uart),
+    I2c(i2c::I2c),
+    Spi(spi::Spi),
+    OneWire(onewire::OneWire),
+    Ws2812(ws2812::Ws2812),
+    Manchester(manchester::Manchester),
+    Can(can::Can),
+}
+
+impl FromStr for Decoder {
+    type Err = String;
+
+    /// `<protocol>:<key>=<value>,...`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, options) = s.split_once(':').unwrap_or((s, ""));
+        let mut options = Options::parse(options)?;
+        let decoder = match kind {
+            "uart" => Decoder::Uart(uart::Uart::from_options(&mut options)?),
+            "i2c" => Decoder::I2c(i2c::I2c::from_options(&mut options)?),
+            "spi" => Decoder::Spi(spi::Spi::from_options(&mut options)?),
+            "onewire" => Decoder::OneWire(onewire::OneWire::from_options(&mut options)?),
+            "ws2812" => Decoder::Ws2812(ws2812::Ws2812::from_options(&mut options)?),
+            "manchester" => Decoder::Manchester(manchester::Manchester::from_options(&mut options)?),
+            "can" => Decoder::Can(can::Can::from_options(&mut options)?),
+            _ => return Err(format!("unknown decoder: {}, options: \
+                uart, i2c, spi, onewire, ws2812, manchester, can", kind)),
+        };
+        options.finish()?;
+        Ok(decoder)
+    }
+}
+
+/// looks up channels by name and turns them digital
+pub struct Inputs<'a> {
+    pub capture: &'a Capture,
+    pub thresholds: &'a [Threshold],
+}
+
+impl Inputs<'_> {
+    pub fn digital(&self, name: &str) -> Result<Digital, String> {
+        let trace = self.capture.traces.iter()
+            .find(|t| t.name == name)
+            .ok_or_else(|| format!("unknown channel: {}", name))?;
+        let threshold = self.thresholds.iter().find(|t| t.channel == name);
+        Ok(Digital::from_trace(trace, threshold))
+    }
+}
+
+impl Decoder {
+    pub fn decode(&self, inputs: &Inputs) -> Result<Vec<Frame>, String> {
+        match self {
+            Decoder::Uart(d) => d.decode(inputs),
+            Decoder::I2c(d) => d.decode(inputs),
+            Decoder::Spi(d) => d.decode(inputs),
+            Decoder::OneWire(d) => d.decode(inputs),
+            Decoder::Ws2812(d) => d.decode(inputs),
+            Decoder::Manchester(d) => d.decode(inputs),
+            Decoder::Can(d) => d.decode(inputs),
+        }
+    }
+}
+
+/// `0x41 'A'` for printable bytes, `0x07` otherwise
+pub fn byte_text(byte: u32) -> String {
+    match char::from_u32(byte).filter(|c| c.is_ascii_graphic() || *c == ' ') {
+        Some(c) => format!("0x{:02X} '{}'", byte, c),
+        None => format!("0x{:02X}", byte),
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// write the frames as a table of events sorted by start time
+pub fn write_csv(path: &Path, frames: &[Frame]) -> io::Result<()> {
+    let mut frames = frames.to_vec();
+    frames.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+    let mut file = File::create(path)?;
+    writeln!(file, "start,end,decoder,text,error,timing_violation")?;
+    for f in frames {
+        writeln!(file, "{},{},{},{},{},{}",
+            f.start,
+            f.end,
+            csv_field(&f.decoder),
+            csv_field(&f.text),
+            csv_field(f.error.as_deref().unwrap_or_default()),
+            f.timing_violation)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// lines sampled every `dt`, 3.3 V when high
+    pub fn capture(dt: f32, lines: &[(&str, &[bool])]) -> Capture {
+        let traces = lines.iter()
+            .map(|(name, levels)| Trace {
+                name: name.to_string(),
+                pair: None,
+                t0: 0.0,
+                dt,
+                values: levels.iter().map(|&l| if l { 3.3 } else { 0.0 }).collect(),
+            })
+            .collect();
+        Capture { duration: 0.0, traces, gaps: Vec::new() }
+    }
+
+    /// `level` held for `time`, rounded to whole samples
+    pub fn hold(levels: &mut Vec<bool>, dt: f32, level: bool, time: f32) {
+        let n = (time / dt).round() as usize;
+        levels.extend(std::iter::repeat_n(level, n));
+    }
+
+    pub fn decode(spec: &str, capture: &Capture) -> Result<Vec<Frame>, String> {
+        let inputs = Inputs { capture, thresholds: &[] };
+        spec.parse::<Decoder>()?.decode(&inputs)
+    }
+
+    pub fn texts(frames: &[Frame]) -> Vec<&str> {
+        frames.iter().map(|f| f.text.as_str()).collect()
+    }
+
+    #[test]
+    fn threshold() {
+        assert_eq!("ch30=1.5".parse(), Ok(Threshold { channel: "ch30".to_owned(), low: 1.5, high: 1.5 }));
+        assert_eq!("ch30=0.8:2".parse(), Ok(Threshold { channel: "ch30".to_owned(), low: 0.8, high: 2.0 }));
+        assert!("ch30".parse::<Threshold>().is_err());
+        assert!("ch30=x".parse::<Threshold>().is_err());
+    }
+
+    #[test]
+    fn hysteresis() {
+        let trace = Trace {
+            name: "ch30".to_owned(),
+            pair: None,
+            t0: 0.0,
+            dt: 1.0,
+            values: vec![0.0, 1.4, 1.6, 2.1, 1.6, 1.4, 0.7, 0.0],
+        };
+        let threshold = "ch30=0.8:2".parse().unwrap();
+        let digital = Digital::from_trace(&trace, Some(&threshold));
+        assert_eq!(digital.levels, [false, false, false, true, true, true, false, false]);
+        let edges: Vec<_> = digital.edges.iter().map(|e| (e.time, e.rising)).collect();
+        assert_eq!(edges, [(2.5, true), (5.5, false)]);
+        assert_eq!(digital.pulses(true).len(), 1);
+        assert_eq!(digital.pulses(false).len(), 0);
+        assert!(digital.at(3.0) && !digital.at(6.0));
+    }
+
+    #[test]
+    fn options() {
+        assert!("uart:rx=ch30,baud=9600,colour=red".parse::<Decoder>().is_err());
+        assert!("uart:baud=9600".parse::<Decoder>().is_err());
+        assert!("uart:rx=ch30,bits=12".parse::<Decoder>().is_err());
+        assert!("morse:data=ch30".parse::<Decoder>().is_err());
+        assert!("spi:clk=ch30".parse::<Decoder>().is_err());
+        assert!("i2c:scl=ch30,sda=ch31".parse::<Decoder>().is_ok());
+    }
+
+    #[test]
+    fn byte_shown() {
+        assert_eq!(byte_text(0x41), "0x41 'A'");
+        assert_eq!(byte_text(0x07), "0x07");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("\"x\","), "\"\"\"x\"\",\"");
+    }
+}
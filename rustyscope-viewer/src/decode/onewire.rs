@@ -0,0 +1,154 @@
+use super::{byte_text, Frame, Inputs, Options, Pulse};
+
+// standard speed timing in seconds
+const RESET: f32 = 480e-6;
+const RESET_MAX: f32 = 960e-6;
+const PRESENCE_MIN: f32 = 60e-6;
+const PRESENCE_MAX: f32 = 240e-6;
+/// a presence pulse starts at most this long after the reset ends
+const PRESENCE_WAIT: f32 = 60e-6;
+const ONE_MAX: f32 = 15e-6;
+const ZERO_MIN: f32 = 60e-6;
+const ZERO_MAX: f32 = 120e-6;
+/// the slave samples the line this long after the slot starts
+const SAMPLE: f32 = 30e-6;
+
+/// `onewire:data=<ch>,tolerance=0.1`, standard speed with the
+/// master pulling the line low to start every slot
+#[derive(Debug, Clone)]
+pub struct OneWire {
+    data: String,
+    /// allowed deviation from the timing limits as a fraction
+    tolerance: f32,
+}
+
+impl OneWire {
+    pub fn from_options(options: &mut Options) -> Result<Self, String> {
+        Ok(Self {
+            data: options.channel("data")?,
+            tolerance: options.get("tolerance", 0.1)?,
+        })
+    }
+
+    fn frame(&self, start: f32, end: f32, text: String, timing_violation: bool) -> Frame {
+        Frame {
+            decoder: format!("1-wire {}", self.data),
+            start,
+            end,
+            text,
+            error: None,
+            timing_violation,
+        }
+    }
+
+    pub fn decode(&self, inputs: &Inputs) -> Result<Vec<Frame>, String> {
+        let data = inputs.digital(&self.data)?;
+        data.resolves(ONE_MAX, "a 1 bit")?;
+        let tol = self.tolerance;
+        let low = data.pulses(false);
+
+        let mut frames = Vec::new();
+        let mut bits: Vec<(Pulse, bool)> = Vec::new();
+        let mut violation = false;
+        let mut after_reset = None;
+        for pulse in low {
+            let width = pulse.width();
+
+            if width >= RESET * (1.0 - tol) {
+                let too_long = width > RESET_MAX * (1.0 + tol);
+                frames.push(self.frame(pulse.start, pulse.end, "RESET".to_owned(), too_long));
+                bits.clear();
+                violation = false;
+                after_reset = Some(pulse.end);
+                continue;
+            }
+
+            if let Some(reset_end) = after_reset.take() {
+                let waited = pulse.start - reset_end;
+                if waited <= PRESENCE_WAIT * (1.0 + tol) && width >= PRESENCE_MIN * (1.0 - tol) {
+                    let in_spec = width <= PRESENCE_MAX * (1.0 + tol);
+                    frames.push(self.frame(pulse.start, pulse.end, "PRESENCE".to_owned(), !in_spec));
+                    continue;
+                }
+            }
+
+            let (bit, in_spec) = if width <= ONE_MAX * (1.0 + tol) {
+                (true, true)
+            } else if width >= ZERO_MIN * (1.0 - tol) {
+                (false, width <= ZERO_MAX * (1.0 + tol))
+            } else {
+                // between a one and a zero, read it like a slave would
+                (width < SAMPLE, false)
+            };
+            violation |= !in_spec;
+            bits.push((pulse, bit));
+
+            if bits.len() == 8 {
+                let byte = bits.iter()
+                    .enumerate()
+                    .filter(|(_, (_, bit))| *bit)
+                    .fold(0u32, |byte, (i, _)| byte | 1 << i);
+                let start = bits[0].0.start;
+                // a slot lasts at least the minimum zero time
+                let end = pulse.start + ZERO_MIN;
+                frames.push(self.frame(start, end, byte_text(byte), violation));
+                bits.clear();
+                violation = false;
+            }
+        }
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{capture, decode, hold, texts};
+
+    const DT: f32 = 1e-6;
+
+    /// least significant bit first, the master pulls the line low
+    /// briefly for a one and for most of the slot for a zero
+    fn byte(data: &mut Vec<bool>, value: u8) {
+        for i in 0..8 {
+            let low = if value >> i & 1 == 1 { 6e-6 } else { 60e-6 };
+            hold(data, DT, false, low);
+            hold(data, DT, true, 70e-6 - low);
+        }
+    }
+
+    #[test]
+    fn reset_and_bytes() {
+        let mut data = Vec::new();
+        hold(&mut data, DT, true, 50e-6);
+        hold(&mut data, DT, false, 480e-6);
+        hold(&mut data, DT, true, 30e-6);
+        hold(&mut data, DT, false, 120e-6);
+        hold(&mut data, DT, true, 330e-6);
+        byte(&mut data, 0xCC);
+        byte(&mut data, 0x41);
+        hold(&mut data, DT, true, 50e-6);
+
+        let frames = decode("onewire:data=data", &capture(DT, &[("data", &data)])).unwrap();
+        assert_eq!(texts(&frames), ["RESET", "PRESENCE", "0xCC", "0x41 'A'"]);
+        assert!(frames.iter().all(|f| !f.timing_violation));
+    }
+
+    #[test]
+    fn out_of_spec() {
+        // 40us is neither a one nor a zero
+        let mut data = Vec::new();
+        hold(&mut data, DT, true, 50e-6);
+        for _ in 0..8 {
+            hold(&mut data, DT, false, 40e-6);
+            hold(&mut data, DT, true, 30e-6);
+        }
+        let frames = decode("onewire:data=data", &capture(DT, &[("data", &data)])).unwrap();
+        assert!(frames[0].timing_violation);
+    }
+
+    #[test]
+    fn too_slow() {
+        let data = [true, false, true];
+        assert!(decode("onewire:data=data", &capture(5e-6, &[("data", &data)])).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use super::{Digital, Frame, Inputs, Options};
+
+/// `spi:clk=<ch>,mosi=<ch>,miso=<ch>,cs=<ch>,cpol=0,cpha=0,bits=8`,
+/// mosi and miso are optional but at least one is needed, cs is
+/// active low and optional, data is most significant bit first
+#[derive(Debug, Clone)]
+pub struct Spi {
+    clk: String,
+    mosi: Option<String>,
+    miso: Option<String>,
+    cs: Option<String>,
+    cpol: u8,
+    cpha: u8,
+    bits: u8,
+}
+
+impl Spi {
+    pub fn from_options(options: &mut Options) -> Result<Self, String> {
+        let spi = Self {
+            clk: options.channel("clk")?,
+            mosi: options.optional_channel("mosi"),
+            miso: options.optional_channel("miso"),
+            cs: options.optional_channel("cs"),
+            cpol: options.get("cpol", 0)?,
+            cpha: options.get("cpha", 0)?,
+            bits: options.get("bits", 8)?,
+        };
+        if spi.mosi.is_none() && spi.miso.is_none() {
+            return Err("spi needs mosi, miso or both".to_owned());
+        }
+        if spi.cpol > 1 || spi.cpha > 1 {
+            return Err("cpol and cpha are either 0 or 1".to_owned());
+        }
+        if !(1..=32).contains(&spi.bits) {
+            return Err(format!("spi supports 1 to 32 bits per word not {}", spi.bits));
+        }
+        Ok(spi)
+    }
+
+    fn name(&self) -> String {
+        format!("spi {}", self.clk)
+    }
+
+    pub fn decode(&self, inputs: &Inputs) -> Result<Vec<Frame>, String> {
+        let clk = inputs.digital(&self.clk)?;
+        let optional = |name: &Option<String>| name.as_deref().map(|n| inputs.digital(n)).transpose();
+        let mosi = optional(&self.mosi)?;
+        let miso = optional(&self.miso)?;
+        let cs = optional(&self.cs)?;
+        if let Some(pulse) = clk.shortest_pulse() {
+            clk.resolves(pulse, "the shortest clock pulse")?;
+        }
+
+        // mode 0 and 3 sample on the rising edge, 1 and 2 on the falling
+        let sample_rising = self.cpol == self.cpha;
+        let mut frames = Vec::new();
+        let mut word = Word::default();
+        for edge in clk.edges.iter().filter(|e| e.rising == sample_rising) {
+            if let Some(cs) = &cs {
+                let deselected = cs.at(edge.time)
+                    || cs.next_edge(word.last, true).is_some_and(|e| e.time < edge.time);
+                if deselected && !word.is_empty() {
+                    frames.push(self.frame(&word, Some("incomplete word".to_owned())));
+                    word = Word::default();
+                }
+                if cs.at(edge.time) {
+                    continue;
+                }
+            }
+
+            word.push(edge.time, mosi.as_ref(), miso.as_ref());
+            if word.len == self.bits {
+                frames.push(self.frame(&word, None));
+                word = Word::default();
+            }
+        }
+        Ok(frames)
+    }
+
+    fn frame(&self, word: &Word, error: Option<String>) -> Frame {
+        let hex = |v: u32| format!("0x{:0w$X}", v, w = (self.bits as usize).div_ceil(4));
+        let text = [("MOSI", word.mosi, &self.mosi), ("MISO", word.miso, &self.miso)]
+            .iter()
+            .filter(|(_, _, ch)| ch.is_some())
+            .map(|(line, v, _)| format!("{} {}", line, hex(*v)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        Frame {
+            decoder: self.name(),
+            start: word.first,
+            end: word.last,
+            text,
+            error,
+            timing_violation: false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Word {
+    len: u8,
+    mosi: u32,
+    miso: u32,
+    first: f32,
+    last: f32,
+}
+
+impl Word {
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, time: f32, mosi: Option<&Digital>, miso: Option<&Digital>) {
+        if self.is_empty() {
+            self.first = time;
+        }
+        self.last = time;
+        self.len += 1;
+        self.mosi = self.mosi << 1 | mosi.is_some_and(|d| d.at(time)) as u32;
+        self.miso = self.miso << 1 | miso.is_some_and(|d| d.at(time)) as u32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{capture, decode, texts};
+
+    /// clk, mosi, miso and cs, every step held for four samples
+    #[derive(Default)]
+    struct Bus {
+        clk: Vec<bool>,
+        mosi: Vec<bool>,
+        miso: Vec<bool>,
+        cs: Vec<bool>,
+    }
+
+    impl Bus {
+        fn step(&mut self, clk: bool, mosi: bool, miso: bool, cs: bool) {
+            for _ in 0..4 {
+                self.clk.push(clk);
+                self.mosi.push(mosi);
+                self.miso.push(miso);
+                self.cs.push(cs);
+            }
+        }
+
+        /// `bits` words most significant bit first, with cs low
+        /// only around them
+        fn transfer(cpol: bool, cpha: bool, words: &[(u32, u32)], bits: u8) -> Self {
+            let mut bus = Bus::default();
+            let (mut mosi, mut miso) = (false, false);
+            bus.step(cpol, mosi, miso, true);
+            bus.step(cpol, mosi, miso, false);
+            for &(out, back) in words {
+                for i in (0..bits).rev() {
+                    let (o, b) = (out >> i & 1 == 1, back >> i & 1 == 1);
+                    if cpha {
+                        // shifted out on the leading edge, sampled on the trailing
+                        bus.step(!cpol, mosi, miso, false);
+                        bus.step(!cpol, o, b, false);
+                        bus.step(cpol, o, b, false);
+                    } else {
+                        bus.step(cpol, o, b, false);
+                        bus.step(!cpol, o, b, false);
+                        bus.step(cpol, o, b, false);
+                    }
+                    mosi = o;
+                    miso = b;
+                }
+            }
+            bus.step(cpol, mosi, miso, false);
+            bus.step(cpol, mosi, miso, true);
+            bus
+        }
+
+        fn decode(&self, spec: &str) -> Vec<String> {
+            let lines: [(&str, &[bool]); 4] =
+                [("clk", &self.clk), ("mosi", &self.mosi), ("miso", &self.miso), ("cs", &self.cs)];
+            let frames = decode(spec, &capture(1e-6, &lines)).unwrap();
+            texts(&frames).into_iter().map(str::to_owned).collect()
+        }
+    }
+
+    #[test]
+    fn modes() {
+        for (cpol, cpha) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+            let bus = Bus::transfer(cpol == 1, cpha == 1, &[(0xA5, 0x3C), (0x01, 0x80)], 8);
+            let spec = format!("spi:clk=clk,mosi=mosi,miso=miso,cs=cs,cpol={},cpha={}", cpol, cpha);
+            assert_eq!(bus.decode(&spec), ["MOSI 0xA5 MISO 0x3C", "MOSI 0x01 MISO 0x80"],
+                "cpol {} cpha {}", cpol, cpha);
+        }
+    }
+
+    #[test]
+    fn word_size() {
+        let bus = Bus::transfer(false, false, &[(0xABC, 0x123)], 12);
+        assert_eq!(bus.decode("spi:clk=clk,mosi=mosi,bits=12"), ["MOSI 0xABC"]);
+        assert_eq!(bus.decode("spi:clk=clk,miso=miso,bits=12"), ["MISO 0x123"]);
+    }
+
+    #[test]
+    fn cut_by_cs() {
+        // cs goes high after 4 of 8 bits
+        let bus = Bus::transfer(false, false, &[(0xA, 0x5)], 4);
+        let lines: [(&str, &[bool]); 4] =
+            [("clk", &bus.clk), ("mosi", &bus.mosi), ("miso", &bus.miso), ("cs", &bus.cs)];
+        let mut frames = decode("spi:clk=clk,mosi=mosi,cs=cs", &capture(1e-6, &lines)).unwrap();
+        assert_eq!(frames.len(), 0);
+        // a second transfer pushes out the first
+        let mut twice = Bus::transfer(false, false, &[(0xA, 0x5)], 4);
+        let again = Bus::transfer(false, false, &[(0xFF, 0x00)], 8);
+        twice.clk.extend(&again.clk);
+        twice.mosi.extend(&again.mosi);
+        twice.miso.extend(&again.miso);
+        twice.cs.extend(&again.cs);
+        let lines: [(&str, &[bool]); 4] =
+            [("clk", &twice.clk), ("mosi", &twice.mosi), ("miso", &twice.miso), ("cs", &twice.cs)];
+        frames = decode("spi:clk=clk,mosi=mosi,cs=cs", &capture(1e-6, &lines)).unwrap();
+        assert_eq!(texts(&frames), ["MOSI 0x0A", "MOSI 0xFF"]);
+        assert_eq!(frames[0].error.as_deref(), Some("incomplete word"));
+    }
+
+    #[test]
+    fn too_slow() {
+        let clk = [false, false, true, true, false, false];
+        let mosi = [true; 6];
+        let error = decode("spi:clk=clk,mosi=mosi", &capture(1e-6, &[("clk", &clk), ("mosi", &mosi)])).unwrap_err();
+        assert!(error.starts_with("the shortest clock pulse of 2.000 us"), "{}", error);
+    }
+}
@@ -0,0 +1,192 @@
+use std::str::FromStr;
+
+use super::{byte_text, Frame, Inputs, Options};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+impl FromStr for Parity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Parity::None),
+            "even" => Ok(Parity::Even),
+            "odd" => Ok(Parity::Odd),
+            _ => Err(format!("unknown parity: {}", s)),
+        }
+    }
+}
+
+/// `uart:rx=<ch>,baud=9600,bits=8,parity=none,stop=1`, idle high
+/// and least significant bit first
+#[derive(Debug, Clone)]
+pub struct Uart {
+    rx: String,
+    baud: u32,
+    bits: u8,
+    parity: Parity,
+    stop: u8,
+}
+
+impl Uart {
+    pub fn from_options(options: &mut Options) -> Result<Self, String> {
+        let uart = Self {
+            rx: options.channel("rx")?,
+            baud: options.get("baud", 9600)?,
+            bits: options.get("bits", 8)?,
+            parity: options.get("parity", Parity::None)?,
+            stop: options.get("stop", 1)?,
+        };
+        if !(5..=9).contains(&uart.bits) {
+            return Err(format!("uart supports 5 to 9 data bits not {}", uart.bits));
+        }
+        if uart.baud == 0 {
+            return Err("baud can not be zero".to_owned());
+        }
+        Ok(uart)
+    }
+
+    fn name(&self) -> String {
+        format!("uart {}", self.rx)
+    }
+
+    pub fn decode(&self, inputs: &Inputs) -> Result<Vec<Frame>, String> {
+        let rx = inputs.digital(&self.rx)?;
+        let bit = 1.0 / self.baud as f32;
+        rx.resolves(bit, "a bit")?;
+        let parity_bits = if self.parity == Parity::None { 0 } else { 1 };
+        let frame_bits = 1 + self.bits as u32 + parity_bits + self.stop as u32;
+
+        let mut frames = Vec::new();
+        let mut time = rx.t0;
+        while let Some(edge) = rx.next_edge(time, false) {
+            let start = edge.time;
+            let end = start + frame_bits as f32 * bit;
+            if end > rx.end() {
+                break;
+            }
+            // the middle of bit `n` counting the start bit as 0
+            let sample = |n: u32| rx.at(start + (n as f32 + 0.5) * bit);
+
+            if sample(0) {
+                // glitch, not a start bit
+                time = start + rx.dt;
+                continue;
+            }
+
+            let data = (0..self.bits as u32)
+                .filter(|b| sample(1 + b))
+                .fold(0u32, |byte, b| byte | 1 << b);
+            let mut error = None;
+            if self.parity != Parity::None {
+                let ones = data.count_ones() + sample(1 + self.bits as u32) as u32;
+                let expected_odd = self.parity == Parity::Odd;
+                if (ones % 2 == 1) != expected_odd {
+                    error = Some("parity error".to_owned());
+                }
+            }
+            let first_stop = 1 + self.bits as u32 + parity_bits;
+            if !(first_stop..frame_bits).all(sample) {
+                error = Some("framing error".to_owned());
+            }
+
+            frames.push(Frame {
+                decoder: self.name(),
+                start,
+                end,
+                text: byte_text(data),
+                error,
+                timing_violation: false,
+            });
+            // a new start bit can begin right after the stop bit
+            time = end - bit / 2.0;
+        }
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{capture, decode, hold, texts};
+
+    const BIT: f32 = 1.0 / 9600.0;
+    const DT: f32 = BIT / 16.0;
+
+    /// start bit, data least significant first, optional parity and stop bits
+    fn send(levels: &mut Vec<bool>, bits: &[bool]) {
+        hold(levels, DT, false, BIT);
+        for &b in bits {
+            hold(levels, DT, b, BIT);
+        }
+    }
+
+    fn byte(value: u8) -> Vec<bool> {
+        (0..8).map(|i| value >> i & 1 == 1).collect()
+    }
+
+    #[test]
+    fn framed() {
+        let mut rx = Vec::new();
+        hold(&mut rx, DT, true, 3.0 * BIT);
+        for value in [0x41, 0x00, 0xFF] {
+            let mut bits = byte(value);
+            bits.push(true);
+            send(&mut rx, &bits);
+        }
+        hold(&mut rx, DT, true, 3.0 * BIT);
+
+        let frames = decode("uart:rx=rx,baud=9600", &capture(DT, &[("rx", &rx)])).unwrap();
+        assert_eq!(texts(&frames), ["0x41 'A'", "0x00", "0xFF"]);
+        assert!(frames.iter().all(|f| f.error.is_none()));
+        assert!((frames[0].start - 3.0 * BIT).abs() < DT);
+        assert!((frames[0].end - frames[0].start - 10.0 * BIT).abs() < 1e-6);
+    }
+
+    #[test]
+    fn framing_error() {
+        let mut rx = Vec::new();
+        hold(&mut rx, DT, true, 3.0 * BIT);
+        // the stop bit is low
+        let mut bits = byte(0x55);
+        bits.push(false);
+        send(&mut rx, &bits);
+        hold(&mut rx, DT, true, 12.0 * BIT);
+
+        let frames = decode("uart:rx=rx,baud=9600", &capture(DT, &[("rx", &rx)])).unwrap();
+        assert_eq!(frames[0].text, "0x55 'U'");
+        assert_eq!(frames[0].error.as_deref(), Some("framing error"));
+    }
+
+    #[test]
+    fn parity() {
+        let mut rx = Vec::new();
+        hold(&mut rx, DT, true, 3.0 * BIT);
+        // 0x03 has two ones, even parity adds a zero
+        for parity in [false, true] {
+            let mut bits = byte(0x03);
+            bits.extend([parity, true, true]);
+            send(&mut rx, &bits);
+        }
+        hold(&mut rx, DT, true, 3.0 * BIT);
+        let capture = capture(DT, &[("rx", &rx)]);
+
+        let even = decode("uart:rx=rx,baud=9600,parity=even,stop=2", &capture).unwrap();
+        let errors: Vec<_> = even.iter().map(|f| f.error.as_deref()).collect();
+        assert_eq!(errors, [None, Some("parity error")]);
+        let odd = decode("uart:rx=rx,baud=9600,parity=odd,stop=2", &capture).unwrap();
+        let errors: Vec<_> = odd.iter().map(|f| f.error.as_deref()).collect();
+        assert_eq!(errors, [Some("parity error"), None]);
+    }
+
+    #[test]
+    fn too_slow() {
+        let rx = [true, false, true];
+        let error = decode("uart:rx=rx,baud=115200", &capture(DT, &[("rx", &rx)])).unwrap_err();
+        assert!(error.starts_with("a bit of 8.681 us"), "{}", error);
+    }
+}
@@ -0,0 +1,133 @@
+use super::{within, Frame, Inputs, Options};
+
+// timing in seconds
+const T0H: f32 = 0.4e-6;
+const T1H: f32 = 0.8e-6;
+const PERIOD: f32 = 1.25e-6;
+/// a low time this long latches the colours and restarts the chain
+const RESET: f32 = 50e-6;
+
+/// `ws2812:din=<ch>,tolerance=0.375`, 24 bits per led in green, red,
+/// blue order and most significant bit first
+#[derive(Debug, Clone)]
+pub struct Ws2812 {
+    din: String,
+    /// allowed deviation from the nominal pulse widths as a fraction,
+    /// the default is the 150ns of the datasheet on a 0 pulse
+    tolerance: f32,
+}
+
+impl Ws2812 {
+    pub fn from_options(options: &mut Options) -> Result<Self, String> {
+        Ok(Self {
+            din: options.channel("din")?,
+            tolerance: options.get("tolerance", 0.375)?,
+        })
+    }
+
+    fn frame(&self, start: f32, end: f32, text: String, timing_violation: bool) -> Frame {
+        Frame {
+            decoder: format!("ws2812 {}", self.din),
+            start,
+            end,
+            text,
+            error: None,
+            timing_violation,
+        }
+    }
+
+    pub fn decode(&self, inputs: &Inputs) -> Result<Vec<Frame>, String> {
+        let din = inputs.digital(&self.din)?;
+        din.resolves(T0H, "the high time of a 0 bit")?;
+        let tol = self.tolerance;
+
+        let mut frames = Vec::new();
+        let mut led = 0;
+        let mut bits = 0u32;
+        let mut count = 0;
+        let mut start = 0.0;
+        let mut violation = false;
+        let mut last_rise: Option<f32> = None;
+        for pulse in din.pulses(true) {
+            if let Some(rise) = last_rise {
+                if pulse.start - rise >= RESET {
+                    if count > 0 {
+                        let text = format!("{} bits", count);
+                        frames.push(Frame {
+                            error: Some("incomplete led".to_owned()),
+                            ..self.frame(start, rise, text, violation)
+                        });
+                    }
+                    frames.push(self.frame(rise, pulse.start, "RESET".to_owned(), false));
+                    led = 0;
+                    count = 0;
+                    violation = false;
+                } else if !within(pulse.start - rise, PERIOD, tol) {
+                    violation = true;
+                }
+            }
+            last_rise = Some(pulse.start);
+
+            let width = pulse.width();
+            let bit = width > (T0H + T1H) / 2.0;
+            let nominal = if bit { T1H } else { T0H };
+            violation |= !within(width, nominal, tol);
+
+            if count == 0 {
+                start = pulse.start;
+                bits = 0;
+            }
+            bits = bits << 1 | bit as u32;
+            count += 1;
+            if count == 24 {
+                let (g, r, b) = (bits >> 16 & 0xff, bits >> 8 & 0xff, bits & 0xff);
+                let text = format!("LED{} #{:02X}{:02X}{:02X}", led, r, g, b);
+                frames.push(self.frame(start, start + 24.0 * PERIOD, text, violation));
+                led += 1;
+                count = 0;
+                violation = false;
+            }
+        }
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{capture, decode, hold, texts};
+    use super::{PERIOD, T0H, T1H};
+
+    const DT: f32 = 0.05e-6;
+
+    /// green, red, blue and most significant bit first
+    fn led(din: &mut Vec<bool>, rgb: u32) {
+        let grb = (rgb >> 8 & 0xff) << 16 | (rgb >> 16 & 0xff) << 8 | rgb & 0xff;
+        for i in (0..24).rev() {
+            let high = if grb >> i & 1 == 1 { T1H } else { T0H };
+            hold(din, DT, true, high);
+            hold(din, DT, false, PERIOD - high);
+        }
+    }
+
+    #[test]
+    fn colours() {
+        let mut din = Vec::new();
+        hold(&mut din, DT, false, 1e-6);
+        led(&mut din, 0xFF8000);
+        led(&mut din, 0x0000FF);
+        hold(&mut din, DT, false, 60e-6);
+        led(&mut din, 0x123456);
+        hold(&mut din, DT, false, 1e-6);
+
+        let frames = decode("ws2812:din=din", &capture(DT, &[("din", &din)])).unwrap();
+        assert_eq!(texts(&frames), ["LED0 #FF8000", "LED1 #0000FF", "RESET", "LED0 #123456"]);
+        assert!(frames.iter().all(|f| !f.timing_violation && f.error.is_none()));
+    }
+
+    #[test]
+    fn too_slow() {
+        let din = [false, true, false];
+        let error = decode("ws2812:din=din", &capture(5e-6, &[("din", &din)])).unwrap_err();
+        assert!(error.contains("0 bit"), "{}", error);
+    }
+}
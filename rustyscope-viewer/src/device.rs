@@ -0,0 +1,537 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ferrous_serialport as serialport;
+use ferrous_serialport::{ClearBuffer, SerialPort};
+use rustyscope_traits::{Abilities, Batch, Command, ConfigAction, ConfigErr, DeviceConfig, Encoding, Mode, PinId, Reply, Status, StreamHeader, Timing, Version};
+use rustyscope_traits::{BAUD_CONFIRM, DEFAULT_BAUD};
+
+use crate::capture::{self, Channel, Gap};
+use crate::error::Error;
+use crate::profile::{AnalogChannel, Profile};
+
+/// what the nrf52 scope can do, the same constants its firmware
+/// is built with. `abilities` checks the device runs that version
+pub use rustyscope_traits::nrf52::{ABILITIES, RESOLUTION, VERSION as FIRMWARE};
+
+pub fn open(port: &Path) -> serialport::Result<Box<dyn SerialPort>> {
+    open_with_timeout(port, Duration::from_secs(20))
+}
+
+pub fn open_with_timeout(port: &Path, timeout: Duration) -> serialport::Result<Box<dyn SerialPort>> {
+    serialport::new(port.to_string_lossy(), DEFAULT_BAUD)
+        .parity(serialport::Parity::None)
+        .flow_control(serialport::FlowControl::Hardware)
+        .timeout(timeout)
+        .open()
+}
+
+pub fn send(serial: &mut dyn SerialPort, cmd: Command) -> io::Result<()> {
+    serial.write_all(&cmd.serialize())
+}
+
+/// a reply together with the payload that follows it
+#[derive(Debug)]
+pub enum Event {
+    Reply(Reply),
+    Data(Vec<u8>),
+    /// the encoded samples of a `Reply::Stream`
+    Stream(StreamHeader, Vec<u8>),
+    Timing(Timing),
+    Status(Status),
+    /// boxed, it is many times the size of the others
+    Config(Box<DeviceConfig>),
+}
+
+/// samples unpacked from data payloads, counting the bytes that
+/// went over the wire
+#[derive(Default)]
+pub struct Received {
+    pub samples: Vec<i16>,
+    pub bytes: usize,
+    /// samples received before each gap and how many went missing
+    pub gaps: Vec<(usize, u32)>,
+    /// samples the device reported dropping
+    pub overrun: u32,
+    /// stream packets that never arrived
+    pub missing_packets: u32,
+    /// sequence number and sample index of the next packet
+    next: Option<(u16, u32)>,
+}
+
+impl Received {
+    pub fn add(&mut self, encoding: Encoding, payload: &[u8]) -> Result<(), Error> {
+        let start = self.samples.len();
+        self.samples.resize(start + encoding.max_samples(payload.len()), 0);
+        let n = encoding.decode(payload, &mut self.samples[start..])
+            .map_err(|e| Error::Device(format!("invalid {} data: {:?}", encoding.name(), e)))?;
+        self.samples.truncate(start + n);
+        self.bytes += payload.len();
+        Ok(())
+    }
+
+    /// a stream packet, samples missing before it are recorded as
+    /// a gap
+    pub fn add_stream(&mut self, encoding: Encoding, header: StreamHeader, payload: &[u8]) -> Result<(), Error> {
+        let (seq, index) = self.next.unwrap_or((header.seq, header.index));
+        // the index wraps after u32::MAX samples, hours into a stream
+        let ahead = header.index.wrapping_sub(index) as i32;
+        if ahead < 0 {
+            return Err(Error::Device(format!(
+                "stream went back from sample {} to {}", index, header.index)));
+        }
+        if ahead > 0 {
+            self.gaps.push((self.samples.len(), ahead as u32));
+        }
+        self.missing_packets += header.seq.wrapping_sub(seq) as u32;
+
+        let before = self.samples.len();
+        self.add(encoding, payload)?;
+        self.bytes += StreamHeader::SIZE;
+        let n = (self.samples.len() - before) as u32;
+        self.next = Some((header.seq.wrapping_add(1), header.index.wrapping_add(n)));
+        Ok(())
+    }
+
+    /// samples lost over all gaps
+    pub fn lost(&self) -> u32 {
+        self.gaps.iter().map(|(_, lost)| lost).sum()
+    }
+
+    /// where the gaps are with `dt` between rounds of `channels`
+    /// samples, a gap covers every round missing a sample
+    pub fn gaps_in_time(&self, channels: usize, dt: f32) -> Vec<Gap> {
+        let channels = channels.max(1);
+        let mut before = 0;
+        self.gaps.iter()
+            .map(|&(at, lost)| {
+                // position of the first lost sample among all received and lost
+                let first = at + before;
+                before += lost as usize;
+                let start = (first / channels) as f32 * dt;
+                let end = (first + lost as usize).div_ceil(channels) as f32 * dt;
+                Gap { start, end, lost }
+            })
+            .collect()
+    }
+
+    /// the samples in volts with NaN for every lost one
+    pub fn volts(&self, channels: &[Channel]) -> Vec<f32> {
+        let mut volts = Vec::with_capacity(self.samples.len() + self.lost() as usize);
+        let mut done = 0;
+        for &(at, lost) in &self.gaps {
+            volts.extend(capture::to_volts(&self.samples[done..at], channels, volts.len()));
+            volts.extend(std::iter::repeat_n(f32::NAN, lost as usize));
+            done = at;
+        }
+        volts.extend(capture::to_volts(&self.samples[done..], channels, volts.len()));
+        volts
+    }
+
+    /// times smaller than sending every sample as an `i16`
+    pub fn ratio(&self) -> f32 {
+        (self.samples.len() * 2) as f32 / self.bytes.max(1) as f32
+    }
+}
+
+impl fmt::Display for Received {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} samples in {} bytes, {:.2}x smaller than raw",
+            self.samples.len(), self.bytes, self.ratio())
+    }
+}
+
+/// blocks until the device sends something, read timeouts are retried
+pub fn next_event(serial: &mut dyn SerialPort) -> io::Result<Event> {
+    read_event(serial, true)
+}
+
+fn read_event(serial: &mut dyn SerialPort, retry: bool) -> io::Result<Event> {
+    let mut buf = [0u8; Reply::SIZE];
+    loop {
+        match serial.read_exact(&mut buf) {
+            Err(e) if retry && e.kind() == ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+            Ok(()) => break,
+        }
+    }
+    event(serial, &buf)
+}
+
+/// like `next_event` but gives up once `deadline` passed without
+/// a reply starting to arrive
+pub fn event_before(serial: &mut dyn SerialPort, deadline: Instant) -> io::Result<Option<Event>> {
+    let timeout = serial.timeout();
+    let mut buf = [0u8; Reply::SIZE];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(None);
+        }
+        serial.set_timeout(left.min(timeout))?;
+        let read = serial.read_exact(&mut buf);
+        serial.set_timeout(timeout)?;
+        match read {
+            Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+            Ok(()) => return event(serial, &buf).map(Some),
+        }
+    }
+}
+
+/// the reply in `buf` with the payload that follows it
+fn event(serial: &mut dyn SerialPort, buf: &[u8; Reply::SIZE]) -> io::Result<Event> {
+    let reply = Reply::try_from(buf)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{:?}", e)))?;
+    match reply {
+        Reply::Data(len) => {
+            let mut buf = vec![0u8; len as usize];
+            serial.read_exact(&mut buf)?;
+            Ok(Event::Data(buf))
+        }
+        Reply::Stream(len) => {
+            let mut buf = vec![0u8; len as usize];
+            serial.read_exact(&mut buf)?;
+            let (header, samples) = StreamHeader::split(&buf)
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "stream packet without header"))?;
+            Ok(Event::Stream(header, samples.to_vec()))
+        }
+        Reply::Timing(len) => {
+            let mut buf = vec![0u8; len as usize];
+            serial.read_exact(&mut buf)?;
+            let timing = Timing::parse(&buf)
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "timing report too short"))?;
+            Ok(Event::Timing(timing))
+        }
+        Reply::Status(len) => {
+            let mut buf = vec![0u8; len as usize];
+            serial.read_exact(&mut buf)?;
+            let status = Status::parse(&buf)
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "invalid status"))?;
+            Ok(Event::Status(status))
+        }
+        Reply::Config(len) => {
+            let mut buf = vec![0u8; len as usize];
+            serial.read_exact(&mut buf)?;
+            let config = DeviceConfig::parse(&buf)
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "invalid config"))?;
+            Ok(Event::Config(Box::new(config)))
+        }
+        reply => Ok(Event::Reply(reply)),
+    }
+}
+
+/// the next reply, data still arriving from before is dropped
+pub fn reply(serial: &mut dyn SerialPort) -> Result<Reply, Error> {
+    loop {
+        match read_event(serial, false) {
+            Ok(Event::Reply(reply)) => return Ok(reply),
+            Ok(Event::Data(_) | Event::Stream(..) | Event::Timing(_)) => continue,
+            Ok(Event::Status(_) | Event::Config(_)) => continue,
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                return Err(Error::Device("no reply, is the firmware up to date?".to_owned()))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// ask `cmd` and wait for the event `wanted` picks out, other
+/// events still arriving are dropped
+fn query<T>(serial: &mut dyn SerialPort, cmd: Command, wanted: impl Fn(Event) -> Option<T>) -> Result<T, Error> {
+    send(serial, cmd)?;
+    loop {
+        match read_event(serial, false) {
+            Ok(Event::Reply(Reply::Err(e))) => return Err(Error::Device(describe(&e))),
+            Ok(event) => match wanted(event) {
+                Some(answer) => return Ok(answer),
+                None => continue,
+            },
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                return Err(Error::Device("no reply, is the firmware up to date?".to_owned()))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+pub fn status(serial: &mut dyn SerialPort) -> Result<Status, Error> {
+    query(serial, Command::GetStatus, |e| match e {
+        Event::Status(status) => Some(status),
+        _ => None,
+    })
+}
+
+/// the configuration the device is using right now
+pub fn config(serial: &mut dyn SerialPort) -> Result<DeviceConfig, Error> {
+    query(serial, Command::GetConfig, |e| match e {
+        Event::Config(config) => Some(*config),
+        _ => None,
+    })
+}
+
+fn mode(mode: &Mode) -> String {
+    match mode {
+        Mode::Idle => "idle".to_owned(),
+        Mode::Continues(kind) => format!("{:?} stream", kind).to_lowercase(),
+        Mode::Burst(kind) => format!("{:?} burst", kind).to_lowercase(),
+    }
+}
+
+/// a pin as the board describes it, ids from the device only
+/// carry the port and number
+pub fn resolve(pin: PinId) -> PinId {
+    pin.resolve(ABILITIES.adc_pins).resolve(ABILITIES.digital_pins)
+}
+
+/// `P0.30/AIN6 (A4)`
+pub fn show_pin(pin: PinId) -> String {
+    let pin = resolve(pin);
+    match pin.name {
+        "" => pin.to_string(),
+        name => format!("{} ({})", pin, name),
+    }
+}
+
+pub fn show_pins(pins: &[PinId]) -> String {
+    if pins.is_empty() {
+        return "none".to_owned();
+    }
+    pins.iter().map(|&p| show_pin(p)).collect::<Vec<_>>().join(", ")
+}
+
+/// read a pin as written on the command line, in a profile or
+/// in the shell, see `PinId::parse`
+pub fn pin(text: &str) -> Result<PinId, String> {
+    let known: Vec<_> = ABILITIES.adc_pins.iter().chain(ABILITIES.digital_pins).copied().collect();
+    PinId::parse(text, &known)
+        .ok_or_else(|| format!("unknown pin: {}, options: {}", text, show_pins(&known)))
+}
+
+pub fn show_status(status: &Status) -> String {
+    let error = status.last_error.as_ref().map_or("none".to_owned(), describe);
+    format!("mode: {}\nlast error: {}\nuptime: {:.1} s\nbuffered: {} of {} samples\n",
+        mode(&status.mode), error, status.uptime_ms as f64 / 1000.,
+        status.buffered, status.capacity)
+}
+
+pub fn show_config(config: &DeviceConfig) -> String {
+    let rate = config.rate.map_or("as fast as possible".to_owned(), |r| format!("{} Hz", r));
+    let channels: Vec<_> = config.analog().iter()
+        .zip(config.minus())
+        .map(|(&pin, &minus)| AnalogChannel::new(pin, minus).pins())
+        .collect();
+    let analog = if channels.is_empty() { "none".to_owned() } else { channels.join(", ") };
+    format!("analog pins: {}\nrate: {}\nresolution: {} bits\nencoding: {}\n",
+        analog, rate, config.resolution, config.encoding.name())
+}
+
+/// the config error spelled out
+pub fn describe(e: &ConfigErr) -> String {
+    match e {
+        ConfigErr::UnavailibleSampler(s) => format!("sampler {} is not available", s),
+        ConfigErr::PinTaken(p) => format!("pin {} is already in use", show_pin(*p)),
+        ConfigErr::InvalidPin(p) => format!("pin {} can not be sampled", show_pin(*p)),
+        ConfigErr::InvalidRate(r) => format!("a rate of {} Hz is not possible", r),
+        ConfigErr::InvalidBaud(b) => format!("a baud rate of {} is not possible", b),
+        ConfigErr::Unimplemented => "not implemented by the firmware".to_owned(),
+        ConfigErr::CommunicationProblem => "communication problem".to_owned(),
+        ConfigErr::RateTooHigh { max } => format!("the rate can be at most {} Hz with these pins", max),
+        ConfigErr::Busy => "the device is sampling, stop it first".to_owned(),
+        ConfigErr::PinNotEnabled(p) => format!("pin {} is not being sampled", show_pin(*p)),
+        ConfigErr::PinReserved(p) => format!("pin {} is used by the board", show_pin(*p)),
+        ConfigErr::BatchTooLong { max } => format!("at most {} changes can be made at once", max),
+        ConfigErr::EncodingTooNarrow { bits } => {
+            format!("the encoding carries at most {} bits, the device samples {}", bits, RESOLUTION)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Info {
+    pub version: Version,
+    pub id: u32,
+}
+
+pub fn show_version(v: Version) -> String {
+    format!("{}.{}.{}", v.major, v.minor, v.patch)
+}
+
+/// `ABILITIES` if the device runs the firmware they describe, a
+/// patch release keeps the pins and rates
+pub fn abilities(version: Version) -> Result<&'static Abilities, Error> {
+    if (version.major, version.minor) != (FIRMWARE.major, FIRMWARE.minor) {
+        return Err(Error::Device(format!(
+            "firmware {} may differ from the {} this viewer knows the pins and rates of, update the viewer",
+            show_version(version), show_version(FIRMWARE))));
+    }
+    Ok(&ABILITIES)
+}
+
+pub fn info(serial: &mut dyn SerialPort) -> Result<Info, Error> {
+    send(serial, Command::Info)?;
+    let version = match reply(serial)? {
+        Reply::Info(version) => version,
+        other => return Err(Error::Device(format!("expected info got: {:?}", other))),
+    };
+    match reply(serial)? {
+        Reply::Id(id) => Ok(Info { version, id }),
+        other => Err(Error::Device(format!("expected id got: {:?}", other))),
+    }
+}
+
+/// set up the pins and rate of the profile, stops at the first
+/// setting the device refuses. Returns the rate the device uses
+pub fn configure(serial: &mut dyn SerialPort, profile: &Profile) -> Result<u32, Error> {
+    let analog = profile.analog.iter().map(|c| match c.minus {
+        Some(minus) => ConfigAction::AnalogDiffPair(c.pin, minus),
+        None => ConfigAction::AnalogPins(c.pin),
+    });
+    let digital = profile.digital.iter().map(|&pin| ConfigAction::DigitalPins(pin));
+    let actions = std::iter::once(ConfigAction::ResetPins)
+        .chain(analog)
+        .chain(digital)
+        .chain(std::iter::once(ConfigAction::AnalogRate(profile.rate)))
+        // firmware without encodings only sends raw
+        .chain((profile.encoding != Encoding::Raw).then_some(ConfigAction::Encoding(profile.encoding)));
+
+    let actions: Vec<_> = actions.collect();
+    let frames = Batch::frames(&actions).map_err(|e| Error::Device(describe(&e)))?;
+    for frame in frames {
+        send(serial, frame)?;
+    }
+
+    // applied all or nothing, a refused batch leaves the device as it was
+    let mut rate = profile.rate;
+    for _ in &actions {
+        match reply(serial)? {
+            Reply::Ok => (),
+            Reply::Rate(actual) => rate = actual,
+            Reply::BatchFailed(i) => {
+                let action = match actions.get(i as usize) {
+                    Some(action) => format!("{:?}", action),
+                    None => format!("action {}", i),
+                };
+                let e = match reply(serial)? {
+                    Reply::Err(e) => describe(&e),
+                    other => format!("unexpected {:?}", other),
+                };
+                return Err(Error::Device(format!("{} refused: {}", action, e)));
+            }
+            Reply::Err(e) => return Err(Error::Device(describe(&e))),
+            other => return Err(Error::Device(format!("expected ok got: {:?}", other))),
+        }
+    }
+    Ok(rate)
+}
+
+/// move the link to `rate`, if the device can not be reached there
+/// both sides end up back at the default rate
+pub fn set_baud(serial: &mut dyn SerialPort, rate: u32) -> Result<(), Error> {
+    send(serial, Command::SetBaud(rate))?;
+    match reply(serial)? {
+        Reply::Ok => (),
+        Reply::Err(e) => return Err(Error::Device(describe(&e))),
+        other => return Err(Error::Device(format!("expected ok got: {:?}", other))),
+    }
+    serial.set_baud_rate(rate)?;
+
+    let timeout = serial.timeout();
+    serial.set_timeout(Duration::from_millis(BAUD_CONFIRM / 2))?;
+    send(serial, Command::Ping)?;
+    let pong = reply(serial);
+    serial.set_timeout(timeout)?;
+    if let Ok(Reply::Pong) = pong {
+        return Ok(());
+    }
+
+    serial.set_baud_rate(DEFAULT_BAUD)?;
+    // by now the device has given up as well
+    thread::sleep(Duration::from_millis(BAUD_CONFIRM));
+    serial.clear(ClearBuffer::All)?;
+    Err(Error::Device(format!("no answer at {} baud, back at {}", rate, DEFAULT_BAUD)))
+}
+
+/// an open port that puts the device back at the default baud
+/// rate when dropped, so the next connection finds it
+pub struct Link {
+    pub serial: Box<dyn SerialPort>,
+}
+
+impl Link {
+    pub fn new(mut serial: Box<dyn SerialPort>, baud: u32) -> Result<Self, Error> {
+        if baud != DEFAULT_BAUD {
+            set_baud(serial.as_mut(), baud)?;
+        }
+        Ok(Self { serial })
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        if self.serial.baud_rate().map_or(true, |b| b == DEFAULT_BAUD) {
+            return;
+        }
+        if let Err(e) = set_baud(self.serial.as_mut(), DEFAULT_BAUD) {
+            eprintln!("could not switch the device back to {} baud: {}", DEFAULT_BAUD, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(seq: u16, index: u32, samples: &[i16]) -> (StreamHeader, Vec<u8>) {
+        let bytes = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        (StreamHeader { seq, index }, bytes)
+    }
+
+    #[test]
+    fn gaps() {
+        let mut received = Received::default();
+        // two channels, the second packet went missing and with it
+        // rounds 3 to 5, the fourth packet starts with the second channel
+        for (seq, index, samples) in [(0, 0, [1, 2, 3, 4, 5, 6]), (2, 12, [7, 8, 9, 10, 11, 12]), (3, 19, [13; 6])] {
+            let (header, payload) = packet(seq, index, &samples);
+            received.add_stream(Encoding::Raw, header, &payload).unwrap();
+        }
+        assert_eq!(received.gaps, [(6, 6), (12, 1)]);
+        assert_eq!(received.missing_packets, 1);
+        assert_eq!(received.lost(), 7);
+
+        let gaps = received.gaps_in_time(2, 0.1);
+        let times: Vec<_> = gaps.iter().map(|g| ((g.start * 10.0).round(), (g.end * 10.0).round())).collect();
+        assert_eq!(times, [(3.0, 6.0), (9.0, 10.0)]);
+
+        let channels = [Channel { name: "a".to_owned(), pair: None }, Channel { name: "b".to_owned(), pair: None }];
+        let volts = received.volts(&channels);
+        assert_eq!(volts.len(), 25);
+        assert!(volts[6..12].iter().chain(&volts[18..19]).all(|v| v.is_nan()));
+        assert!(!volts[19].is_nan());
+    }
+
+    #[test]
+    fn stream_going_back() {
+        let mut received = Received::default();
+        let (header, payload) = packet(0, 8, &[1, 2]);
+        received.add_stream(Encoding::Raw, header, &payload).unwrap();
+        let (header, payload) = packet(1, 4, &[3, 4]);
+        assert!(received.add_stream(Encoding::Raw, header, &payload).is_err());
+    }
+
+    #[test]
+    fn stream_index_wraps() {
+        let mut received = Received::default();
+        let (header, payload) = packet(0, u32::MAX - 1, &[1, 2]);
+        received.add_stream(Encoding::Raw, header, &payload).unwrap();
+        let (header, payload) = packet(1, 1, &[3, 4]);
+        received.add_stream(Encoding::Raw, header, &payload).unwrap();
+        assert_eq!(received.samples, [1, 2, 3, 4]);
+        assert_eq!(received.gaps, [(2, 1)]);
+    }
+}
@@ -0,0 +1,88 @@
+//! Find scopes by asking every serial port what is on the other side
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ferrous_serialport::{self as serialport, SerialPortType};
+
+use crate::device::{self, Info};
+use crate::error::Error;
+
+/// usb to serial bridges a scope can sit behind as (vendor, product),
+/// `None` matches any product of that vendor
+const BRIDGES: &[(u16, Option<u16>)] = &[
+    // segger j-link on nordic development kits
+    (0x1366, None),
+    // ftdi
+    (0x0403, None),
+    // silicon labs cp210x
+    (0x10c4, Some(0xea60)),
+    // wch ch340
+    (0x1a86, Some(0x7523)),
+];
+
+/// how long a port may stay silent before it is not a scope
+const HANDSHAKE: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub port: PathBuf,
+    pub info: Info,
+}
+
+/// ports that could have a scope, usb ports are only kept if the
+/// vendor and product are a known bridge, ports without usb
+/// information are always kept
+fn candidates() -> Result<Vec<PathBuf>, Error> {
+    let ports = serialport::available_ports()?;
+    let ports = ports.into_iter()
+        .filter(|p| match &p.port_type {
+            SerialPortType::UsbPort(usb) => BRIDGES.iter()
+                .any(|(vid, pid)| *vid == usb.vid && pid.is_none_or(|pid| pid == usb.pid)),
+            SerialPortType::BluetoothPort => false,
+            SerialPortType::PciPort | SerialPortType::Unknown => true,
+        })
+        .map(|p| device_path(&p.port_name))
+        .collect();
+    Ok(ports)
+}
+
+/// without udev ports are listed by their sysfs entry
+fn device_path(name: &str) -> PathBuf {
+    match Path::new(name).strip_prefix("/sys/class/tty") {
+        Ok(tty) => Path::new("/dev").join(tty),
+        Err(_) => PathBuf::from(name),
+    }
+}
+
+fn handshake(port: &Path) -> Option<Info> {
+    let mut serial = device::open_with_timeout(port, HANDSHAKE).ok()?;
+    device::info(serial.as_mut()).ok()
+}
+
+/// every port that answers the handshake
+pub fn scopes() -> Result<Vec<Found>, Error> {
+    Ok(candidates()?
+        .into_iter()
+        .filter_map(|port| handshake(&port).map(|info| Found { port, info }))
+        .collect())
+}
+
+/// the port given or else the only scope found
+pub fn port(given: &Option<PathBuf>) -> Result<PathBuf, Error> {
+    if let Some(port) = given {
+        return Ok(port.clone());
+    }
+    let mut found = scopes()?;
+    match found.len() {
+        0 => Err(Error::Usage("no scope found, connect one or pass --port".to_owned())),
+        1 => {
+            let found = found.remove(0);
+            eprintln!("using scope on {}", found.port.display());
+            Ok(found.port)
+        }
+        _ => Err(Error::Usage(format!("found {} scopes, pick one with --port: {}",
+            found.len(),
+            found.iter().map(|f| f.port.display().to_string()).collect::<Vec<_>>().join(", ")))),
+    }
+}
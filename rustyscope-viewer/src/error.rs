@@ -0,0 +1,51 @@
+use std::fmt;
+use std::io;
+
+use ferrous_serialport as serialport;
+
+/// everything that ends the viewer early, each kind has its own
+/// exit code
+#[derive(Debug)]
+pub enum Error {
+    /// the device answered with an error or not as expected
+    Device(String),
+    /// invalid arguments, profile or input file
+    Usage(String),
+    /// the serial port or a file could not be used
+    Io(String),
+}
+
+impl Error {
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::Device(_) => 1,
+            Error::Usage(_) => 2,
+            Error::Io(_) => 3,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Device(e) => write!(f, "device: {}", e),
+            Error::Usage(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "io: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::InvalidData => Error::Device(e.to_string()),
+            _ => Error::Io(e.to_string()),
+        }
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(e: serialport::Error) -> Self {
+        Error::Io(e.to_string())
+    }
+}
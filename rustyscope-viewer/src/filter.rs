@@ -0,0 +1,275 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+use crate::capture::{Capture, Trace};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// centered average over this many samples
+    MovingAverage(usize),
+    /// single pole iir low pass, cutoff in Hz
+    LowPass(f32),
+    /// single pole iir high pass, cutoff in Hz
+    HighPass(f32),
+    /// windowed sinc fir low pass
+    Sinc { cutoff: f32, taps: usize },
+    /// keep every n-th sample after low pass filtering
+    Decimate(usize),
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    /// `avg:<n>`, `lp:<hz>`, `hp:<hz>`, `sinc:<hz>[:<taps>]` or `decimate:<n>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let mut arg = || parts.next()
+            .ok_or_else(|| format!("filter {} is missing an argument", kind));
+        let num = |a: &str| a.parse::<f32>().map_err(|e| format!("{}: {}", a, e));
+        let int = |a: &str| a.parse::<usize>().map_err(|e| format!("{}: {}", a, e));
+
+        let filter = match kind {
+            "avg" => Filter::MovingAverage(int(arg()?)?),
+            "lp" => Filter::LowPass(num(arg()?)?),
+            "hp" => Filter::HighPass(num(arg()?)?),
+            "sinc" => {
+                let cutoff = num(arg()?)?;
+                let taps = arg().map(int).unwrap_or(Ok(63))?;
+                Filter::Sinc { cutoff, taps }
+            }
+            "decimate" => Filter::Decimate(int(arg()?)?),
+            _ => return Err(format!("unknown filter: {}, options: \
+                avg:<n>, lp:<hz>, hp:<hz>, sinc:<hz>[:<taps>], decimate:<n>", kind)),
+        };
+        Ok(filter)
+    }
+}
+
+/// start and samples of every stretch without lost samples
+fn segments(values: &[f32]) -> impl Iterator<Item = (usize, &[f32])> {
+    let mut start = 0;
+    values.split(|v| v.is_nan()).filter_map(move |segment| {
+        let at = start;
+        start += segment.len() + 1;
+        (!segment.is_empty()).then_some((at, segment))
+    })
+}
+
+impl Filter {
+    /// every stretch between lost samples is filtered on its own
+    /// so nothing gets smeared across a gap
+    pub fn apply(&self, trace: &Trace) -> Trace {
+        let step = match *self {
+            Filter::Decimate(n) => n.max(1),
+            _ => 1,
+        };
+        let len = trace.values.len().div_ceil(step);
+        let mut values = vec![f32::NAN; len];
+        for (start, segment) in segments(&trace.values) {
+            let filtered = self.filter(segment, trace.dt);
+            // keep the samples that fall on the decimated time axis
+            let skip = (step - start % step) % step;
+            let at = ((start + skip) / step).min(len);
+            let kept = filtered.into_iter().skip(skip).step_by(step);
+            values[at..].iter_mut().zip(kept).for_each(|(v, k)| *v = k);
+        }
+
+        Trace {
+            name: trace.name.clone(),
+            pair: trace.pair.clone(),
+            t0: trace.t0,
+            dt: trace.dt * step as f32,
+            values,
+        }
+    }
+
+    fn filter(&self, values: &[f32], dt: f32) -> Vec<f32> {
+        let rc = |cutoff: f32| 1.0 / (2.0*PI*cutoff);
+
+        match *self {
+            Filter::MovingAverage(n) => convolve(values, &vec![1.0 / n.max(1) as f32; n.max(1)]),
+            Filter::LowPass(cutoff) => {
+                let alpha = dt / (rc(cutoff) + dt);
+                let mut y = values.first().copied().unwrap_or_default();
+                values.iter().map(|x| { y += alpha*(x - y); y }).collect()
+            }
+            Filter::HighPass(cutoff) => {
+                let alpha = rc(cutoff) / (rc(cutoff) + dt);
+                let mut y = 0.0;
+                values.iter()
+                    .scan(values.first().copied().unwrap_or_default(), |prev, x| {
+                        y = alpha*(y + x - *prev);
+                        *prev = *x;
+                        Some(y)
+                    })
+                    .collect()
+            }
+            Filter::Sinc { cutoff, taps } => convolve(values, &sinc(cutoff*dt, taps)),
+            // only the low pass, `apply` picks the samples to keep
+            Filter::Decimate(n) => {
+                let n = n.max(1);
+                // keep well below the new nyquist frequency
+                convolve(values, &sinc(0.4 / n as f32, 8*n + 1))
+            }
+        }
+    }
+}
+
+/// hamming windowed sinc low pass with unity gain at dc, `cutoff`
+/// is a fraction of the sample rate
+fn sinc(cutoff: f32, taps: usize) -> Vec<f32> {
+    let taps = taps.max(1) | 1; // odd so the filter has no delay
+    let mid = (taps / 2) as f32;
+    let kernel: Vec<f32> = (0..taps)
+        .map(|i| {
+            let x = i as f32 - mid;
+            let sinc = if x == 0.0 {
+                2.0*cutoff
+            } else {
+                (2.0*PI*cutoff*x).sin() / (PI*x)
+            };
+            let window = 0.54 - 0.46*(2.0*PI*i as f32 / (taps - 1).max(1) as f32).cos();
+            sinc * window
+        })
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter().map(|k| k / sum).collect()
+}
+
+/// centered convolution, the edges are extended with the first
+/// and last sample
+fn convolve(values: &[f32], kernel: &[f32]) -> Vec<f32> {
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let mid = (kernel.len() / 2) as isize;
+    let last = values.len() as isize - 1;
+    (0..values.len() as isize)
+        .map(|i| kernel.iter()
+            .enumerate()
+            .map(|(k, w)| w * values[(i + k as isize - mid).clamp(0, last) as usize])
+            .sum())
+        .collect()
+}
+
+/// filters for one channel, applied in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelFilter {
+    /// channel name or `all`
+    pub channel: String,
+    pub chain: Vec<Filter>,
+}
+
+impl FromStr for ChannelFilter {
+    type Err = String;
+
+    /// `<channel>=<filter>,<filter>,...`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, chain) = s.split_once('=')
+            .ok_or_else(|| format!("expected <channel>=<filters> got: {}", s))?;
+        let chain = chain.split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { channel: channel.to_owned(), chain })
+    }
+}
+
+impl ChannelFilter {
+    fn applies_to(&self, trace: &Trace) -> bool {
+        self.channel == "all" || self.channel == trace.name
+    }
+}
+
+/// run every trace through the filters configured for it
+pub fn apply(capture: &Capture, filters: &[ChannelFilter]) -> Capture {
+    let traces = capture.traces.iter()
+        .map(|trace| filters.iter()
+            .filter(|f| f.applies_to(trace))
+            .flat_map(|f| &f.chain)
+            .fold(trace.clone(), |trace, filter| filter.apply(&trace)))
+        .collect();
+    Capture { duration: capture.duration, traces, gaps: capture.gaps.clone() }
+}
+
+/// the filtered traces followed by the unfiltered traces they came
+/// from, for overlaying the two
+pub fn with_raw(raw: &Capture, filtered: &Capture, filters: &[ChannelFilter]) -> Capture {
+    let raw = raw.traces.iter()
+        .filter(|t| filters.iter().any(|f| f.applies_to(t)))
+        .map(|t| Trace { name: format!("{} (raw)", t.name), ..t.clone() });
+    let traces = filtered.traces.iter().cloned().chain(raw).collect();
+    Capture { duration: filtered.duration, traces, gaps: filtered.gaps.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(values: Vec<f32>) -> Trace {
+        Trace { name: "ch30".to_owned(), pair: None, t0: 0.0, dt: 1e-3, values }
+    }
+
+    fn same(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.is_nan() && b.is_nan() || (a - b).abs() < 1e-4)
+    }
+
+    #[test]
+    fn split_at_gaps() {
+        let nan = f32::NAN;
+        let values = [1.0, nan, nan, 2.0, 3.0, nan];
+        let found: Vec<_> = segments(&values).collect();
+        assert_eq!(found, [(0, &[1.0][..]), (3, &[2.0, 3.0][..])]);
+        assert_eq!(segments(&[nan, nan]).count(), 0);
+    }
+
+    #[test]
+    fn decimated_on_the_time_axis() {
+        // lost samples 3..=9, kept are 0, 4, 8, 12 and 16
+        let mut values = vec![2.0; 20];
+        values[3..10].iter_mut().for_each(|v| *v = f32::NAN);
+        let out = Filter::Decimate(4).apply(&trace(values));
+        assert!(same(&out.values, &[2.0, f32::NAN, f32::NAN, 2.0, 2.0]), "{:?}", out.values);
+        assert!((out.dt - 4e-3).abs() < 1e-9);
+
+        let out = Filter::Decimate(3).apply(&trace(vec![1.0; 10]));
+        assert_eq!(out.values.len(), 4);
+    }
+
+    #[test]
+    fn sinc_unity_gain() {
+        for &(cutoff, taps) in &[(0.1, 63), (0.25, 9), (0.01, 201), (0.1, 1)] {
+            let sum: f32 = sinc(cutoff, taps).iter().sum();
+            assert!((sum - 1.0).abs() < 1e-5, "{} {}: {}", cutoff, taps, sum);
+        }
+        // odd so the filter has no delay
+        assert_eq!(sinc(0.1, 8).len(), 9);
+
+        let dc = Filter::Sinc { cutoff: 100.0, taps: 63 }.apply(&trace(vec![1.5; 200]));
+        assert!(same(&dc.values, &[1.5; 200]));
+        // nyquist is far above the cutoff
+        let alternating = (0..200).map(|i| if i % 2 == 0 { 1.0 } else { -1.0 }).collect();
+        let out = Filter::Sinc { cutoff: 100.0, taps: 63 }.apply(&trace(alternating));
+        assert!(out.values[50..150].iter().all(|v| v.abs() < 0.01));
+    }
+
+    #[test]
+    fn moving_average() {
+        let ramp: Vec<f32> = (0..10).map(|i| i as f32).collect();
+        let out = Filter::MovingAverage(3).apply(&trace(ramp.clone()));
+        assert!(same(&out.values[1..9], &ramp[1..9]));
+        // edges are extended with the first and last sample
+        assert!(same(&out.values[..1], &[1.0 / 3.0]));
+    }
+
+    #[test]
+    fn parsed() {
+        assert_eq!("sinc:100".parse(), Ok(Filter::Sinc { cutoff: 100.0, taps: 63 }));
+        assert_eq!("decimate:4".parse(), Ok(Filter::Decimate(4)));
+        assert!("lp".parse::<Filter>().is_err());
+        assert!("notch:50".parse::<Filter>().is_err());
+        let chain: ChannelFilter = "ch30=avg:5,lp:1000".parse().unwrap();
+        assert_eq!(chain.channel, "ch30");
+        assert_eq!(chain.chain, [Filter::MovingAverage(5), Filter::LowPass(1000.0)]);
+    }
+}
@@ -1,104 +1,573 @@
-use std::io::Write;
-use std::time::Duration;
-use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(feature = "serve")]
+use std::net::SocketAddr;
 
-use rustyscope_traits::{Command, Reply, SampleKind, ConfigAction};
-use ferrous_serialport as serialport;
+use rustyscope_traits::{Command, Encoding, Reply, SampleKind, Timing, DEFAULT_BAUD};
 use ferrous_serialport::SerialPort;
-use byteorder::{ByteOrder, LittleEndian};
-use std::path::PathBuf;
-use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
 
+mod capture;
+mod decode;
+mod device;
+mod discover;
+mod error;
+mod filter;
+mod math;
+mod measure;
 mod plot;
-const GAIN: f32 = 1.0/4.0;
-const REFV: f32 = 3.3/4.;
-const MAX_VOLT: f32 = REFV/GAIN;
+mod profile;
+#[cfg(feature = "serve")]
+mod serve;
+mod shell;
+mod spectrogram;
+mod spectrum;
+use capture::{Capture, MAX_VOLT};
+use decode::{Decoder, Frame, Threshold};
+use device::{Event, Link, Received};
+use error::Error;
+use filter::ChannelFilter;
+use math::MathChannel;
+use profile::{Profile, Trigger};
+use spectrum::{Scale, Window};
 
+/// exits with 1 if the device reports an error, 2 on invalid
+/// arguments or input and 3 if the port or a file can not be used
 #[derive(structopt::StructOpt, Debug)]
 #[structopt(name = "scope viewer")]
 struct Args {
-    /// path to the serial port
-    #[structopt(short, long)]
-    port: PathBuf,
+    /// path to the serial port, if not given the only scope
+    /// connected is used
+    #[structopt(short, long, global = true)]
+    port: Option<PathBuf>,
+    #[structopt(flatten)]
+    profile: ProfileArgs,
+    #[structopt(flatten)]
+    output: OutputArgs,
+    #[structopt(subcommand)]
+    cmd: Cmd,
 }
 
-fn plot_burst(mut serial: Box<dyn SerialPort>) {
-    use std::io::ErrorKind::TimedOut;
-    let mut bytes = Vec::new();
+#[derive(structopt::StructOpt, Debug)]
+struct ProfileArgs {
+    /// toml file with a table per profile
+    #[structopt(long, default_value = "profiles.toml", global = true)]
+    profiles: PathBuf,
+    /// profile to use, without one pins 30 and 31 are sampled at 250 Hz
+    #[structopt(long, global = true)]
+    profile: Option<String>,
+    /// analog pins to sample, replaces those of the profile. As
+    /// a number on port 0, P0.30, AIN6 or the board name like A4,
+    /// A2-A3 measures A2 against A3
+    #[structopt(long = "analog", number_of_values = 1, global = true)]
+    analog: Vec<profile::AnalogChannel>,
+    /// samples per second
+    #[structopt(long, global = true)]
+    rate: Option<u32>,
+    /// uart speed to switch to after connecting, not used by
+    /// shell and serve
+    #[structopt(long, global = true)]
+    baud: Option<u32>,
+    /// how the device packs samples: raw, packed12, bits8,
+    /// delta-varint or rle
+    #[structopt(long, parse(try_from_str = profile::encoding), global = true)]
+    encoding: Option<Encoding>,
+    /// samples per channel to keep from a burst
+    #[structopt(long, global = true)]
+    samples: Option<usize>,
+    /// show the burst from where a channel crosses a level, as
+    /// <channel>:<volt>[:rising|falling]
+    #[structopt(long, global = true)]
+    trigger: Option<Trigger>,
+}
+
+#[derive(structopt::StructOpt, Debug)]
+struct OutputArgs {
+    /// print measurements as json instead of text
+    #[structopt(long, global = true)]
+    json: bool,
+    /// filters for a channel as <channel>=<filter>,<filter>.. where
+    /// channel can be `all`, filters: avg:<n>, lp:<hz>, hp:<hz>,
+    /// sinc:<hz>[:<taps>] and decimate:<n>
+    #[structopt(long = "filter", number_of_values = 1, global = true)]
+    filters: Vec<ChannelFilter>,
+    /// overlay the unfiltered traces
+    #[structopt(long, global = true)]
+    raw: bool,
+    /// add a channel computed from others as <name> = <expression>,
+    /// supports + - * / abs() integrate() derivative() and constants,
+    /// for example: p = ch30 * (ch31 / 0.1)
+    #[structopt(long = "math", number_of_values = 1, global = true)]
+    math: Vec<MathChannel>,
+    /// decode a bus as <protocol>:<key>=<value>,.. for example
+    /// uart:rx=ch30,baud=9600,bits=8,parity=none,stop=1
+    /// i2c:scl=ch30,sda=ch31 or
+    /// spi:clk=ch30,mosi=ch31,miso=ch29,cs=ch28,cpol=0,cpha=0,bits=8
+    /// onewire:data=ch30, ws2812:din=ch30,
+    /// manchester:data=ch30,rate=1000,convention=ieee or
+    /// can:rx=ch30,bitrate=500000, tolerance=<fraction> sets how far
+    /// timing may be off before a frame is flagged
+    #[structopt(long = "decode", number_of_values = 1, global = true)]
+    decoders: Vec<Decoder>,
+    /// threshold for decoding a channel as <channel>=<volt> or
+    /// <channel>=<low>:<high> for hysteresis, defaults to the
+    /// midpoint between min and max
+    #[structopt(long = "threshold", number_of_values = 1, global = true)]
+    thresholds: Vec<Threshold>,
+    /// write the decoded frames to this csv file
+    #[structopt(long, global = true)]
+    events: Option<PathBuf>,
+    #[structopt(flatten)]
+    spectrum: SpectrumArgs,
+}
+
+#[derive(structopt::StructOpt, Debug)]
+struct SpectrumArgs {
+    /// plot the spectrum of the burst below the traces
+    #[structopt(long, global = true)]
+    spectrum: bool,
+    /// window applied before the fft: rectangular, hann, hamming,
+    /// blackman or flat-top
+    #[structopt(long, default_value = "hann", global = true)]
+    window: Window,
+    /// spectrum scale: dbv or linear
+    #[structopt(long, default_value = "dbv", global = true)]
+    scale: Scale,
+    /// number of bursts to average the spectrum over
+    #[structopt(long, default_value = "1", global = true)]
+    average: usize,
+    /// number of spectral peaks to label
+    #[structopt(long, default_value = "5", global = true)]
+    peaks: usize,
+}
+
+#[derive(structopt::StructOpt, Debug)]
+enum Cmd {
+    /// show the firmware version and what the device can do
+    Info,
+    /// show what the device is doing and how it is configured
+    Status,
+    /// list the scopes connected to this computer
+    List,
+    /// apply the profile to the device
+    Config {
+        /// only print the profile, do not touch the device
+        #[structopt(long)]
+        show: bool,
+    },
+    /// capture one burst then plot it
+    Burst {
+        /// store the burst so it can be opened or exported later
+        #[structopt(long)]
+        save: Option<PathBuf>,
+        /// do not open the plot
+        #[structopt(long)]
+        no_plot: bool,
+    },
+    /// sample continuously for a while then plot the result
+    Stream {
+        /// how long to sample
+        #[structopt(long, default_value = "10")]
+        seconds: f32,
+        /// store the samples so they can be opened or exported later
+        #[structopt(long)]
+        save: Option<PathBuf>,
+        /// do not open the plot
+        #[structopt(long)]
+        no_plot: bool,
+        /// plot a spectrogram per channel instead of the traces,
+        /// uses --window and --scale
+        #[structopt(long)]
+        spectrogram: bool,
+        /// samples per spectrogram segment
+        #[structopt(long, default_value = "256")]
+        segment: usize,
+        /// fraction by which spectrogram segments overlap
+        #[structopt(long, default_value = "0.5")]
+        overlap: f32,
+    },
+    /// convert a saved capture to csv or json, filters and math
+    /// channels are applied
+    Export {
+        input: PathBuf,
+        /// the extension, csv or json, picks the format
+        output: PathBuf,
+    },
+    /// plot a saved capture
+    Open {
+        input: PathBuf,
+    },
+    /// drive the device by hand, commands are typed one per line
+    Shell,
+    /// serve a web dashboard that streams samples and bursts
+    /// to every connected browser, needs the `serve` feature
+    #[cfg(feature = "serve")]
+    Serve {
+        /// address to listen on, the default takes connections from
+        /// other machines, 127.0.0.1:8080 keeps it to this one
+        #[structopt(long, default_value = "0.0.0.0:8080")]
+        addr: SocketAddr,
+    },
+}
 
+/// the selected profile with the command line overrides applied,
+/// checked against what the device can do
+fn profile(args: &ProfileArgs) -> Result<Profile, String> {
+    let mut profile = match &args.profile {
+        Some(name) => profile::load(&args.profiles, name)?,
+        None => Profile::default(),
+    };
+    if !args.analog.is_empty() {
+        profile.analog = args.analog.clone();
+    }
+    profile.rate = args.rate.unwrap_or(profile.rate);
+    profile.baud = args.baud.unwrap_or(profile.baud);
+    profile.encoding = args.encoding.unwrap_or(profile.encoding);
+    profile.samples = args.samples.or(profile.samples);
+    profile.trigger = args.trigger.clone().or(profile.trigger);
+    profile.validate(&device::ABILITIES)?;
+    Ok(profile)
+}
+
+fn open(port: &Option<PathBuf>) -> Result<Box<dyn SerialPort>, Error> {
+    let port = discover::port(port)?;
+    device::open(&port)
+        .map_err(|e| Error::Io(format!("could not open {}: {}", port.display(), e)))
+}
+
+/// open the port and make sure the device runs the firmware the
+/// profile was validated against
+fn checked(port: &Option<PathBuf>) -> Result<Box<dyn SerialPort>, Error> {
+    let mut serial = open(port)?;
+    device::abilities(device::info(serial.as_mut())?.version)?;
+    Ok(serial)
+}
+
+/// open the port and switch to the baud rate of the profile
+fn link(port: &Option<PathBuf>, profile: &Profile) -> Result<Link, Error> {
+    Link::new(checked(port)?, profile.baud)
+}
+
+/// apply the profile, the rate becomes the one the device rounded it to
+fn configure(serial: &mut dyn SerialPort, profile: &mut Profile) -> Result<(), Error> {
+    let rate = device::configure(serial, profile)?;
+    if rate != profile.rate {
+        println!("rate: the device rounds {} Hz to {} Hz", profile.rate, rate);
+        profile.rate = rate;
+    }
+    Ok(())
+}
+
+/// fraction the achieved sample rate may be off before we warn
+const RATE_TOLERANCE: f32 = 0.02;
+
+/// compare the rate the device managed to the one asked for, `samples` counts
+/// every channel, `timing` is only known for bursts
+fn report_rate(profile: &Profile, samples: usize, seconds: f32, timing: Option<&Timing>) {
+    let requested = profile.rate;
+    let channels = profile.names().len().max(1);
+    let achieved = (samples / channels) as f32 / seconds;
+    println!("rate: requested {} Hz, achieved {:.1} Hz", requested, achieved);
+    if let Some(t) = timing {
+        if let Some(jitter) = t.jitter() {
+            let us = |v: u32| measure::si(v as f32 / 1e6, "s");
+            println!("interval: min {}, max {}, jitter {}",
+                us(t.min_interval), us(t.max_interval), us(jitter));
+        }
+        if t.missed > 0 {
+            eprintln!("warning: {} of {} samples were taken late", t.missed, t.samples);
+        }
+    }
+    let off = (achieved - requested as f32).abs() / requested as f32;
+    if off > RATE_TOLERANCE {
+        eprintln!("warning: the achieved rate is {:.1}% off the requested rate", off * 100.0);
+    }
+}
+
+fn read_burst(serial: &mut dyn SerialPort, profile: &Profile) -> Result<Capture, Error> {
+    let mut received = Received::default();
+    let mut timing = None;
+
+    let cmd = Command::Burst(SampleKind::Analog);
+    device::send(serial, cmd)?;
     let duration = loop {
-        let mut buf = [0u8; Command::SIZE];
-        match serial.read_exact(&mut buf) {
-            Err(e) if e.kind() == TimedOut => continue, 
-            Err(e) => panic!("{}", e),
-            Ok(()) => (),
+        match device::next_event(serial)? {
+            Event::Data(buf) => received.add(profile.encoding, &buf)?,
+            Event::Timing(t) => timing = Some(t),
+            Event::Reply(Reply::Err(e)) => return Err(Error::Device(device::describe(&e))),
+            Event::Reply(Reply::Done(duration)) => break duration as f32/1_000_000.,
+            Event::Stream(..) | Event::Status(_) | Event::Config(_) | Event::Reply(_) => continue,
         }
-        
-        let len = match Reply::try_from(&buf).unwrap() {
-            Reply::Ok => continue,
-            Reply::Err(config_err) => panic!("config err: {:?}", config_err),
-            Reply::Data(len) => len,
-            Reply::Done(duration) => break duration as f32/1_000_000.,
-        };
-
-        let mut buf = vec![0u8; len as usize];
-        serial.read_exact(&mut buf).unwrap();
-        bytes.append(&mut buf);
     };
 
     println!("MAX_VOLT: {}", MAX_VOLT);
     println!("duration: {:?}", duration);
-    let mut data = vec![0u16; bytes.len()/2];
-    LittleEndian::read_u16_into(&bytes, &mut data);
-    let data: Vec<f32> = data.drain(..)
-        .map(|v| v as f32)
-        .map(|v| v / (u16::MAX as f32) * MAX_VOLT*4.0)
-        .collect();
-    let y1: Vec<f32> = data.iter().step_by(2).copied().collect();
-    let y2: Vec<f32> = data.iter().skip(1).step_by(2).copied().collect();
-    let x: Vec<f32> = (0..data.len()).into_iter()
-        .map(|i| (i as f32)*duration/(data.len() as f32))
-        .collect();
-    println!("mean {}", data.iter().sum::<f32>()/(data.len() as f32));
-    // plot::line(x.clone(), data);
-    plot::two_lines(x, y1, y2);
-}
-
-
-#[paw::main]
-fn main(args: Args) -> Result<(), std::io::Error> {
-    let mut serial = serialport::new(args.port.to_str().unwrap(), 9600)
-        .parity(serialport::Parity::None)
-        .flow_control(serialport::FlowControl::Hardware)
-        .timeout(Duration::from_secs(20))
-        .open()
-        .unwrap();
-
-    let read_port = serial.try_clone().unwrap();
-    let handle = thread::spawn(move || plot_burst(read_port));
-    
-    let cmd = Command::Config(ConfigAction::ResetPins);
-    serial.write_all(&cmd.serialize()).unwrap();
-
-    let cmd = Command::Config(ConfigAction::AnalogPins(30));
-    serial.write_all(&cmd.serialize()).unwrap();
-    let cmd = Command::Config(ConfigAction::AnalogPins(31));
-    serial.write_all(&cmd.serialize()).unwrap();
-    let cmd = Command::Config(ConfigAction::AnalogRate(250));
-    serial.write_all(&cmd.serialize()).unwrap();
+    println!("received {}", received);
+    report_rate(profile, received.samples.len(), duration, timing.as_ref());
+    let channels = profile.channels();
+    let data = capture::to_volts(&received.samples, &channels, 0);
+    Ok(Capture::from_burst(&data, duration, &channels))
+}
 
-    let cmd = Command::Burst(SampleKind::Analog);
-    serial.write_all(&cmd.serialize()).unwrap();
+/// take `count` bursts one after another
+fn bursts(serial: &mut dyn SerialPort, profile: &Profile, count: usize) -> Result<Vec<Capture>, Error> {
+    (0..count)
+        .map(|_| read_burst(serial, profile).map(|c| profile.prepare(c)))
+        .collect()
+}
+
+fn stream(serial: &mut dyn SerialPort, profile: &Profile, seconds: f32) -> Result<Capture, Error> {
+    let mut received = Received::default();
+    let mut rate = profile.rate;
+
+    let cmd = Command::Continues(SampleKind::Analog);
+    device::send(serial, cmd)?;
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs_f32(seconds);
+    while let Some(event) = device::event_before(serial, deadline)? {
+        match event {
+            Event::Stream(header, buf) => received.add_stream(profile.encoding, header, &buf)?,
+            Event::Reply(Reply::Overrun { lost }) => received.overrun += lost,
+            Event::Reply(Reply::Rate(lowered)) => {
+                eprintln!("the uart can not keep up with {} Hz, streaming at {} Hz, try a higher --baud or a denser --encoding",
+                    rate, lowered);
+                rate = lowered;
+            }
+            Event::Reply(Reply::Err(e)) => return Err(Error::Device(device::describe(&e))),
+            Event::Data(_) | Event::Timing(_) | Event::Status(_) | Event::Config(_) | Event::Reply(_) => continue,
+        }
+    }
+    device::send(serial, Command::Stop)?;
+    let elapsed = start.elapsed().as_secs_f32();
+
+    println!("received {}", received);
+    report_rate(profile, received.samples.len() + received.lost() as usize, elapsed, None);
+    if !received.gaps.is_empty() {
+        eprintln!("warning: {} samples lost in {} gaps, the device dropped {} and {} packets got lost on the wire",
+            received.lost(), received.gaps.len(), received.overrun, received.missing_packets);
+    }
+    if received.overrun > 0 {
+        eprintln!("the uart can not keep up, try a higher --baud or a denser --encoding");
+    }
+
+    let channels = profile.channels();
+    let data = received.volts(&channels);
+    let dt = 1.0 / rate as f32;
+    let duration = (data.len() / channels.len().max(1)) as f32 * dt;
+    let mut capture = Capture::from_burst(&data, duration, &channels);
+    capture.gaps = received.gaps_in_time(channels.len(), dt);
+    Ok(capture)
+}
+
+/// filter the capture then add the math channels
+fn process(raw: &Capture, out: &OutputArgs) -> Result<Capture, Error> {
+    let filtered = filter::apply(raw, &out.filters);
+    math::apply(&filtered, &out.math)
+        .map_err(|e| Error::Usage(format!("math channel: {}", e)))
+}
+
+/// run the decoders, draw their frames and export them if asked
+fn decode(capture: &Capture, out: &OutputArgs, overlay: &mut plot::Overlay) -> Result<(), Error> {
+    let inputs = decode::Inputs { capture, thresholds: &out.thresholds };
+    let frames: Vec<Frame> = out.decoders.iter()
+        .map(|d| d.decode(&inputs))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::Usage(format!("decoder: {}", e)))?
+        .concat();
 
-    loop {
-        thread::sleep(Duration::from_secs(1));
-        let cmd = Command::Stop;
-        serial.write_all(&cmd.serialize()).unwrap();
+    if let Some(path) = &out.events {
+        decode::write_csv(path, &frames)?;
     }
-    
+    plot::frames(overlay, &frames);
+    Ok(())
+}
 
-    handle.join().unwrap();
-    println!("all done");
+/// the filtered capture, with the raw traces added if requested
+fn shown(raw: &Capture, filtered: &Capture, out: &OutputArgs) -> Capture {
+    if out.raw {
+        filter::with_raw(raw, filtered, &out.filters)
+    } else {
+        filtered.clone()
+    }
+}
 
+fn save(path: &Option<PathBuf>, capture: &Capture) -> Result<(), Error> {
+    if let Some(path) = path {
+        capture::save(path, capture)
+            .map_err(|e| Error::Io(format!("could not save {}: {}", path.display(), e)))?;
+    }
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<Capture, Error> {
+    capture::load(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::InvalidInput => Error::Usage(format!("{} is not a capture: {}", path.display(), e)),
+        _ => Error::Io(format!("could not read {}: {}", path.display(), e)),
+    })
+}
+
+/// measure and plot bursts, with a spectrum averaged over all of
+/// them if asked
+fn show_bursts(raw: &[Capture], out: &OutputArgs, plot: bool) -> Result<(), Error> {
+    let args = &out.spectrum;
+    let captures = raw.iter()
+        .map(|c| process(c, out))
+        .collect::<Result<Vec<_>, _>>()?;
+    let capture = captures.last()
+        .ok_or_else(|| Error::Device("no burst received".to_owned()))?;
+    let measurements: Vec<_> = capture.traces.iter().map(measure::measure).collect();
+    if out.json {
+        println!("{}", serde_json::to_string_pretty(&measurements).unwrap());
+    } else {
+        measurements.iter().for_each(|m| println!("{}", m));
+    }
+
+    let mut overlay = plot::Overlay {
+        annotations: plot::measurements(&measurements),
+        ..plot::Overlay::default()
+    };
+    plot::gaps(&mut overlay, &capture.gaps);
+    decode(capture, out, &mut overlay)?;
+    if !plot {
+        return Ok(());
+    }
+    let shown = shown(raw.last().unwrap(), capture, out);
+    if !args.spectrum {
+        plot::capture(&shown, overlay).show();
+        return Ok(());
+    }
+
+    let spectra: Vec<_> = (0..capture.traces.len())
+        .filter_map(|ch| {
+            let spectra: Vec<_> = captures.iter()
+                .map(|c| spectrum::spectrum(&c.traces[ch], args.window))
+                .collect();
+            spectrum::average(&spectra)
+        })
+        .collect();
+    plot::with_spectrum(&shown, overlay, &spectra, args.scale, args.peaks).show();
     Ok(())
 }
+
+fn export(input: &Path, output: &Path, out: &OutputArgs) -> Result<(), Error> {
+    let capture = process(&load(input)?, out)?;
+    let written = match output.extension().and_then(|e| e.to_str()) {
+        Some("csv") => capture::write_csv(output, &capture),
+        Some("json") => capture::save(output, &capture),
+        _ => return Err(Error::Usage(format!(
+            "can not export to {}, use a .csv or .json extension", output.display()))),
+    };
+    written.map_err(|e| Error::Io(format!("could not write {}: {}", output.display(), e)))
+}
+
+fn run(args: Args) -> Result<(), Error> {
+    let mut profile = profile(&args.profile).map_err(Error::Usage)?;
+    let out = profile.output(args.output).map_err(Error::Usage)?;
+
+    match args.cmd {
+        Cmd::Info => {
+            // not checked, this is where to find the firmware version
+            let mut link = Link::new(open(&args.port)?, profile.baud)?;
+            let info = device::info(link.serial.as_mut())?;
+            println!("firmware: {}", device::show_version(info.version));
+            println!("device id: {:08X}", info.id);
+            let a = device::abilities(info.version)?;
+            println!("analog pins: {}", device::show_pins(a.adc_pins));
+            println!("digital pins: {}", device::show_pins(a.digital_pins));
+            println!("resolutions: {:?} bits", a.adc_res);
+            println!("references: {}", a.adc_ref.join(", "));
+            println!("baud rates: {:?}", a.baud_rates);
+            if let Some(max) = a.rate.max_rate(1, device::RESOLUTION) {
+                println!("max rate: {} Hz on one channel", max);
+            }
+        }
+        Cmd::Status => {
+            let mut link = link(&args.port, &profile)?;
+            let status = device::status(link.serial.as_mut())?;
+            let config = device::config(link.serial.as_mut())?;
+            print!("{}{}", device::show_status(&status), device::show_config(&config));
+        }
+        Cmd::List => {
+            let found = discover::scopes()?;
+            if found.is_empty() {
+                return Err(Error::Usage("no scope found".to_owned()));
+            }
+            for f in found {
+                let v = f.info.version;
+                println!("{}  firmware {}.{}.{}  id {:08X}",
+                    f.port.display(), v.major, v.minor, v.patch, f.info.id);
+            }
+        }
+        Cmd::Config { show: true } => print!("{}", profile),
+        Cmd::Config { show: false } => {
+            let mut link = link(&args.port, &profile)?;
+            configure(link.serial.as_mut(), &mut profile)?;
+            print!("{}", profile);
+        }
+        Cmd::Burst { save: path, no_plot } => {
+            let mut link = link(&args.port, &profile)?;
+            configure(link.serial.as_mut(), &mut profile)?;
+            let count = if out.spectrum.spectrum { out.spectrum.average.max(1) } else { 1 };
+            let raw = bursts(link.serial.as_mut(), &profile, count)?;
+            drop(link);
+            if let Some(last) = raw.last() {
+                save(&path, last)?;
+            }
+            show_bursts(&raw, &out, !no_plot)?;
+        }
+        Cmd::Stream { seconds, save: path, no_plot, spectrogram, segment, overlap } => {
+            let mut link = link(&args.port, &profile)?;
+            configure(link.serial.as_mut(), &mut profile)?;
+            let raw = stream(link.serial.as_mut(), &profile, seconds)?;
+            drop(link);
+            let raw = profile.scale(raw);
+            save(&path, &raw)?;
+            let capture = process(&raw, &out)?;
+            let mut overlay = plot::Overlay::default();
+            plot::gaps(&mut overlay, &capture.gaps);
+            decode(&capture, &out, &mut overlay)?;
+            if no_plot {
+                return Ok(());
+            }
+            if !spectrogram {
+                plot::capture(&shown(&raw, &capture, &out), overlay).show();
+                return Ok(());
+            }
+            for trace in &capture.traces {
+                let spectrogram = spectrogram::spectrogram(trace,
+                    out.spectrum.window, segment, overlap);
+                plot::spectrogram(&spectrogram, out.spectrum.scale).show();
+            }
+        }
+        Cmd::Export { input, output } => export(&input, &output, &out)?,
+        Cmd::Open { input } => show_bursts(&[load(&input)?], &out, true)?,
+        Cmd::Shell if profile.baud != DEFAULT_BAUD => {
+            return Err(Error::Usage("shell and serve only talk at the default baud rate".to_owned()));
+        }
+        Cmd::Shell => shell::run(checked(&args.port)?)?,
+        #[cfg(feature = "serve")]
+        Cmd::Serve { .. } if profile.baud != DEFAULT_BAUD => {
+            return Err(Error::Usage("shell and serve only talk at the default baud rate".to_owned()));
+        }
+        #[cfg(feature = "serve")]
+        Cmd::Serve { addr } => serve::run(checked(&args.port)?, addr)?,
+    }
+    Ok(())
+}
+
+fn main() {
+    use structopt::clap::ErrorKind;
+    use structopt::StructOpt;
+
+    let args = match Args::from_iter_safe(std::env::args_os()) {
+        Ok(args) => args,
+        Err(e) if matches!(e.kind, ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed) => e.exit(),
+        Err(e) => {
+            eprintln!("{}", e.message);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("error: {}", e);
+        std::process::exit(e.code());
+    }
+}
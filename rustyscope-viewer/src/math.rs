@@ -0,0 +1,381 @@
+//! Channels computed from other channels, for example the power
+//! through a 0.1 Ohm shunt: `p = ch30 * (ch31 / 0.1)`
+
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+use crate::capture::{Capture, Trace};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Func {
+    Abs,
+    Integrate,
+    Derivative,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Num(f32),
+    Channel(String),
+    Neg(Box<Expr>),
+    Bin(Op, Box<Expr>, Box<Expr>),
+    Call(Func, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f32),
+    Ident(String),
+    Op(char),
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<Chars> = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '0'..='9' | '.' => {
+                let mut num = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                    num.push(c);
+                    chars.next();
+                }
+                Token::Num(num.parse().map_err(|_| format!("invalid number: {}", num))?)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                    ident.push(c);
+                    chars.next();
+                }
+                Token::Ident(ident)
+            }
+            '+' | '-' | '*' | '/' => {
+                chars.next();
+                Token::Op(c)
+            }
+            '(' => {
+                chars.next();
+                Token::Open
+            }
+            ')' => {
+                chars.next();
+                Token::Close
+            }
+            _ => return Err(format!("unexpected character: {}", c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// recursive descent over:
+/// expr   = term (('+' | '-') term)*
+/// term   = factor (('*' | '/') factor)*
+/// factor = '-' factor | number | channel | func '(' expr ')' | '(' expr ')'
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Op(c @ ('+' | '-'))) = self.peek() {
+            let op = if *c == '+' { Op::Add } else { Op::Sub };
+            self.next();
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.factor()?;
+        while let Some(Token::Op(c @ ('*' | '/'))) = self.peek() {
+            let op = if *c == '*' { Op::Mul } else { Op::Div };
+            self.next();
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(self.factor()?));
+        }
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Op('-')) => Ok(Expr::Neg(Box::new(self.factor()?))),
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Open) => {
+                let expr = self.expr()?;
+                self.close()?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                let func = match name.as_str() {
+                    "abs" => Func::Abs,
+                    "integrate" => Func::Integrate,
+                    "derivative" => Func::Derivative,
+                    _ => return Ok(Expr::Channel(name)),
+                };
+                if self.next() != Some(Token::Open) {
+                    return Err(format!("expected ( after {}", name));
+                }
+                let arg = self.expr()?;
+                self.close()?;
+                Ok(Expr::Call(func, Box::new(arg)))
+            }
+            Some(t) => Err(format!("unexpected {:?}", t)),
+            None => Err("unexpected end of expression".to_owned()),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Close) => Ok(()),
+            _ => Err("missing )".to_owned()),
+        }
+    }
+}
+
+/// a math channel definition: `<name> = <expression>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MathChannel {
+    pub name: String,
+    expr: Expr,
+}
+
+impl FromStr for MathChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, expr) = s.split_once('=')
+            .ok_or_else(|| format!("expected <name> = <expression> got: {}", s))?;
+        let mut parser = Parser { tokens: tokenize(expr)?, pos: 0 };
+        let expr = parser.expr()?;
+        if let Some(t) = parser.peek() {
+            return Err(format!("unexpected {:?} after expression", t));
+        }
+        Ok(Self { name: name.trim().to_owned(), expr })
+    }
+}
+
+/// intermediate result, constants are spread out over the samples
+/// when combined with a channel
+enum Value {
+    Const(f32),
+    Samples(Trace),
+}
+
+impl MathChannel {
+    /// evaluate sample by sample, every channel used needs the
+    /// same sample interval
+    pub fn evaluate(&self, capture: &Capture) -> Result<Trace, String> {
+        match eval(&self.expr, capture)? {
+            // not a measurement of the pins any more
+            Value::Samples(trace) => Ok(Trace { name: self.name.clone(), pair: None, ..trace }),
+            Value::Const(_) => Err(format!("{} does not use any channel", self.name)),
+        }
+    }
+}
+
+fn eval(expr: &Expr, capture: &Capture) -> Result<Value, String> {
+    Ok(match expr {
+        Expr::Num(n) => Value::Const(*n),
+        Expr::Channel(name) => capture.traces.iter()
+            .find(|t| &t.name == name)
+            .cloned()
+            .map(Value::Samples)
+            .ok_or_else(|| format!("unknown channel: {}", name))?,
+        Expr::Neg(e) => map(eval(e, capture)?, |v| -v),
+        Expr::Call(Func::Abs, e) => map(eval(e, capture)?, f32::abs),
+        Expr::Call(func, e) => match eval(e, capture)? {
+            Value::Const(_) => return Err(format!("{:?} needs a channel", func)),
+            Value::Samples(trace) if *func == Func::Integrate => Value::Samples(integrate(trace)),
+            Value::Samples(trace) => Value::Samples(derivative(trace)),
+        },
+        Expr::Bin(op, a, b) => {
+            let f = match op {
+                Op::Add => |a, b| a + b,
+                Op::Sub => |a, b| a - b,
+                Op::Mul => |a, b| a * b,
+                Op::Div => |a, b| a / b,
+            };
+            combine(eval(a, capture)?, eval(b, capture)?, f)?
+        }
+    })
+}
+
+fn map(value: Value, f: impl Fn(f32) -> f32) -> Value {
+    match value {
+        Value::Const(c) => Value::Const(f(c)),
+        Value::Samples(mut trace) => {
+            trace.values.iter_mut().for_each(|v| *v = f(*v));
+            Value::Samples(trace)
+        }
+    }
+}
+
+fn combine(a: Value, b: Value, f: fn(f32, f32) -> f32) -> Result<Value, String> {
+    Ok(match (a, b) {
+        (Value::Const(a), Value::Const(b)) => Value::Const(f(a, b)),
+        (Value::Samples(a), Value::Const(b)) => map(Value::Samples(a), |a| f(a, b)),
+        (Value::Const(a), Value::Samples(b)) => map(Value::Samples(b), |b| f(a, b)),
+        (Value::Samples(a), Value::Samples(b)) => {
+            if (a.dt - b.dt).abs() > a.dt * 1e-3 {
+                return Err(format!("{} and {} have different sample intervals", a.name, b.name));
+            }
+            if a.values.len() != b.values.len() {
+                return Err(format!("{} and {} have a different number of samples", a.name, b.name));
+            }
+            let values = a.values.iter().zip(&b.values).map(|(a, b)| f(*a, *b)).collect();
+            Value::Samples(Trace { values, ..a })
+        }
+    })
+}
+
+/// running trapezoid integral
+fn integrate(mut trace: Trace) -> Trace {
+    let mut sum = 0.0;
+    let mut prev = None;
+    for v in trace.values.iter_mut() {
+        if let Some(prev) = prev {
+            sum += (prev + *v) / 2.0 * trace.dt;
+        }
+        prev = Some(*v);
+        *v = sum;
+    }
+    trace
+}
+
+/// central difference, one sided at the edges
+fn derivative(trace: Trace) -> Trace {
+    let v = &trace.values;
+    let last = v.len().saturating_sub(1);
+    let values = (0..v.len())
+        .map(|i| {
+            let (a, b) = (i.saturating_sub(1), (i + 1).min(last));
+            if a == b { 0.0 } else { (v[b] - v[a]) / ((b - a) as f32 * trace.dt) }
+        })
+        .collect();
+    Trace { values, ..trace }
+}
+
+/// add the math channels to the capture, later channels can use
+/// earlier ones
+pub fn apply(capture: &Capture, channels: &[MathChannel]) -> Result<Capture, String> {
+    let mut capture = capture.clone();
+    for channel in channels {
+        let trace = channel.evaluate(&capture)?;
+        capture.traces.push(trace);
+    }
+    Ok(capture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ch(name: &str) -> Box<Expr> {
+        Box::new(Expr::Channel(name.to_owned()))
+    }
+
+    fn num(n: f32) -> Box<Expr> {
+        Box::new(Expr::Num(n))
+    }
+
+    fn parsed(s: &str) -> Expr {
+        s.parse::<MathChannel>().unwrap().expr
+    }
+
+    fn trace(name: &str, values: Vec<f32>) -> Trace {
+        Trace { name: name.to_owned(), pair: None, t0: 0.0, dt: 0.5, values }
+    }
+
+    fn capture() -> Capture {
+        let traces = vec![trace("a", vec![1.0, 2.0, 3.0]), trace("b", vec![2.0, 2.0, 2.0])];
+        Capture { duration: 1.5, traces, gaps: Vec::new() }
+    }
+
+    fn values(s: &str) -> Result<Vec<f32>, String> {
+        s.parse::<MathChannel>()?.evaluate(&capture()).map(|t| t.values)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(parsed("x = a + b * c"), Expr::Bin(Op::Add, ch("a"), Box::new(Expr::Bin(Op::Mul, ch("b"), ch("c")))));
+        assert_eq!(parsed("x = (a + b) * c"), Expr::Bin(Op::Mul, Box::new(Expr::Bin(Op::Add, ch("a"), ch("b"))), ch("c")));
+        // left to right
+        assert_eq!(parsed("x = a - b - c"), Expr::Bin(Op::Sub, Box::new(Expr::Bin(Op::Sub, ch("a"), ch("b"))), ch("c")));
+        assert_eq!(parsed("x = a / b / 2"), Expr::Bin(Op::Div, Box::new(Expr::Bin(Op::Div, ch("a"), ch("b"))), num(2.0)));
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(parsed("x = -a * 2"), Expr::Bin(Op::Mul, Box::new(Expr::Neg(ch("a"))), num(2.0)));
+        assert_eq!(parsed("x = a - -2"), Expr::Bin(Op::Sub, ch("a"), Box::new(Expr::Neg(num(2.0)))));
+        assert_eq!(parsed("x = --a"), Expr::Neg(Box::new(Expr::Neg(ch("a")))));
+        assert_eq!(values("x = -a + 1"), Ok(vec![0.0, -1.0, -2.0]));
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(parsed("x = abs(a - 1.5)"),
+            Expr::Call(Func::Abs, Box::new(Expr::Bin(Op::Sub, ch("a"), num(1.5)))));
+        assert_eq!(values("x = abs(a - 2)"), Ok(vec![1.0, 0.0, 1.0]));
+        assert_eq!(values("x = derivative(a)"), Ok(vec![2.0, 2.0, 2.0]));
+        assert_eq!(values("x = integrate(b)"), Ok(vec![0.0, 1.0, 2.0]));
+    }
+
+    #[test]
+    fn evaluated() {
+        assert_eq!(values("p = a * (b / 0.5) - 1"), Ok(vec![3.0, 7.0, 11.0]));
+        let trace = "p = a * 2".parse::<MathChannel>().unwrap().evaluate(&capture()).unwrap();
+        assert_eq!((trace.name.as_str(), trace.dt), ("p", 0.5));
+    }
+
+    #[test]
+    fn refused() {
+        for s in ["x", "x = (a", "x = a +", "x = a $ b", "x = abs a", "x = a b", "x = 1..2", "x = )"] {
+            assert!(s.parse::<MathChannel>().is_err(), "{}", s);
+        }
+        assert!(values("x = 1 + 2").is_err());
+        assert!(values("x = c * 2").is_err());
+        assert!(values("x = integrate(2)").is_err());
+    }
+
+    #[test]
+    fn traces_must_line_up() {
+        let mut capture = capture();
+        capture.traces.push(trace("short", vec![1.0, 2.0]));
+        capture.traces.push(Trace { dt: 0.25, ..trace("fast", vec![1.0, 2.0, 3.0]) });
+        let eval = |s: &str, capture: &Capture| s.parse::<MathChannel>().unwrap().evaluate(capture);
+        assert!(eval("x = a + short", &capture).is_err());
+        assert!(eval("x = a + fast", &capture).is_err());
+        // lost samples stay lost
+        capture.traces[1].values[1] = f32::NAN;
+        let sum = eval("x = a + b", &capture).unwrap();
+        assert!(sum.values[1].is_nan() && sum.values[2] == 5.0);
+    }
+}
@@ -0,0 +1,263 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::capture::Trace;
+
+/// fraction of the peak to peak voltage a signal has to pass the
+/// midpoint by before it counts as an edge
+const HYSTERESIS: f32 = 0.1;
+
+/// automatic measurements on one trace, timing results are
+/// interpolated between samples but can not resolve anything
+/// much shorter than `resolution`
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Measurements {
+    pub name: String,
+    pub min: f32,
+    pub max: f32,
+    pub vpp: f32,
+    pub mean: f32,
+    pub rms: f32,
+    /// rms with the mean removed
+    pub ac_rms: f32,
+    pub frequency: Option<f32>,
+    pub period: Option<f32>,
+    /// fraction of the period the signal is high
+    pub duty_cycle: Option<f32>,
+    pub pos_width: Option<f32>,
+    pub neg_width: Option<f32>,
+    /// 10% to 90% rise time
+    pub rise_time: Option<f32>,
+    /// 90% to 10% fall time
+    pub fall_time: Option<f32>,
+    /// time between samples
+    pub resolution: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+    /// sample at which the edge got past the hysteresis band
+    idx: usize,
+    /// interpolated time at which the midpoint was crossed
+    time: f32,
+    rising: bool,
+}
+
+pub fn measure(trace: &Trace) -> Measurements {
+    // lost samples do not count
+    let values: Vec<f32> = trace.values.iter().copied().filter(|v| !v.is_nan()).collect();
+    let mut m = Measurements {
+        name: trace.name.clone(),
+        resolution: trace.dt,
+        ..Measurements::default()
+    };
+    if values.is_empty() {
+        return m;
+    }
+
+    let n = values.len() as f32;
+    m.min = values.iter().copied().fold(f32::INFINITY, f32::min);
+    m.max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    m.vpp = m.max - m.min;
+    m.mean = values.iter().sum::<f32>() / n;
+    m.rms = (values.iter().map(|v| v*v).sum::<f32>() / n).sqrt();
+    m.ac_rms = (values.iter().map(|v| (v - m.mean).powi(2)).sum::<f32>() / n).sqrt();
+
+    let edges = edges(trace, m.min, m.max);
+    // edges lost in a gap would stretch whatever spans it, only
+    // time between edges without lost samples in between counts
+    let whole = |a: &Edge, b: &Edge| !trace.values[a.idx..b.idx].iter().any(|v| v.is_nan());
+    let rising: Vec<_> = edges.iter().filter(|e| e.rising).collect();
+    m.period = mean(rising.windows(2)
+        .filter(|w| whole(w[0], w[1]))
+        .map(|w| w[1].time - w[0].time));
+    m.frequency = m.period.map(|p| 1.0 / p);
+
+    m.pos_width = mean(edges.windows(2)
+        .filter(|w| w[0].rising && !w[1].rising && whole(&w[0], &w[1]))
+        .map(|w| w[1].time - w[0].time));
+    m.neg_width = mean(edges.windows(2)
+        .filter(|w| !w[0].rising && w[1].rising && whole(&w[0], &w[1]))
+        .map(|w| w[1].time - w[0].time));
+    if let (Some(pos), Some(neg)) = (m.pos_width, m.neg_width) {
+        m.duty_cycle = Some(pos / (pos + neg));
+    }
+
+    let low = m.min + 0.1*m.vpp;
+    let high = m.min + 0.9*m.vpp;
+    m.rise_time = mean(edges.iter()
+        .filter(|e| e.rising)
+        .filter_map(|e| transition(trace, e.idx, low, high)));
+    m.fall_time = mean(edges.iter()
+        .filter(|e| !e.rising)
+        .filter_map(|e| transition(trace, e.idx, high, low)));
+    m
+}
+
+fn mean(iter: impl Iterator<Item = f32>) -> Option<f32> {
+    let (sum, n) = iter.fold((0.0, 0), |(s, n), v| (s + v, n + 1));
+    if n == 0 {
+        None
+    } else {
+        Some(sum / n as f32)
+    }
+}
+
+/// time at which the line between sample `i-1` and `i` crosses `level`
+fn crossing(trace: &Trace, i: usize, level: f32) -> f32 {
+    let (a, b) = (trace.values[i-1], trace.values[i]);
+    let frac = if a == b { 0.0 } else { (level - a) / (b - a) };
+    trace.t0 + trace.dt*((i - 1) as f32 + frac)
+}
+
+/// find the midpoint crossings, the signal has to leave the
+/// hysteresis band around the midpoint for a crossing to count
+fn edges(trace: &Trace, min: f32, max: f32) -> Vec<Edge> {
+    let values = &trace.values;
+    let mid = (min + max) / 2.0;
+    let band = (max - min) * HYSTERESIS / 2.0;
+    if band <= 0.0 {
+        return Vec::new();
+    }
+
+    let mut high = None;
+    let mut edges = Vec::new();
+    for (i, &v) in values.iter().enumerate() {
+        if v.is_nan() {
+            // an edge can not be placed inside a gap
+            high = None;
+            continue;
+        }
+        let now_high = if v > mid + band {
+            true
+        } else if v < mid - band {
+            false
+        } else {
+            continue;
+        };
+
+        if high == Some(!now_high) {
+            // walk back to where the midpoint was crossed
+            let j = (1..=i).rev()
+                .find(|&j| (values[j-1] < mid) == now_high && (values[j] >= mid) == now_high)
+                .unwrap_or(i);
+            let time = crossing(trace, j, mid);
+            edges.push(Edge { idx: i, time, rising: now_high });
+        }
+        high = Some(now_high);
+    }
+    edges
+}
+
+/// time between the signal passing `from` and `to` around the edge
+/// that passed the hysteresis band at sample `idx`
+fn transition(trace: &Trace, idx: usize, from: f32, to: f32) -> Option<f32> {
+    let values = &trace.values;
+    let rising = to > from;
+    let past = |v: f32, level: f32| if rising { v >= level } else { v <= level };
+
+    let start = (1..=idx).rev().find(|&i| past(values[i], from) && !past(values[i-1], from))?;
+    let end = (start..values.len()).find(|&i| past(values[i], to))?;
+    Some(crossing(trace, end, to) - crossing(trace, start, from))
+}
+
+fn time(t: Option<f32>, resolution: f32) -> String {
+    match t {
+        None => "-".to_owned(),
+        Some(t) if t < 2.0*resolution => format!("{} (below resolution {})", si(t, "s"), si(resolution, "s")),
+        Some(t) => si(t, "s"),
+    }
+}
+
+/// format a value with an si prefix
+pub fn si(v: f32, unit: &str) -> String {
+    const PREFIXES: [(f32, &str); 5] = [(1e6, "M"), (1e3, "k"), (1.0, ""), (1e-3, "m"), (1e-6, "u")];
+    let (scale, prefix) = PREFIXES.iter()
+        .find(|(scale, _)| v.abs() >= *scale || v == 0.0 && *scale == 1.0)
+        .unwrap_or(&(1e-9, "n"));
+    format!("{:.3} {}{}", v / scale, prefix, unit)
+}
+
+impl fmt::Display for Measurements {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let opt = |v: Option<f32>, unit| v.map(|v| si(v, unit)).unwrap_or_else(|| "-".to_owned());
+        writeln!(f, "{}:", self.name)?;
+        writeln!(f, "  min {}  max {}  Vpp {}", si(self.min, "V"), si(self.max, "V"), si(self.vpp, "V"))?;
+        writeln!(f, "  mean {}  rms {}  ac rms {}", si(self.mean, "V"), si(self.rms, "V"), si(self.ac_rms, "V"))?;
+        writeln!(f, "  frequency {}  period {}", opt(self.frequency, "Hz"), time(self.period, self.resolution))?;
+        writeln!(f, "  duty cycle {}", self.duty_cycle
+            .map(|d| format!("{:.1}%", d*100.))
+            .unwrap_or_else(|| "-".to_owned()))?;
+        writeln!(f, "  +width {}  -width {}",
+            time(self.pos_width, self.resolution),
+            time(self.neg_width, self.resolution))?;
+        write!(f, "  rise {}  fall {}",
+            time(self.rise_time, self.resolution),
+            time(self.fall_time, self.resolution))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(values: Vec<f32>) -> Trace {
+        Trace { name: "ch30".to_owned(), pair: None, t0: 0.0, dt: 1e-3, values }
+    }
+
+    fn close(a: Option<f32>, b: f32) -> bool {
+        a.is_some_and(|a| (a - b).abs() <= 1e-4 * b.abs().max(1.0))
+    }
+
+    #[test]
+    fn square_wave() {
+        // high for 5 of every 20 samples
+        let values = (0..100).map(|i| if i % 20 < 5 { 3.0 } else { 0.0 }).collect();
+        let m = measure(&trace(values));
+        assert_eq!((m.min, m.max, m.vpp), (0.0, 3.0, 3.0));
+        assert!((m.mean - 0.75).abs() < 1e-6);
+        assert!(close(m.period, 0.020), "{:?}", m.period);
+        assert!(close(m.frequency, 50.0), "{:?}", m.frequency);
+        assert!(close(m.pos_width, 0.005), "{:?}", m.pos_width);
+        assert!(close(m.neg_width, 0.015), "{:?}", m.neg_width);
+        assert!(close(m.duty_cycle, 0.25), "{:?}", m.duty_cycle);
+    }
+
+    #[test]
+    fn rise_time() {
+        // 10 samples low, a ramp of 10 samples, 10 samples high
+        let values = (0..30).map(|i| ((i as f32 - 9.0) * 0.1).clamp(0.0, 1.0)).collect();
+        let m = measure(&trace(values));
+        // 10% at sample 10, 90% at sample 18
+        assert!(close(m.rise_time, 0.008), "{:?}", m.rise_time);
+        assert_eq!(m.fall_time, None);
+        assert_eq!(m.period, None);
+    }
+
+    #[test]
+    fn flat_has_no_edges() {
+        let m = measure(&trace(vec![1.0; 50]));
+        assert_eq!((m.vpp, m.ac_rms), (0.0, 0.0));
+        assert_eq!((m.frequency, m.duty_cycle, m.rise_time), (None, None, None));
+    }
+
+    #[test]
+    fn si_prefix() {
+        assert_eq!(si(0.0, "V"), "0.000 V");
+        assert_eq!(si(1500.0, "Hz"), "1.500 kHz");
+        assert_eq!(si(-0.002, "V"), "-2.000 mV");
+        assert_eq!(si(3e-6, "s"), "3.000 us");
+        assert_eq!(si(5e-9, "s"), "5.000 ns");
+    }
+
+    #[test]
+    fn gaps_are_skipped() {
+        let mut values: Vec<f32> = (0..100).map(|i| if i % 20 < 10 { 1.0 } else { -1.0 }).collect();
+        values[40..45].iter_mut().for_each(|v| *v = f32::NAN);
+        let m = measure(&trace(values));
+        assert_eq!((m.min, m.max), (-1.0, 1.0));
+        assert!(close(m.period, 0.020), "{:?}", m.period);
+        assert!(close(m.duty_cycle, 0.5), "{:?}", m.duty_cycle);
+    }
+}
@@ -1,42 +1,190 @@
-use plotly::common::Mode;
-use plotly::{Plot, Scatter, ImageFormat};
+use plotly::common::color::NamedColor;
+use plotly::common::{ColorBar, Mode, Title};
+use plotly::layout::{Annotation, Axis, Layout, Shape, ShapeType};
+use plotly::{HeatMap, Plot, Scatter};
 
-pub fn line_y(y: impl IntoIterator<Item=f32> + ExactSizeIterator) {
-    let x = (0..y.len()).into_iter().map(|i| i as f32);
-    line(x,y);
-}
+use crate::capture::{Capture, Gap};
+use crate::decode::Frame;
+use crate::measure::{self, Measurements};
+use crate::spectrogram::Spectrogram;
+use crate::spectrum::{Scale, Spectrum};
 
-pub fn two_lines(x: impl IntoIterator<Item=f32>, 
-    y1: impl IntoIterator<Item=f32>, 
-    y2: impl IntoIterator<Item=f32>) {
+/// markings drawn on top of the traces
+#[derive(Default)]
+pub struct Overlay {
+    pub annotations: Vec<Annotation>,
+    pub shapes: Vec<Shape>,
+}
 
-    let x1: Vec<f32> = x.into_iter().collect();
-    let x2: Vec<f32> = x1.clone();
-    let trace1 = Scatter::new(x1, y1)
-        .name("trace1")
-        .mode(Mode::Lines);
-    let trace2 = Scatter::new(x2, y2)
-        .name("trace2")
-        .mode(Mode::Lines);
+fn add_traces(plot: &mut Plot, capture: &Capture) {
+    for trace in &capture.traces {
+        let line = Scatter::new(trace.time(), trace.values.clone())
+            .name(&trace.name)
+            .mode(Mode::Lines);
+        plot.add_trace(line);
+    }
+}
 
+/// one line per trace in the capture
+pub fn capture(capture: &Capture, overlay: Overlay) -> Plot {
     let mut plot = Plot::new();
-    plot.add_trace(trace1);
-    plot.add_trace(trace2);
+    add_traces(&mut plot, capture);
+    plot.set_layout(Layout::new()
+        .annotations(overlay.annotations)
+        .shapes(overlay.shapes));
 
     // The following will save the plot in all available formats and show the plot.
     // plot.save("scatter", ImageFormat::PNG,  1024, 680, 1.0);
-    plot.show();
+    plot
 }
 
-pub fn line(x: impl IntoIterator<Item=f32>, y: impl IntoIterator<Item=f32>) {
-    let trace = Scatter::new(x, y)
-        .name("trace2")
-        .mode(Mode::Lines);
+/// the capture on top with the spectra of its traces below, the
+/// highest `peaks` peaks of every spectrum get labeled
+pub fn with_spectrum(capture: &Capture, mut overlay: Overlay,
+    spectra: &[Spectrum], scale: Scale, peaks: usize) -> Plot {
 
     let mut plot = Plot::new();
-    plot.add_trace(trace);
+    add_traces(&mut plot, capture);
+    for spectrum in spectra {
+        let values: Vec<_> = spectrum.vrms.iter().map(|v| scale.apply(*v)).collect();
+        let line = Scatter::new(spectrum.freq(), values)
+            .name(&format!("{} spectrum", spectrum.name))
+            .mode(Mode::Lines)
+            .x_axis("x2")
+            .y_axis("y2");
+        plot.add_trace(line);
 
-    // The following will save the plot in all available formats and show the plot.
-    // plot.save("scatter", ImageFormat::PNG,  1024, 680, 1.0);
-    plot.show();
+        for (freq, vrms) in spectrum.peaks(peaks) {
+            overlay.annotations.push(Annotation::new()
+                .text(&measure::si(freq, "Hz"))
+                .x_ref("x2")
+                .y_ref("y2")
+                .x(freq as f64)
+                .y(scale.apply(vrms) as f64)
+                .show_arrow(true));
+        }
+    }
+
+    plot.set_layout(Layout::new()
+        .annotations(overlay.annotations)
+        .shapes(overlay.shapes)
+        .x_axis(Axis::new().title(Title::new("time (s)")))
+        .y_axis(Axis::new().title(Title::new("V")).domain(&[0.55, 1.0]))
+        .x_axis2(Axis::new().title(Title::new("frequency (Hz)")).anchor("y2"))
+        .y_axis2(Axis::new()
+            .title(Title::new(scale.unit()))
+            .domain(&[0.0, 0.45])
+            .anchor("x2")));
+    plot
+}
+
+/// a summary of the measurements per trace in the top left corner
+pub fn measurements(measurements: &[Measurements]) -> Vec<Annotation> {
+    measurements.iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let freq = m.frequency
+                .map(|f| format!(", f {}", measure::si(f, "Hz")))
+                .unwrap_or_default();
+            let text = format!("{}: Vpp {}, mean {}, rms {}{}",
+                m.name,
+                measure::si(m.vpp, "V"),
+                measure::si(m.mean, "V"),
+                measure::si(m.rms, "V"),
+                freq);
+            Annotation::new()
+                .text(&text)
+                .x_ref("paper")
+                .y_ref("paper")
+                .x(0.0)
+                .y(1.0 - 0.05*(i as f64))
+                .show_arrow(false)
+        })
+        .collect()
+}
+
+/// time on the x axis, frequency on the y axis and amplitude as color
+pub fn spectrogram(spectrogram: &Spectrogram, scale: Scale) -> Plot {
+    let z: Vec<Vec<f32>> = spectrogram.vrms.iter()
+        .map(|row| row.iter().map(|v| scale.apply(*v)).collect())
+        .collect();
+    let heatmap = HeatMap::new(spectrogram.times.clone(), spectrogram.freqs.clone(), z)
+        .name(&spectrogram.name)
+        .color_bar(ColorBar::new().title(Title::new(scale.unit())));
+
+    let mut plot = Plot::new();
+    plot.add_trace(heatmap);
+    plot.set_layout(Layout::new()
+        .title(Title::new(&format!("{} spectrogram", spectrogram.name)))
+        .x_axis(Axis::new().title(Title::new("time (s)")))
+        .y_axis(Axis::new().title(Title::new("frequency (Hz)"))));
+    plot
+}
+
+/// gray out where samples were lost
+pub fn gaps(overlay: &mut Overlay, gaps: &[Gap]) {
+    for gap in gaps {
+        overlay.shapes.push(Shape::new()
+            .shape_type(ShapeType::Rect)
+            .x_ref("x")
+            .y_ref("paper")
+            .x0(gap.start as f64)
+            .x1(gap.end as f64)
+            .y0(0.0)
+            .y1(1.0)
+            .fill_color(NamedColor::Gray)
+            .opacity(0.3));
+        overlay.annotations.push(Annotation::new()
+            .text(&format!("{} lost", gap.lost))
+            .x_ref("x")
+            .y_ref("paper")
+            .x(((gap.start + gap.end) / 2.0) as f64)
+            .y(1.0)
+            .show_arrow(false));
+    }
+}
+
+/// decoded frames as labeled boxes along the bottom of the plot,
+/// one row per decoder, frames with errors are red and frames with
+/// timing violations orange
+pub fn frames(overlay: &mut Overlay, frames: &[Frame]) {
+    let mut decoders: Vec<&str> = Vec::new();
+    for frame in frames {
+        let row = match decoders.iter().position(|d| *d == frame.decoder) {
+            Some(row) => row,
+            None => {
+                decoders.push(&frame.decoder);
+                decoders.len() - 1
+            }
+        };
+        let y0 = 0.06 * row as f64;
+        let color = match frame {
+            Frame { error: Some(_), .. } => NamedColor::Red,
+            Frame { timing_violation: true, .. } => NamedColor::Orange,
+            _ => NamedColor::LightBlue,
+        };
+        let text = match &frame.error {
+            Some(e) => format!("{} ({})", frame.text, e),
+            None => frame.text.clone(),
+        };
+
+        overlay.shapes.push(Shape::new()
+            .shape_type(ShapeType::Rect)
+            .x_ref("x")
+            .y_ref("paper")
+            .x0(frame.start as f64)
+            .x1(frame.end as f64)
+            .y0(y0)
+            .y1(y0 + 0.05)
+            .fill_color(color)
+            .opacity(0.4));
+        overlay.annotations.push(Annotation::new()
+            .text(&text)
+            .hover_text(&format!("{}: {}", frame.decoder, text))
+            .x_ref("x")
+            .y_ref("paper")
+            .x(((frame.start + frame.end) / 2.0) as f64)
+            .y(y0 + 0.025)
+            .show_arrow(false));
+    }
 }
@@ -0,0 +1,572 @@
+//! Named setups read from a toml file so changing pins or rates
+//! does not need a recompile, for example:
+//!
+//! ```toml
+//! [motor-current]
+//! rate = 1000
+//! samples = 500
+//! analog = [{ pin = 30, label = "shunt", scale = 10.0 }, { pin = "A2", minus = "A3" }]
+//! trigger = { channel = "shunt", level = 1.5, edge = "rising" }
+//! output = { filters = ["shunt=lp:100"], math = ["i = shunt / 0.1"] }
+//! ```
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rustyscope_traits::{Abilities, Encoding, PinId, DEFAULT_BAUD};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+use crate::capture::{self, Capture, Gap, Trace};
+use crate::OutputArgs;
+use crate::device;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AnalogChannel {
+    #[serde(deserialize_with = "pin")]
+    pub pin: PinId,
+    /// measure `pin` against this one instead of ground, the
+    /// difference can be negative
+    #[serde(default, deserialize_with = "minus")]
+    pub minus: Option<PinId>,
+    /// name of the channel, `ch<pin>` or `ch<pin>_ch<minus>` if not set
+    pub label: Option<String>,
+    /// probe scaling, volts at the probe tip per volt at the pin
+    #[serde(default = "one")]
+    pub scale: f32,
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+/// a pin in a profile, a number is one on port 0 and text is
+/// read by `device::pin`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PinText {
+    Number(u8),
+    Text(String),
+}
+
+impl PinText {
+    fn read(self) -> Result<PinId, String> {
+        match self {
+            PinText::Number(pin) => Ok(device::resolve(PinId::new(0, pin))),
+            PinText::Text(text) => device::pin(&text),
+        }
+    }
+}
+
+fn pin<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PinId, D::Error> {
+    PinText::deserialize(deserializer)?.read().map_err(D::Error::custom)
+}
+
+fn minus<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PinId>, D::Error> {
+    pin(deserializer).map(Some)
+}
+
+fn pins<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PinId>, D::Error> {
+    Vec::<PinText>::deserialize(deserializer)?
+        .into_iter()
+        .map(|p| p.read().map_err(D::Error::custom))
+        .collect()
+}
+
+/// name of a channel without a label, `ch30` for P0.30
+pub fn channel(pin: PinId) -> String {
+    match pin.port {
+        0 => format!("ch{}", pin.pin),
+        port => format!("ch{}_{}", port, pin.pin),
+    }
+}
+
+impl AnalogChannel {
+    pub fn new(pin: PinId, minus: Option<PinId>) -> Self {
+        Self { pin, minus, label: None, scale: 1.0 }
+    }
+
+    pub fn name(&self) -> String {
+        self.label.clone().unwrap_or_else(|| match self.minus {
+            Some(minus) => format!("{}_{}", channel(self.pin), channel(minus)),
+            None => channel(self.pin),
+        })
+    }
+
+    /// the pins as shown to the user, `A4 - A5` for a pair
+    pub fn pins(&self) -> String {
+        match self.minus {
+            Some(minus) => format!("{} - {}", device::show_pin(self.pin), device::show_pin(minus)),
+            None => device::show_pin(self.pin),
+        }
+    }
+
+    pub fn to_channel(&self) -> capture::Channel {
+        capture::Channel {
+            name: self.name(),
+            pair: self.minus.map(|minus| format!("{} - {}", self.pin, minus)),
+        }
+    }
+}
+
+/// `<pin>` or `<pin>-<minus>` for a differential channel
+impl FromStr for AnalogChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find('-') {
+            Some(dash) => Ok(Self::new(device::pin(&s[..dash])?, Some(device::pin(&s[dash + 1..])?))),
+            None => Ok(Self::new(device::pin(s)?, None)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Slope {
+    Rising,
+    Falling,
+}
+
+impl FromStr for Slope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rising" => Ok(Slope::Rising),
+            "falling" => Ok(Slope::Falling),
+            _ => Err(format!("unknown edge: {}, options: rising, falling", s)),
+        }
+    }
+}
+
+/// starts the shown part of a burst where a channel crosses a level,
+/// done on the host after the burst is received
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Trigger {
+    pub channel: String,
+    /// in volts after probe scaling
+    pub level: f32,
+    #[serde(default = "rising")]
+    pub edge: Slope,
+    /// fraction of the shown samples before the trigger
+    #[serde(default)]
+    pub position: f32,
+}
+
+fn rising() -> Slope {
+    Slope::Rising
+}
+
+impl FromStr for Trigger {
+    type Err = String;
+
+    /// `<channel>:<level>[:<edge>]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let channel = parts.next().unwrap_or_default().to_owned();
+        let level = parts.next()
+            .ok_or_else(|| format!("expected <channel>:<level>[:<edge>] got: {}", s))?;
+        let level = level.parse().map_err(|e| format!("{}: {}", level, e))?;
+        let edge = parts.next().map(str::parse).unwrap_or(Ok(Slope::Rising))?;
+        Ok(Self { channel, level, edge, position: 0.0 })
+    }
+}
+
+impl Trigger {
+    /// index of the first sample past the level
+    fn find(&self, trace: &Trace) -> Option<usize> {
+        trace.values.windows(2)
+            .position(|w| match self.edge {
+                Slope::Rising => w[0] < self.level && w[1] >= self.level,
+                Slope::Falling => w[0] > self.level && w[1] <= self.level,
+            })
+            .map(|i| i + 1)
+    }
+}
+
+/// output options, each list is parsed like its command line flag
+/// and comes before the entries given on the command line
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Output {
+    json: bool,
+    raw: bool,
+    spectrum: bool,
+    filters: Vec<String>,
+    math: Vec<String>,
+    decode: Vec<String>,
+    thresholds: Vec<String>,
+    events: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub analog: Vec<AnalogChannel>,
+    #[serde(deserialize_with = "pins")]
+    pub digital: Vec<PinId>,
+    /// samples per second
+    pub rate: u32,
+    /// uart speed while the viewer talks to the device
+    pub baud: u32,
+    /// how the device packs samples
+    pub encoding: Encoding,
+    /// samples per channel to keep from a burst
+    pub samples: Option<usize>,
+    pub trigger: Option<Trigger>,
+    pub output: Output,
+}
+
+/// what the viewer did before there were profiles
+impl Default for Profile {
+    fn default() -> Self {
+        let analog = |pin| AnalogChannel::new(device::resolve(PinId::new(0, pin)), None);
+        Self {
+            analog: vec![analog(30), analog(31)],
+            digital: Vec::new(),
+            rate: 250,
+            baud: DEFAULT_BAUD,
+            encoding: Encoding::Raw,
+            samples: None,
+            trigger: None,
+            output: Output::default(),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in &self.analog {
+            writeln!(f, "analog pin {}: {} x{}", c.pins(), c.name(), c.scale)?;
+        }
+        for &pin in &self.digital {
+            writeln!(f, "digital pin {}: {}", device::show_pin(pin), channel(pin))?;
+        }
+        writeln!(f, "rate: {} Hz", self.rate)?;
+        writeln!(f, "baud: {}", self.baud)?;
+        writeln!(f, "encoding: {}", self.encoding.name())?;
+        if let Some(samples) = self.samples {
+            writeln!(f, "samples: {}", samples)?;
+        }
+        if let Some(t) = &self.trigger {
+            writeln!(f, "trigger: {} {:?} through {} V at {}%",
+                t.channel, t.edge, t.level, t.position * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// parse an encoding by the name used in profiles
+pub fn encoding(s: &str) -> Result<Encoding, String> {
+    Encoding::ALL.iter()
+        .copied()
+        .find(|e| e.name() == s)
+        .ok_or_else(|| format!("unknown encoding: {}, options: {}", s,
+            Encoding::ALL.iter().map(|e| e.name()).collect::<Vec<_>>().join(", ")))
+}
+
+/// read profile `name` from a file with one table per profile
+pub fn load(path: &Path, name: &str) -> Result<Profile, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let mut profiles: BTreeMap<String, Profile> = toml::from_str(&text)
+        .map_err(|e| format!("invalid profiles in {}: {}", path.display(), e))?;
+    profiles.remove(name).ok_or_else(|| format!(
+        "no profile {} in {}, options: {}",
+        name,
+        path.display(),
+        profiles.keys().cloned().collect::<Vec<_>>().join(", ")))
+}
+
+impl Profile {
+    /// channel names in the order the device samples them
+    pub fn names(&self) -> Vec<String> {
+        self.analog.iter().map(AnalogChannel::name).collect()
+    }
+
+    /// the analog channels in the order the device samples them
+    pub fn channels(&self) -> Vec<capture::Channel> {
+        self.analog.iter().map(AnalogChannel::to_channel).collect()
+    }
+
+    /// check the profile against what the device can do, before
+    /// anything is sent to it
+    pub fn validate(&self, abilities: &Abilities) -> Result<(), String> {
+        let mut problems = Vec::new();
+        let mut pins = HashSet::new();
+        for pin in self.analog.iter().flat_map(|c| std::iter::once(c.pin).chain(c.minus)) {
+            if !abilities.adc_pins.contains(&pin) {
+                problems.push(format!("pin {} can not be sampled analog, options: {}",
+                    device::show_pin(pin), device::show_pins(abilities.adc_pins)));
+            }
+            if !pins.insert(pin) {
+                problems.push(format!("pin {} is used twice", device::show_pin(pin)));
+            }
+        }
+        for &pin in &self.digital {
+            if !abilities.digital_pins.contains(&pin) {
+                problems.push(format!("pin {} can not be sampled digital, options: {}",
+                    device::show_pin(pin), device::show_pins(abilities.digital_pins)));
+            }
+            if !pins.insert(pin) {
+                problems.push(format!("pin {} is used twice", device::show_pin(pin)));
+            }
+        }
+        if pins.is_empty() {
+            problems.push("no pins to sample".to_owned());
+        }
+
+        let names = self.names();
+        let unique: HashSet<_> = names.iter().collect();
+        if unique.len() != names.len() {
+            problems.push("channel labels are not unique".to_owned());
+        }
+        for c in self.analog.iter().filter(|c| !c.scale.is_normal()) {
+            problems.push(format!("scale of {} has to be a non zero number", c.name()));
+        }
+        if let Err(e) = abilities.rate.period(self.rate, self.analog.len(), device::RESOLUTION) {
+            problems.push(device::describe(&e));
+        }
+        if !abilities.baud_rates.contains(&self.baud) {
+            problems.push(format!("baud rate {} is not supported, options: {:?}",
+                self.baud, abilities.baud_rates));
+        }
+        if !abilities.encodings.contains(&self.encoding) {
+            problems.push(format!("encoding {} is not supported", self.encoding.name()));
+        }
+        if let Some(bits) = self.encoding.max_bits().filter(|&b| b < device::RESOLUTION) {
+            problems.push(format!("encoding {} carries at most {} bits, the device samples {}, options: {}",
+                self.encoding.name(), bits, device::RESOLUTION,
+                Encoding::ALL.iter()
+                    .filter(|e| e.max_bits().is_none_or(|b| b >= device::RESOLUTION))
+                    .map(|e| e.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")));
+        }
+        if !self.encoding.signed() && self.analog.iter().any(|c| c.minus.is_some()) {
+            problems.push(format!("encoding {} loses the negative readings of differential channels, options: {}",
+                self.encoding.name(),
+                Encoding::ALL.iter().filter(|e| e.signed()).map(|e| e.name()).collect::<Vec<_>>().join(", ")));
+        }
+        if self.samples == Some(0) {
+            problems.push("samples can not be zero".to_owned());
+        }
+        if let Some(trigger) = &self.trigger {
+            if !names.contains(&trigger.channel) {
+                problems.push(format!("trigger channel {} is not sampled", trigger.channel));
+            }
+            if !(0.0..=1.0).contains(&trigger.position) {
+                problems.push("trigger position has to be between 0 and 1".to_owned());
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("\n"))
+        }
+    }
+
+    /// add the output options of the profile to those given on the
+    /// command line, flags set in either are set
+    pub fn output(&self, mut out: OutputArgs) -> Result<OutputArgs, String> {
+        fn parsed<T: FromStr<Err = String>>(profile: &[String], cli: Vec<T>) -> Result<Vec<T>, String> {
+            let mut list = profile.iter()
+                .map(|s| s.parse())
+                .collect::<Result<Vec<T>, _>>()?;
+            list.extend(cli);
+            Ok(list)
+        }
+
+        let p = &self.output;
+        out.json |= p.json;
+        out.raw |= p.raw;
+        out.spectrum.spectrum |= p.spectrum;
+        out.filters = parsed(&p.filters, out.filters)?;
+        out.math = parsed(&p.math, out.math)?;
+        out.decoders = parsed(&p.decode, out.decoders)?;
+        out.thresholds = parsed(&p.thresholds, out.thresholds)?;
+        out.events = out.events.or_else(|| p.events.clone());
+        Ok(out)
+    }
+
+    /// scale to the probe tip
+    pub fn scale(&self, mut capture: Capture) -> Capture {
+        for (trace, channel) in capture.traces.iter_mut().zip(&self.analog) {
+            trace.values.iter_mut().for_each(|v| *v *= channel.scale);
+        }
+        capture
+    }
+
+    /// scale the burst, then cut out the samples to show with time
+    /// zero at the trigger
+    pub fn prepare(&self, capture: Capture) -> Capture {
+        let capture = self.scale(capture);
+        let first = match capture.traces.first() {
+            Some(trace) => trace,
+            None => return capture,
+        };
+
+        let (mut start, mut zero) = (first.t0, 0.0);
+        if let Some(trigger) = &self.trigger {
+            let trace = capture.traces.iter().find(|t| t.name == trigger.channel);
+            match trace.and_then(|t| trigger.find(t).map(|i| (t, i))) {
+                Some((trace, i)) => {
+                    zero = trace.t0 + i as f32 * trace.dt;
+                    let before = self.samples.unwrap_or(trace.values.len()) as f32 * trigger.position;
+                    start = zero - before * trace.dt;
+                }
+                None => eprintln!("trigger on {} not found, showing the whole burst",
+                    trigger.channel),
+            }
+        }
+
+        let traces: Vec<_> = capture.traces.iter()
+            .map(|trace| {
+                // index of the first sample at or after start
+                let skip = ((start - trace.t0) / trace.dt).ceil().max(0.0) as usize;
+                let values: Vec<_> = trace.values.iter()
+                    .skip(skip)
+                    .take(self.samples.unwrap_or(usize::MAX))
+                    .copied()
+                    .collect();
+                Trace {
+                    name: trace.name.clone(),
+                    pair: trace.pair.clone(),
+                    t0: trace.t0 + skip as f32 * trace.dt - zero,
+                    dt: trace.dt,
+                    values,
+                }
+            })
+            .collect();
+        let duration = traces.iter()
+            .map(|t| t.values.len() as f32 * t.dt)
+            .fold(0.0, f32::max);
+        let gaps = capture.gaps.iter()
+            .map(|g| Gap { start: g.start - zero, end: g.end - zero, ..g.clone() })
+            .collect();
+        Capture { duration, traces, gaps }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::ABILITIES;
+
+    fn parse(toml: &str) -> Profile {
+        let mut profiles: BTreeMap<String, Profile> = toml::from_str(toml).unwrap();
+        profiles.remove("test").unwrap()
+    }
+
+    fn problems(profile: &Profile) -> Vec<String> {
+        match profile.validate(&ABILITIES) {
+            Ok(()) => Vec::new(),
+            Err(e) => e.lines().map(str::to_owned).collect(),
+        }
+    }
+
+    #[test]
+    fn parsed() {
+        let profile = parse(r#"
+            [test]
+            rate = 1000
+            analog = [{ pin = 30, label = "shunt", scale = 10.0 }, { pin = "A2", minus = "A3" }]
+            trigger = { channel = "shunt", level = 1.5 }
+        "#);
+        assert_eq!(profile.names(), ["shunt", "ch28_ch29"]);
+        assert_eq!(profile.analog[1].minus, Some(device::pin("A3").unwrap()));
+        assert_eq!(profile.trigger.as_ref().unwrap().edge, Slope::Rising);
+        assert_eq!(problems(&profile), Vec::<String>::new());
+        assert!(toml::from_str::<BTreeMap<String, Profile>>("[test]\nspeed = 3").is_err());
+    }
+
+    #[test]
+    fn default_is_valid() {
+        assert_eq!(problems(&Profile::default()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn pins() {
+        let mut profile = Profile::default();
+        profile.analog.push(AnalogChannel::new(PinId::new(0, 7), None));
+        profile.analog.push(AnalogChannel::new(device::pin("A2").unwrap(), Some(PinId::new(0, 30))));
+        let problems = problems(&profile);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("pin P0.07 can not be sampled analog"), "{}", problems[0]);
+        assert!(problems[1].ends_with("is used twice"), "{}", problems[1]);
+
+        profile.analog.clear();
+        assert_eq!(self::problems(&profile), ["no pins to sample"]);
+    }
+
+    #[test]
+    fn settings() {
+        let mut profile = Profile { rate: 1_000_000, baud: 12345, samples: Some(0), ..Profile::default() };
+        profile.analog[1].label = Some("ch30".to_owned());
+        profile.analog[0].scale = 0.0;
+        let problems = problems(&profile);
+        assert_eq!(problems.len(), 5, "{:?}", problems);
+        assert!(problems.iter().any(|p| p.starts_with("baud rate 12345")));
+        assert!(problems.iter().any(|p| p == "channel labels are not unique"));
+        assert!(problems.iter().any(|p| p == "samples can not be zero"));
+    }
+
+    #[test]
+    fn differential_needs_signed() {
+        let mut profile = Profile {
+            analog: vec!["A2-A3".parse().unwrap()],
+            encoding: Encoding::Packed12,
+            ..Profile::default()
+        };
+        let problems = problems(&profile);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("encoding packed12 loses the negative readings"));
+        profile.encoding = Encoding::DeltaVarint;
+        assert_eq!(self::problems(&profile), Vec::<String>::new());
+    }
+
+    #[test]
+    fn encoding_too_narrow() {
+        let mut profile = Profile { encoding: Encoding::Bits8, ..Profile::default() };
+        let problems = problems(&profile);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("encoding bits8 carries at most 8 bits, the device samples 12"), "{}", problems[0]);
+        profile.encoding = Encoding::Packed12;
+        assert_eq!(self::problems(&profile), Vec::<String>::new());
+    }
+
+    #[test]
+    fn trigger() {
+        let trigger = Trigger { position: 1.5, ..Trigger::from_str("ch7:1").unwrap() };
+        let profile = Profile { trigger: Some(trigger), ..Profile::default() };
+        assert_eq!(problems(&profile), ["trigger channel ch7 is not sampled", "trigger position has to be between 0 and 1"]);
+    }
+
+    #[test]
+    fn prepared() {
+        let trigger = Trigger { position: 0.5, ..Trigger::from_str("ch30:1").unwrap() };
+        let mut profile = Profile { samples: Some(4), trigger: Some(trigger), ..Profile::default() };
+        profile.analog.truncate(1);
+        profile.analog[0].scale = 2.0;
+        let trace = Trace {
+            name: "ch30".to_owned(),
+            pair: None,
+            t0: 0.0,
+            dt: 0.1,
+            values: vec![0.0, 0.1, 0.2, 0.3, 0.4, 0.6, 0.7, 0.8],
+        };
+        let capture = Capture { duration: 0.8, traces: vec![trace], gaps: Vec::new() };
+        // scaled by 2 the level is crossed at 0.5, two samples are kept before it
+        let prepared = profile.prepare(capture);
+        let trace = &prepared.traces[0];
+        assert_eq!(trace.values, [0.6, 0.8, 1.2, 1.4]);
+        assert!((trace.t0 + 0.2).abs() < 1e-6, "{}", trace.t0);
+    }
+}
@@ -0,0 +1,333 @@
+//! Local web dashboard, every connected browser sees the same samples
+//! and bursts and can send commands to the scope
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use ferrous_serialport::SerialPort;
+use rustyscope_traits::{Command, DeviceConfig, Encoding, Reply, StreamHeader};
+use serde_json::json;
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::capture::{self, Capture};
+use crate::device::{self, Event, Received};
+use crate::profile::AnalogChannel;
+use crate::{measure, plot};
+
+const INDEX: &str = include_str!("../static/index.html");
+/// the version the plotly crate renders with, served from here so
+/// the dashboard works without internet, MIT licensed, see
+/// `static/plotly-LICENSE.txt`
+const PLOTLY: &str = include_str!("../static/plotly-1.54.6.min.js");
+/// how long a websocket read may block before we check for
+/// messages that need to go out
+const POLL: Duration = Duration::from_millis(50);
+
+/// what we know about the device setup, taken from the config the
+/// device reports so refused changes never show up here
+#[derive(Default)]
+struct Setup {
+    channels: Vec<AnalogChannel>,
+    rate: Option<u32>,
+    /// index of the next continues sample
+    streamed: usize,
+    encoding: Encoding,
+    /// frames of a `ConfigBatch` still to pass through
+    batch_left: u8,
+}
+
+impl Setup {
+    /// the commands to send for `cmd`, a config change is followed
+    /// by `GetConfig` (after the last frame for a batch) so the
+    /// setup is updated from the answer
+    fn outgoing(&mut self, cmd: Command) -> io::Result<Vec<Command>> {
+        match cmd {
+            Command::Continues(_) => self.streamed = 0,
+            // the port here would stay at the old rate
+            Command::SetBaud(_) => {
+                return Err(io::Error::new(ErrorKind::InvalidInput, "baud rate can not be changed while serving"));
+            }
+            Command::ConfigBatch(frames) => self.batch_left = frames,
+            Command::Config(_) if self.batch_left > 1 => self.batch_left -= 1,
+            Command::Config(_) => {
+                self.batch_left = 0;
+                return Ok(vec![cmd, Command::GetConfig]);
+            }
+            Command::Stop | Command::Burst(_) | Command::Info | Command::Ping
+                | Command::GetStatus | Command::GetConfig => (),
+        }
+        Ok(vec![cmd])
+    }
+
+    /// take over the config the device reports
+    fn follow(&mut self, config: &DeviceConfig) {
+        self.channels = config.analog()
+            .iter()
+            .zip(config.minus())
+            .map(|(&pin, &minus)| AnalogChannel::new(device::resolve(pin), minus.map(device::resolve)))
+            .collect();
+        self.rate = config.rate;
+        self.encoding = config.encoding;
+    }
+}
+
+struct Hub {
+    serial: Mutex<Box<dyn SerialPort>>,
+    setup: Mutex<Setup>,
+    clients: Mutex<Vec<mpsc::Sender<String>>>,
+}
+
+impl Hub {
+    fn broadcast(&self, msg: String) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|c| c.send(msg.clone()).is_ok());
+    }
+
+    fn command(&self, cmd: Command) -> io::Result<()> {
+        let outgoing = self.setup.lock().unwrap().outgoing(cmd)?;
+        let mut serial = self.serial.lock().unwrap();
+        for cmd in outgoing {
+            device::send(serial.as_mut(), cmd)?;
+        }
+        Ok(())
+    }
+
+    /// spread continues samples over the channels and give them a
+    /// time based on the configured rate (or their index if unknown)
+    fn samples(&self, header: StreamHeader, received: &Received) -> String {
+        let mut setup = self.setup.lock().unwrap();
+        let channels: Vec<_> = setup.channels.iter().map(AnalogChannel::to_channel).collect();
+        let n = channels.len().max(1);
+        let period = setup.rate.map(|r| 1.0 / r as f32).unwrap_or(1.0);
+
+        let mut x = vec![Vec::new(); n];
+        let mut y = vec![Vec::new(); n];
+        if header.index as usize > setup.streamed {
+            // a null breaks the lines where samples were lost
+            let idx = setup.streamed;
+            for (x, y) in x.iter_mut().zip(&mut y) {
+                x.push((idx / n) as f32 * period);
+                y.push(f32::NAN);
+            }
+        }
+        setup.streamed = header.index as usize;
+        for value in capture::to_volts(&received.samples, &channels, setup.streamed) {
+            let idx = setup.streamed;
+            x[idx % n].push((idx / n) as f32 * period);
+            y[idx % n].push(value);
+            setup.streamed += 1;
+        }
+
+        let traces: Vec<_> = x.into_iter()
+            .zip(y)
+            .enumerate()
+            .map(|(i, (x, y))| {
+                let name = channels.get(i).map(|c| c.name.clone()).unwrap_or_default();
+                json!({ "name": name, "x": x, "y": y })
+            })
+            .collect();
+        json!({ "type": "samples", "traces": traces }).to_string()
+    }
+
+    fn burst(&self, received: &Received, micros: u32) -> String {
+        let channels: Vec<_> = self.setup.lock().unwrap().channels.iter().map(AnalogChannel::to_channel).collect();
+        if channels.is_empty() {
+            return error("burst finished but no pins are configured");
+        }
+
+        let data = capture::to_volts(&received.samples, &channels, 0);
+        let capture = Capture::from_burst(&data, micros as f32 / 1_000_000., &channels);
+        let measurements: Vec<_> = capture.traces.iter().map(measure::measure).collect();
+        let overlay = plot::Overlay {
+            annotations: plot::measurements(&measurements),
+            ..plot::Overlay::default()
+        };
+        let plot = plot::capture(&capture, overlay).to_json();
+        format!(r#"{{"type": "burst", "plot": {}}}"#, plot)
+    }
+}
+
+fn error(msg: impl ToString) -> String {
+    json!({ "type": "error", "error": msg.to_string() }).to_string()
+}
+
+fn ws_err(e: tungstenite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// serve the dashboard on `addr` until the device connection fails
+pub fn run(serial: Box<dyn SerialPort>, addr: SocketAddr) -> io::Result<()> {
+    let read_port = serial.try_clone()?;
+    let hub = Arc::new(Hub {
+        serial: Mutex::new(serial),
+        setup: Mutex::new(Setup::default()),
+        clients: Mutex::new(Vec::new()),
+    });
+
+    // start from whatever the device is set up for
+    hub.command(Command::GetConfig)?;
+    let listener = TcpListener::bind(addr)?;
+    println!("serving dashboard on http://{}", addr);
+    let listen_hub = hub.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let hub = listen_hub.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(hub, stream) {
+                    eprintln!("connection closed: {}", e);
+                }
+            });
+        }
+    });
+
+    forward_device(&hub, read_port)
+}
+
+/// push everything the device sends to all browsers
+fn forward_device(hub: &Hub, mut serial: Box<dyn SerialPort>) -> io::Result<()> {
+    let mut burst = Received::default();
+    loop {
+        let msg = match device::next_event(serial.as_mut())? {
+            Event::Data(bytes) => {
+                let encoding = hub.setup.lock().unwrap().encoding;
+                match burst.add(encoding, &bytes) {
+                    Err(e) => error(e),
+                    Ok(()) => continue,
+                }
+            }
+            Event::Stream(header, bytes) => {
+                let encoding = hub.setup.lock().unwrap().encoding;
+                let mut received = Received::default();
+                match received.add(encoding, &bytes) {
+                    Err(e) => error(e),
+                    Ok(()) => hub.samples(header, &received),
+                }
+            }
+            Event::Reply(Reply::Overrun { lost }) => error(format!("device dropped {} samples", lost)),
+            Event::Reply(Reply::Done(micros)) => {
+                let msg = hub.burst(&burst, micros);
+                burst = Received::default();
+                msg
+            }
+            Event::Reply(Reply::Err(e)) => error(device::describe(&e)),
+            Event::Reply(Reply::Rate(rate)) => {
+                hub.setup.lock().unwrap().rate = Some(rate);
+                continue;
+            }
+            Event::Config(config) => {
+                hub.setup.lock().unwrap().follow(&config);
+                continue;
+            }
+            Event::Timing(_) | Event::Status(_) | Event::Reply(_) => continue,
+        };
+        hub.broadcast(msg);
+    }
+}
+
+fn handle_connection(hub: Arc<Hub>, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    let mut key = None;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_owned());
+            }
+        }
+    }
+
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    match (path, key) {
+        ("/ws", Some(key)) => websocket(hub, stream, &key),
+        ("/", _) => respond(stream, "200 OK", "text/html", INDEX),
+        ("/plotly-1.54.6.min.js", _) => respond(stream, "200 OK", "text/javascript", PLOTLY),
+        _ => respond(stream, "404 Not Found", "text/plain", "not found"),
+    }
+}
+
+fn respond(mut stream: TcpStream, status: &str, kind: &str, body: &str) -> io::Result<()> {
+    write!(stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, kind, body.len(), body)
+}
+
+fn websocket(hub: Arc<Hub>, mut stream: TcpStream, key: &str) -> io::Result<()> {
+    write!(stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes()))?;
+    stream.set_read_timeout(Some(POLL))?;
+    let mut ws = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+    let (tx, rx) = mpsc::channel();
+    hub.clients.lock().unwrap().push(tx);
+
+    loop {
+        match ws.read() {
+            Ok(Message::Text(text)) => {
+                let res = serde_json::from_str::<Command>(&text)
+                    .map_err(|e| format!("invalid command: {}", e))
+                    .and_then(|cmd| hub.command(cmd).map_err(|e| e.to_string()));
+                if let Err(e) = res {
+                    ws.send(Message::Text(error(e))).map_err(ws_err)?;
+                }
+            }
+            Ok(Message::Close(_)) | Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Ok(_) => (),
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+            Err(e) => return Err(ws_err(e)),
+        }
+
+        for msg in rx.try_iter() {
+            ws.send(Message::Text(msg)).map_err(ws_err)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyscope_traits::{ConfigAction, PinId};
+
+    fn config(pins: &[&str]) -> DeviceConfig {
+        let pins: Vec<_> = pins.iter().map(|p| (device::pin(p).unwrap(), None)).collect();
+        DeviceConfig::new(pins, Some(1000), 12, Encoding::Raw)
+    }
+
+    #[test]
+    fn refused_pin_keeps_channels() {
+        let mut setup = Setup::default();
+        setup.follow(&config(&["A4"]));
+        let add = Command::Config(ConfigAction::AnalogPins(PinId::new(0, 7)));
+        assert_eq!(setup.outgoing(add).unwrap(), [add, Command::GetConfig]);
+        // the device answers `Err(InvalidPin)` and then the old config
+        setup.follow(&config(&["A4"]));
+        let pins: Vec<_> = setup.channels.iter().map(AnalogChannel::pins).collect();
+        assert_eq!(pins, [device::show_pin(device::pin("A4").unwrap())]);
+        assert_eq!(setup.rate, Some(1000));
+    }
+
+    #[test]
+    fn batch_asks_once() {
+        let mut setup = Setup::default();
+        let reset = Command::Config(ConfigAction::ResetPins);
+        let rate = Command::Config(ConfigAction::AnalogRate(500));
+        assert_eq!(setup.outgoing(Command::ConfigBatch(2)).unwrap(), [Command::ConfigBatch(2)]);
+        assert_eq!(setup.outgoing(reset).unwrap(), [reset]);
+        assert_eq!(setup.outgoing(rate).unwrap(), [rate, Command::GetConfig]);
+        assert!(setup.outgoing(Command::SetBaud(115_200)).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Interactive shell for bring-up, every line becomes one command
+//! and everything the device sends is printed as it arrives
+
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::thread;
+
+use ferrous_serialport::SerialPort;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::{Context, ExternalPrinter, Helper, Highlighter, Hinter, Validator};
+use rustyscope_traits::{Command, ConfigAction, Encoding, Reply, SampleKind};
+
+use crate::device::{self, Event};
+use crate::profile;
+use crate::error::Error;
+
+const HELP: &str = "\
+pin add <pin> [analog|digital]  sample a pin, analog if not given
+pin diff <pin> <minus>          sample the first pin against the
+                                second on one channel
+pin remove <pin>                stop sampling a pin, or the pair
+                                it is in
+pin move <pin> <position>       change where a pin is in the
+                                sample order, 0 is first
+pin reset                       stop sampling all pins
+rate <hz>                       samples per second
+encoding <name>                 how samples are packed, data is
+                                shown in bytes as sent
+burst [analog|digital]          sample as fast as possible once
+stream [analog|digital]         sample continuously
+stop                            stop continuous sampling or a burst
+info                            firmware version and device id
+status                          mode, last error and buffer fill
+config                          pins, rate and encoding in use
+ping                            check the device answers
+help                            this text
+quit                            leave the shell
+
+pins are given as 30 for P0.30, P0.30, AIN6 or the board name like A4";
+
+/// what a line asks for
+#[derive(Debug, PartialEq)]
+enum Input {
+    Send(Command),
+    Help,
+    Quit,
+    Nothing,
+}
+
+fn kind(word: Option<&str>) -> Result<SampleKind, String> {
+    match word {
+        None | Some("analog") => Ok(SampleKind::Analog),
+        Some("digital") => Ok(SampleKind::Digital),
+        Some(other) => Err(format!("expected analog or digital got: {}", other)),
+    }
+}
+
+fn parse(line: &str) -> Result<Input, String> {
+    let words: Vec<_> = line.split_whitespace().collect();
+    let num = |word: Option<&&str>| {
+        let word = word.ok_or("missing a number")?;
+        word.parse().map_err(|_| format!("not a number: {}", word))
+    };
+    let pin = |word: Option<&&str>| device::pin(word.ok_or("missing a pin")?);
+
+    let input = match words.as_slice() {
+        [] => Input::Nothing,
+        ["pin", "reset"] => Input::Send(Command::Config(ConfigAction::ResetPins)),
+        ["pin", "add", rest @ ..] if rest.len() <= 2 => {
+            let pin = pin(rest.first())?;
+            let action = match kind(rest.get(1).copied())? {
+                SampleKind::Analog => ConfigAction::AnalogPins(pin),
+                SampleKind::Digital => ConfigAction::DigitalPins(pin),
+            };
+            Input::Send(Command::Config(action))
+        }
+        ["pin", "diff", rest @ ..] if rest.len() <= 2 => {
+            let action = ConfigAction::AnalogDiffPair(pin(rest.first())?, pin(rest.get(1))?);
+            Input::Send(Command::Config(action))
+        }
+        ["pin", "remove", rest @ ..] if rest.len() <= 1 => {
+            Input::Send(Command::Config(ConfigAction::RemovePin(pin(rest.first())?)))
+        }
+        ["pin", "move", rest @ ..] if rest.len() <= 2 => {
+            let pin = pin(rest.first())?;
+            let to: u32 = num(rest.get(1))?;
+            let to = u8::try_from(to).unwrap_or(u8::MAX);
+            Input::Send(Command::Config(ConfigAction::MovePin { pin, to }))
+        }
+        ["rate", rest @ ..] if rest.len() <= 1 => {
+            Input::Send(Command::Config(ConfigAction::AnalogRate(num(rest.first())?)))
+        }
+        ["encoding", name] => Input::Send(Command::Config(ConfigAction::Encoding(profile::encoding(name)?))),
+        ["burst", rest @ ..] if rest.len() <= 1 => Input::Send(Command::Burst(kind(rest.first().copied())?)),
+        ["stream", rest @ ..] if rest.len() <= 1 => Input::Send(Command::Continues(kind(rest.first().copied())?)),
+        ["stop"] => Input::Send(Command::Stop),
+        ["info"] => Input::Send(Command::Info),
+        ["ping"] => Input::Send(Command::Ping),
+        ["status"] => Input::Send(Command::GetStatus),
+        ["config"] => Input::Send(Command::GetConfig),
+        ["help"] => Input::Help,
+        ["quit"] | ["exit"] => Input::Quit,
+        _ => return Err(format!("unknown command: {}, try help", line.trim())),
+    };
+    Ok(input)
+}
+
+fn show(event: &Event) -> String {
+    match event {
+        Event::Data(bytes) => format!("< data {} bytes", bytes.len()),
+        Event::Stream(h, bytes) => format!("< stream #{} from sample {}, {} bytes", h.seq, h.index, bytes.len()),
+        Event::Reply(Reply::Overrun { lost }) => format!("< overrun, {} samples dropped", lost),
+        Event::Timing(t) => format!("< timing: {} samples, {} late, {} to {} us apart",
+            t.samples, t.missed, t.min_interval, t.max_interval),
+        Event::Reply(Reply::Done(micros)) => format!("< done after {} us", micros),
+        Event::Reply(Reply::Err(e)) => format!("< {:?}: {}", e, device::describe(e)),
+        Event::Reply(Reply::Info(v)) => format!("< firmware {}.{}.{}", v.major, v.minor, v.patch),
+        Event::Reply(Reply::Id(id)) => format!("< device id {:08X}", id),
+        Event::Reply(Reply::BatchFailed(i)) => format!("< batch refused at action {}, nothing changed", i),
+        Event::Reply(Reply::Rate(hz)) => format!("< sampling at {} Hz", hz),
+        Event::Status(s) => format!("< {}", device::show_status(s).trim_end().replace('\n', "\n< ")),
+        Event::Config(c) => format!("< {}", device::show_config(c).trim_end().replace('\n', "\n< ")),
+        Event::Reply(reply) => format!("< {:?}", reply),
+    }
+}
+
+/// completes the word under the cursor from the words that can
+/// follow the ones before it
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct Completion;
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(' ').map(|i| i + 1).unwrap_or(0);
+        let before: Vec<_> = line[..start].split_whitespace().collect();
+        let pins: Vec<String>;
+        let names: Vec<String>;
+        let options: &[&str] = match before.as_slice() {
+            [] => &["pin", "rate", "encoding", "burst", "stream", "stop", "info", "ping", "status", "config", "help", "quit"],
+            ["pin"] => &["add", "diff", "remove", "move", "reset"],
+            ["pin", "add" | "diff" | "remove" | "move"] | ["pin", "diff", _] => {
+                pins = device::ABILITIES.adc_pins.iter()
+                    .map(|p| if p.name.is_empty() { p.to_string() } else { p.name.to_owned() })
+                    .collect();
+                return Ok((start, pins.into_iter().filter(|p| p.starts_with(&line[start..])).collect()));
+            }
+            ["pin", "add", _] | ["burst"] | ["stream"] => &["analog", "digital"],
+            ["encoding"] => {
+                names = Encoding::ALL.iter().map(|e| e.name().to_owned()).collect();
+                return Ok((start, names.into_iter().filter(|n| n.starts_with(&line[start..])).collect()));
+            }
+            _ => &[],
+        };
+        let word = &line[start..];
+        let matches = options.iter()
+            .filter(|o| o.starts_with(word))
+            .map(|o| o.to_string())
+            .collect();
+        Ok((start, matches))
+    }
+}
+
+fn history() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rustyscope_history"))
+}
+
+pub fn run(mut serial: Box<dyn SerialPort>) -> Result<(), Error> {
+    let readline_err = |e: ReadlineError| Error::Io(e.to_string());
+    let mut editor = rustyline::Editor::new().map_err(readline_err)?;
+    editor.set_helper(Some(Completion));
+    if let Some(path) = history() {
+        // there is no history the first time
+        let _ = editor.load_history(&path);
+    }
+
+    // printing above the prompt only works on a terminal
+    let mut printer = editor.create_external_printer().ok();
+    let mut read_port = serial.try_clone()?;
+    thread::spawn(move || {
+        let mut print = |line: String| match printer.as_mut() {
+            Some(printer) => printer.print(line).is_ok(),
+            None => {
+                println!("{}", line);
+                true
+            }
+        };
+        loop {
+            match device::next_event(read_port.as_mut()) {
+                Ok(event) => if !print(show(&event)) {
+                    break;
+                },
+                Err(e) => {
+                    print(format!("< could not read: {}", e));
+                    break;
+                }
+            }
+        }
+    });
+
+    println!("type help for the commands");
+    loop {
+        let line = match editor.readline("scope> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => return Err(readline_err(e)),
+        };
+        let _ = editor.add_history_entry(line.as_str());
+        match parse(&line) {
+            Ok(Input::Send(cmd)) => {
+                println!("> {:?}", cmd);
+                device::send(serial.as_mut(), cmd)?;
+            }
+            Ok(Input::Help) => println!("{}", HELP),
+            Ok(Input::Quit) => break,
+            Ok(Input::Nothing) => (),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    if let Some(path) = history() {
+        editor.save_history(&path).map_err(readline_err)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use crate::capture::Trace;
+use crate::spectrum::{self, Window};
+
+/// short time fourier transform of a trace
+#[derive(Debug, Clone)]
+pub struct Spectrogram {
+    pub name: String,
+    /// center of every segment in seconds
+    pub times: Vec<f32>,
+    pub freqs: Vec<f32>,
+    /// rms amplitude indexed as `[freq][time]`
+    pub vrms: Vec<Vec<f32>>,
+}
+
+/// split the trace into segments of `size` samples that overlap by
+/// a fraction `overlap` and take the spectrum of each
+pub fn spectrogram(trace: &Trace, window: Window, size: usize, overlap: f32) -> Spectrogram {
+    let size = size.max(2).min(trace.values.len().max(2));
+    let hop = (size - (size as f32 * overlap.clamp(0.0, 0.99)) as usize).max(1);
+
+    let mut times = Vec::new();
+    let mut columns = Vec::new();
+    let mut start = 0;
+    while start + size <= trace.values.len() {
+        let segment = Trace {
+            name: trace.name.clone(),
+            pair: trace.pair.clone(),
+            t0: trace.t0 + start as f32 * trace.dt,
+            dt: trace.dt,
+            values: trace.values[start..start + size].to_vec(),
+        };
+        times.push(segment.t0 + size as f32 * trace.dt / 2.0);
+        columns.push(spectrum::spectrum(&segment, window));
+        start += hop;
+    }
+
+    let freqs = columns.first().map(|c| c.freq()).unwrap_or_default();
+    let vrms = (0..freqs.len())
+        .map(|f| columns.iter().map(|c| c.vrms[f]).collect())
+        .collect();
+
+    Spectrogram {
+        name: trace.name.clone(),
+        times,
+        freqs,
+        vrms,
+    }
+}
@@ -0,0 +1,159 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+
+use crate::capture::Trace;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    /// accurate amplitudes at the cost of frequency resolution
+    FlatTop,
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "rectangular" | "none" => Window::Rectangular,
+            "hann" => Window::Hann,
+            "hamming" => Window::Hamming,
+            "blackman" => Window::Blackman,
+            "flat-top" | "flattop" => Window::FlatTop,
+            _ => return Err(format!("unknown window: {}, options: \
+                rectangular, hann, hamming, blackman, flat-top", s)),
+        })
+    }
+}
+
+impl Window {
+    pub fn coefficients(self, len: usize) -> Vec<f32> {
+        let n = (len.max(2) - 1) as f32;
+        let cos = |i: usize, k: f32| (2.0*PI*k*(i as f32)/n).cos();
+        (0..len)
+            .map(|i| match self {
+                Window::Rectangular => 1.0,
+                Window::Hann => 0.5 - 0.5*cos(i, 1.),
+                Window::Hamming => 0.54 - 0.46*cos(i, 1.),
+                Window::Blackman => 0.42 - 0.5*cos(i, 1.) + 0.08*cos(i, 2.),
+                Window::FlatTop => 0.215_578_95
+                    - 0.416_631_58*cos(i, 1.)
+                    + 0.277_263_16*cos(i, 2.)
+                    - 0.083_578_95*cos(i, 3.)
+                    + 0.006_947_37*cos(i, 4.),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scale {
+    /// dB relative to 1 Vrms
+    Dbv,
+    /// Vrms
+    Linear,
+}
+
+impl FromStr for Scale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dbv" => Ok(Scale::Dbv),
+            "linear" => Ok(Scale::Linear),
+            _ => Err(format!("unknown scale: {}, options: dbv, linear", s)),
+        }
+    }
+}
+
+impl Scale {
+    pub fn apply(self, vrms: f32) -> f32 {
+        match self {
+            Scale::Dbv => 20.0*vrms.max(1e-12).log10(),
+            Scale::Linear => vrms,
+        }
+    }
+
+    pub fn unit(self) -> &'static str {
+        match self {
+            Scale::Dbv => "dBV",
+            Scale::Linear => "Vrms",
+        }
+    }
+}
+
+/// single sided amplitude spectrum of a trace
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    pub name: String,
+    /// bin spacing in Hz
+    pub df: f32,
+    /// rms amplitude per bin in volts
+    pub vrms: Vec<f32>,
+}
+
+impl Spectrum {
+    pub fn freq(&self) -> Vec<f32> {
+        (0..self.vrms.len()).map(|i| i as f32 * self.df).collect()
+    }
+
+    /// the `n` highest local maxima, skipping dc, as (frequency, vrms)
+    pub fn peaks(&self, n: usize) -> Vec<(f32, f32)> {
+        let mut peaks: Vec<_> = self.vrms.windows(3)
+            .enumerate()
+            .filter(|(_, w)| w[1] > w[0] && w[1] >= w[2])
+            .map(|(i, w)| ((i + 1) as f32 * self.df, w[1]))
+            .collect();
+        peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+        peaks.truncate(n);
+        peaks
+    }
+}
+
+pub fn spectrum(trace: &Trace, window: Window) -> Spectrum {
+    let len = trace.values.len();
+    let coefficients = window.coefficients(len);
+    // correct for the amplitude the window removes
+    let gain: f32 = coefficients.iter().sum::<f32>().max(f32::EPSILON);
+
+    let mut buffer: Vec<_> = trace.values.iter()
+        .zip(&coefficients)
+        .map(|(v, w)| Complex::new(v*w, 0.0))
+        .collect();
+    FftPlanner::new().plan_fft_forward(len).process(&mut buffer);
+
+    let vrms = buffer.iter()
+        .take(len/2 + 1)
+        .enumerate()
+        .map(|(i, c)| {
+            let amplitude = c.norm() / gain;
+            if i == 0 { amplitude } else { 2.0*amplitude / 2f32.sqrt() }
+        })
+        .collect();
+
+    Spectrum {
+        name: trace.name.clone(),
+        df: 1.0 / (trace.dt * len.max(1) as f32),
+        vrms,
+    }
+}
+
+/// power average of the spectra of repeated captures of the same
+/// channel, averaging lowers the noise floor but keeps the peaks
+pub fn average(spectra: &[Spectrum]) -> Option<Spectrum> {
+    let first = spectra.first()?;
+    let len = spectra.iter().map(|s| s.vrms.len()).min()?;
+    let vrms = (0..len)
+        .map(|i| {
+            let power: f32 = spectra.iter().map(|s| s.vrms[i].powi(2)).sum();
+            (power / spectra.len() as f32).sqrt()
+        })
+        .collect();
+    Some(Spectrum { name: first.name.clone(), df: first.df, vrms })
+}
@@ -0,0 +1,276 @@
+//! What is wired where on the board. The uart, the adc pins and the
+//! abilities are all generated from the `board!` at the bottom, pin
+//! numbers are read from the embassy and saadc names so each is
+//! written once, and a pin that ends up listed twice fails the build
+
+use rustyscope_traits::{ConfigErr, PinId};
+use embedded_hal::adc::OneShot;
+use crate::hal::gpio;
+use crate::hal::pac::saadc::ch::{pseln, pselp};
+use crate::hal::pac::SAADC;
+use crate::hal::saadc::Saadc;
+
+/// the number a pin or saadc input name ends in, 28 for `P0_28`
+/// and 4 for `analog_input4`
+const fn number(name: &str) -> u8 {
+    let bytes = name.as_bytes();
+    let mut start = bytes.len();
+    while start > 0 && bytes[start - 1].is_ascii_digit() {
+        start -= 1;
+    }
+    assert!(start < bytes.len(), "a board pin name has to end in its number");
+    let mut n = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        n = n * 10 + (bytes[i] - b'0');
+        i += 1;
+    }
+    n
+}
+
+/// whether no pin is in `pins` twice
+const fn unique(pins: &[PinId]) -> bool {
+    let mut i = 0;
+    while i < pins.len() {
+        let mut j = i + 1;
+        while j < pins.len() {
+            if pins[i].same(&pins[j]) {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// whether `pins` are `shared` in the same order, with the same
+/// analog inputs and names
+pub const fn described(pins: &[PinId], shared: &[PinId]) -> bool {
+    if pins.len() != shared.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < pins.len() {
+        let (a, b) = (&pins[i], &shared[i]);
+        let ain = match (a.ain, b.ain) {
+            (Some(x), Some(y)) => x == y,
+            (None, None) => true,
+            _ => false,
+        };
+        if !a.same(b) || !ain || !same_str(a.name, b.name) {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// `==` on str for const contexts
+const fn same_str(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// what one sampled channel measures
+pub enum AdcChannel {
+    Single(AdcPin),
+    /// the first pin minus the second, readings are signed
+    Diff(AdcPin, AdcPin),
+}
+
+impl AdcChannel {
+    /// the pin, the positive one of a pair
+    pub fn pin(&self) -> PinId {
+        match self {
+            AdcChannel::Single(p) | AdcChannel::Diff(p, _) => p.pin(),
+        }
+    }
+
+    /// the negative pin of a pair
+    pub fn minus(&self) -> Option<PinId> {
+        match self {
+            AdcChannel::Single(_) => None,
+            AdcChannel::Diff(_, n) => Some(n.pin()),
+        }
+    }
+
+    /// whether `pin` is measured by this channel
+    pub fn has(&self, pin: PinId) -> bool {
+        self.pin() == pin || self.minus() == Some(pin)
+    }
+
+    pub fn is_diff(&self) -> bool {
+        matches!(self, AdcChannel::Diff(..))
+    }
+
+    pub fn input<'w>(&self, w: &'w mut pselp::W) -> &'w mut pselp::W {
+        match self {
+            AdcChannel::Single(p) | AdcChannel::Diff(p, _) => p.input(w),
+        }
+    }
+
+    /// not connected for a single ended channel
+    pub fn input_n<'w>(&self, w: &'w mut pseln::W) -> &'w mut pseln::W {
+        match self {
+            AdcChannel::Single(_) => w.pseln().nc(),
+            AdcChannel::Diff(_, n) => n.input_n(w),
+        }
+    }
+
+    /// a single sample through the oneshot driver. It only sets
+    /// pselp of channel 0, a pair switches the rest around the read
+    pub fn read(&mut self, adc: &mut Saadc) -> i16 {
+        match self {
+            AdcChannel::Single(p) => p.read(adc),
+            AdcChannel::Diff(p, n) => {
+                let ch = unsafe { &(*SAADC::ptr()).ch[0] };
+                ch.pseln.write(|w| n.input_n(w));
+                ch.config.modify(|_, w| w.mode().diff());
+                let val = p.read(adc);
+                ch.config.modify(|_, w| w.mode().se());
+                ch.pseln.write(|w| w.pseln().nc());
+                val
+            }
+        }
+    }
+}
+
+macro_rules! board {
+    (
+        uart: {
+            rxd: $rxd:ident,
+            txd: $txd:ident,
+            cts: $cts:ident,
+            rts: $rts:ident $(,)?
+        },
+        leds: [$($led:literal),* $(,)?],
+        reserved: [$($res:literal),* $(,)?],
+        adc: [$($adc:ident $field:ident => $ain:ident $name:literal),* $(,)?],
+    ) => {
+        pub type Rxd = embassy_nrf::peripherals::$rxd;
+        pub type Txd = embassy_nrf::peripherals::$txd;
+        pub type Cts = embassy_nrf::peripherals::$cts;
+        pub type Rts = embassy_nrf::peripherals::$rts;
+
+        /// take the uart and its pins, nothing else is used from
+        /// the embassy peripherals
+        #[allow(non_snake_case)]
+        pub fn uart(p: embassy_nrf::Peripherals) -> (embassy_nrf::peripherals::UARTE0, Rxd, Txd, Cts, Rts) {
+            let embassy_nrf::Peripherals { UARTE0, $rxd, $txd, $cts, $rts, .. } = p;
+            (UARTE0, $rxd, $txd, $cts, $rts)
+        }
+
+        #[allow(dead_code)]
+        pub const LEDS: &[PinId] = &[$(PinId::new(0, $led)),*];
+        /// pins the board needs for something else, never sampled
+        pub const RESERVED: &[PinId] = &[
+            PinId::new(0, number(stringify!($rxd))), PinId::new(0, number(stringify!($txd))),
+            PinId::new(0, number(stringify!($cts))), PinId::new(0, number(stringify!($rts))),
+            $(PinId::new(0, $led),)* $(PinId::new(0, $res)),*
+        ];
+        /// pins that can be sampled analog
+        pub const ADC_PINS: &[PinId] = &[
+            $(PinId::new(0, number(stringify!($adc))).analog(number(stringify!($ain))).named($name)),*
+        ];
+
+        const ALL: &[PinId] = &[
+            PinId::new(0, number(stringify!($rxd))), PinId::new(0, number(stringify!($txd))),
+            PinId::new(0, number(stringify!($cts))), PinId::new(0, number(stringify!($rts))),
+            $(PinId::new(0, $led),)* $(PinId::new(0, $res),)* $(PinId::new(0, number(stringify!($adc)))),*
+        ];
+        const _: () = assert!(unique(ALL), "a pin is listed twice in the board description");
+
+        pub enum AdcPin {
+            $($adc(gpio::p0::$adc<gpio::Disconnected>)),*
+        }
+
+        impl AdcPin {
+            pub fn pin(&self) -> PinId {
+                match self {
+                    $(AdcPin::$adc(_) => PinId::new(0, number(stringify!($adc))).analog(number(stringify!($ain))).named($name)),*
+                }
+            }
+
+            /// route this pin to a saadc channel
+            pub fn input<'w>(&self, w: &'w mut pselp::W) -> &'w mut pselp::W {
+                match self {
+                    $(AdcPin::$adc(_) => w.pselp().$ain()),*
+                }
+            }
+
+            /// route this pin to the negative input of a channel
+            pub fn input_n<'w>(&self, w: &'w mut pseln::W) -> &'w mut pseln::W {
+                match self {
+                    $(AdcPin::$adc(_) => w.pseln().$ain()),*
+                }
+            }
+
+            /// a single sample through the oneshot driver
+            pub fn read(&mut self, adc: &mut Saadc) -> i16 {
+                match self {
+                    $(AdcPin::$adc(p) => adc.read(p).unwrap()),*
+                }
+            }
+        }
+
+        /// the adc pins that are not sampled
+        pub struct AdcPins {
+            $($field: Option<gpio::p0::$adc<gpio::Disconnected>>),*
+        }
+
+        impl AdcPins {
+            pub fn new(gpios: gpio::p0::Parts) -> Self {
+                Self {
+                    $($field: Some(gpios.$field)),*
+                }
+            }
+
+            pub fn take(&mut self, pin: PinId) -> Result<AdcPin, ConfigErr> {
+                $(if (pin.port, pin.pin) == (0, number(stringify!($adc))) {
+                    return self.$field.take().map(AdcPin::$adc).ok_or(ConfigErr::PinTaken(pin));
+                })*
+                if RESERVED.contains(&pin) {
+                    Err(ConfigErr::PinReserved(pin))
+                } else {
+                    Err(ConfigErr::InvalidPin(pin))
+                }
+            }
+
+            pub fn give_back(&mut self, p: AdcPin) {
+                match p {
+                    $(AdcPin::$adc(p) => self.$field = Some(p)),*
+                }
+            }
+        }
+    };
+}
+
+// nRF52 DK (PCA10040), the uart goes to the debuggers usb serial
+board! {
+    uart: { rxd: P0_08, txd: P0_06, cts: P0_05, rts: P0_07 },
+    leds: [17, 18, 19, 20],
+    // 32.768 kHz crystal and reset
+    reserved: [0, 1, 21],
+    // with the names of the arduino header
+    adc: [
+        P0_02 p0_02 => analog_input0 "AREF",
+        P0_03 p0_03 => analog_input1 "A0",
+        P0_04 p0_04 => analog_input2 "A1",
+        // P0_05 is AIN3 but the uart cts
+        P0_28 p0_28 => analog_input4 "A2",
+        P0_29 p0_29 => analog_input5 "A3",
+        P0_30 p0_30 => analog_input6 "A4",
+        P0_31 p0_31 => analog_input7 "A5",
+    ],
+}
@@ -1,28 +1,44 @@
-use embassy_nrf::peripherals::{P0_05, P0_06, P0_07, P0_08};
 use embassy_nrf::peripherals::UARTE0;
 use embassy_nrf::{uarte, interrupt};
 use embassy_nrf::uarte::Uarte;
 use embassy::traits::uart::{Read, Write};
-use rustyscope_traits::{Command, Reply};
+use embassy::time::{Duration, Timer};
+use futures::future::{select, Either};
+use futures::pin_mut;
+use arrayvec::ArrayVec;
+use rustyscope_traits::{Action, Batch, Command, ConfigErr, DeviceConfig, Encoding, Input, Reply, Status, StreamHeader, Timing, BAUD_CONFIRM, DEFAULT_BAUD, STREAM_PACKET};
 use core::pin::Pin;
 use core::ops::DerefMut;
 use core::convert::TryFrom;
 
 use crate::Mode;
+use crate::board;
+use crate::description;
 use crate::mutex::Mutex;
 use crate::config::Config;
-use crate::sampling::Channel;
+use crate::sampling::{self, Channel};
+use core::sync::atomic::{compiler_fence, AtomicU32, Ordering};
+use futures_lite::future::yield_now;
 
 pub struct Serial<'a,'d>(pub Mutex<Pin<&'a mut Uarte<'d, UARTE0>>>);
 
+/// the baud rate the uart runs at, streaming is limited by it
+static BAUD: AtomicU32 = AtomicU32::new(DEFAULT_BAUD);
+
+/// milliseconds a command read keeps the uart from sending at most
+const READ_SLICE: u64 = 5;
+/// milliseconds without a new sample after which `send_data` sends
+/// the samples it holds, so the end of a stream is not lost
+const TAIL: u64 = 20;
+
 
 impl<'a,'d> Serial<'a,'d> {
     pub fn setup_uart(
         uart: UARTE0,
-        rxd: P0_08,
-        txd: P0_06,
-        cts: P0_05,
-        rts: P0_07,
+        rxd: board::Rxd,
+        txd: board::Txd,
+        cts: board::Cts,
+        rts: board::Rts,
     ) -> Uarte<'d, UARTE0> {
         let mut config = uarte::Config::default();
         config.parity = uarte::Parity::EXCLUDED;
@@ -36,20 +52,116 @@ impl<'a,'d> Serial<'a,'d> {
         }
     }
 
+    /// value of the BAUDRATE register for a baud rate from
+    /// the abilities
+    fn baud_register(rate: u32) -> Option<u32> {
+        Some(match rate {
+            9600 => 0x0027_5000,
+            19200 => 0x004E_A000,
+            38400 => 0x009D_5000,
+            57600 => 0x00EB_F000,
+            115200 => 0x01D7_E000,
+            230400 => 0x03AF_B000,
+            460800 => 0x075F_7000,
+            921600 => 0x0EBE_D000,
+            1000000 => 0x1000_0000,
+            _ => return None,
+        })
+    }
+
+    /// the uarte driver has no way to change the baud rate
+    /// once set up, so write the register directly
+    pub fn set_baud(&self, rate: u32) -> Result<(), ConfigErr> {
+        let value = Self::baud_register(rate).ok_or(ConfigErr::InvalidBaud(rate))?;
+        // safe: only BAUDRATE is touched which the driver
+        // does not read back
+        unsafe {
+            let uarte = &*crate::hal::pac::UARTE0::ptr();
+            uarte.baudrate.write(|w| w.bits(value));
+        }
+        BAUD.store(rate, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn baud(&self) -> u32 {
+        BAUD.load(Ordering::Relaxed)
+    }
+
     pub fn from_pinned_uart(uart: Pin<&'a mut Uarte<'d, UARTE0>>) -> Self {
         Self(Mutex::new(uart, true))
     }
 
-    // TODO this blocks until a new command is recieved
-    // which keeps the mutex locked blocking sending
-    // need a serial that has seperate objects for 
-    // reading and writing
+    /// wait for a command without keeping the uart from sending, the
+    /// read is given up every `READ_SLICE` to let replies and stream
+    /// packets out and the bytes it got so far are kept
     pub async fn read_command(&self) -> Command {
+        let mut buf = [0u8; Command::SIZE];
+        let mut got = 0;
+        while got < buf.len() {
+            {
+                let mut m = self.0.lock().await;
+                let serial = m.deref_mut();
+                let done = {
+                    let read = serial.read(&mut buf[got..]);
+                    let timeout = Timer::after(Duration::from_millis(READ_SLICE));
+                    pin_mut!(read, timeout);
+                    match select(read, timeout).await {
+                        Either::Left((result, _)) => {
+                            result.unwrap();
+                            true
+                        }
+                        // dropping the read stops rx
+                        Either::Right(_) => false,
+                    }
+                };
+                if done {
+                    got = buf.len();
+                } else {
+                    got += Self::received();
+                    got += Self::flush_rx(&mut buf[got..]);
+                }
+            }
+            yield_now().await;
+        }
+        Command::try_from(&buf).unwrap()
+    }
+
+    /// bytes the last rx transfer wrote, also when it was stopped early
+    fn received() -> usize {
+        // safe: only reads a register of a transfer that has ended
+        unsafe { (*crate::hal::pac::UARTE0::ptr()).rxd.amount.read().bits() as usize }
+    }
+
+    /// up to four bytes can arrive after rx is stopped, they wait in
+    /// the rx fifo and would be lost on the next start
+    fn flush_rx(buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        // safe: rx is stopped so the driver does not use the rx
+        // registers, `buf` outlives the transfer as we wait for it
+        unsafe {
+            let uarte = &*crate::hal::pac::UARTE0::ptr();
+            uarte.rxd.ptr.write(|w| w.ptr().bits(buf.as_mut_ptr() as u32));
+            uarte.rxd.maxcnt.write(|w| w.maxcnt().bits(buf.len() as _));
+            uarte.events_endrx.reset();
+            compiler_fence(Ordering::SeqCst);
+            uarte.tasks_flushrx.write(|w| w.bits(1));
+            // ends right away with nothing copied if the fifo was empty
+            while uarte.events_endrx.read().bits() == 0 {}
+            compiler_fence(Ordering::SeqCst);
+            uarte.rxd.amount.read().bits() as usize
+        }
+    }
+
+    /// like `read_command` but garbage, for example
+    /// from a host at a different baud rate, is not fatal
+    pub async fn try_read_command(&self) -> Option<Command> {
         let mut m = self.0.lock().await;
         let serial = m.deref_mut();
         let mut buf = [0u8; Command::SIZE];
-        serial.read(&mut buf).await.unwrap();
-        Command::try_from(&buf).unwrap()
+        serial.read(&mut buf).await.ok()?;
+        Command::try_from(&buf).ok()
     }
 
     pub async fn send_reply(&self, reply: Reply) {
@@ -59,64 +171,229 @@ impl<'a,'d> Serial<'a,'d> {
         serial.write(&buf).await.unwrap();
     }
 
-    pub async fn send_data(&self, data: [i16;8]) {
+    /// send the samples in as many `Reply::Data` as needed, each
+    /// payload fits one EasyDMA transfer
+    async fn write_samples(serial: &mut Pin<&'a mut Uarte<'d, UARTE0>>, data: &[i16], encoding: Encoding) {
+        let mut buf = [0u8; u8::MAX as usize];
+        let mut rest = data;
+        while !rest.is_empty() {
+            let (used, len) = encoding.encode(rest, &mut buf);
+            let header = Reply::Data(len as u32).serialize();
+            serial.write(&header).await.unwrap();
+            serial.write(&buf[..len]).await.unwrap();
+            rest = &rest[used..];
+        }
+    }
+
+    /// one `Reply::Stream` packet, the samples have to fit
+    /// a single payload
+    pub async fn send_stream(&self, header: StreamHeader, data: &[i16], encoding: Encoding) {
         let mut m = self.0.lock().await;
         let serial = m.deref_mut();
 
-        let buffer = bytemuck::cast_slice(&data);
-        let data = Reply::Data(data.len() as u32).serialize();
-        serial.write(&data).await.unwrap();
-        serial.write(&buffer).await.unwrap();
+        let mut buf = [0u8; u8::MAX as usize];
+        buf[..StreamHeader::SIZE].copy_from_slice(&header.serialize());
+        let (used, len) = encoding.encode(data, &mut buf[StreamHeader::SIZE..]);
+        debug_assert_eq!(used, data.len());
+        let len = StreamHeader::SIZE + len;
+        let reply = Reply::Stream(len as u32).serialize();
+        serial.write(&reply).await.unwrap();
+        serial.write(&buf[..len]).await.unwrap();
+    }
+
+    /// a reply followed by its payload
+    async fn send_payload(&self, reply: Reply, payload: &[u8]) {
+        let mut m = self.0.lock().await;
+        let serial = m.deref_mut();
+        serial.write(&reply.serialize()).await.unwrap();
+        serial.write(payload).await.unwrap();
     }
 
-    pub async fn send_burst_data(&self, data: &[i16], duration: u64) {
+    pub async fn send_burst_data(&self, data: &[i16], duration: u64, timing: Timing, encoding: Encoding) {
         let mut m = self.0.lock().await;
         let serial = m.deref_mut();
 
-        let data = bytemuck::cast_slice(data);
-        for chunk in data.chunks(u8::MAX.into()) {
-            let data = Reply::Data(chunk.len() as u32).serialize();
-            serial.write(&data).await.unwrap();
-            serial.write(&chunk).await.unwrap();
-        }
+        Self::write_samples(serial, data, encoding).await;
+        let reply = Reply::Timing(Timing::SIZE as u32).serialize();
+        serial.write(&reply).await.unwrap();
+        serial.write(&timing.serialize()).await.unwrap();
         let done = Reply::Done(duration as u32).serialize();
         serial.write(&done).await.unwrap();
     }
 }
 
+/// switch to a new baud rate, the host has to confirm it can
+/// reach us there within `BAUD_CONFIRM` or we go back to the
+/// default rate
+async fn change_baud(serial: &Serial<'_, '_>, rate: u32) {
+    if Serial::baud_register(rate).is_none() {
+        serial.send_reply(Reply::Err(ConfigErr::InvalidBaud(rate))).await;
+        return;
+    }
+    // acknowledged at the old rate
+    serial.send_reply(Reply::Ok).await;
+    let _ = serial.set_baud(rate);
+
+    let ping = serial.try_read_command();
+    let timeout = Timer::after(Duration::from_millis(BAUD_CONFIRM));
+    pin_mut!(ping, timeout);
+    match select(ping, timeout).await {
+        Either::Left((Some(Command::Ping), _)) => serial.send_reply(Reply::Pong).await,
+        _ => {
+            defmt::warn!("baud rate {} not confirmed, going back to default", rate);
+            let _ = serial.set_baud(DEFAULT_BAUD);
+        }
+    }
+}
+
+/// lower half of the factory programmed 64 bit device id
+fn device_id() -> u32 {
+    // safe: FICR is read only and never taken as a peripheral
+    unsafe { (*crate::hal::pac::FICR::ptr()).deviceid[0].read().bits() }
+}
+
 pub async fn handle_commands<'a, 'd>(serial: &Serial<'a, 'd>, mode: &Mutex<Mode>, config: &Config) {
+    let mut last_error = None;
     loop {
         let command = serial.read_command().await;
         defmt::info!("got command: {}", command);
+        // the frames of a batch follow even if it gets refused
+        let mut batch = None;
+        if let Command::ConfigBatch(len) = command {
+            let mut frames = Batch::new(len);
+            while frames.remaining() > 0 {
+                frames.push(serial.read_command().await);
+            }
+            batch = Some(frames);
+        }
+
+        let (current, action) = {
+            let mut m = mode.lock().await;
+            let mode = m.deref_mut();
+            let (next, action) = mode.next(Input::Command(command));
+            *mode = next;
+            (next, action)
+        };
 
-        let new_mode = match command {
-            Command::Stop => Some(Mode::Idle),
-            Command::Continues(s) => Some(Mode::Continues(s)),
-            Command::Burst(s) => Some(Mode::Burst(s)),
-            Command::Config(change) => match config.apply(change).await {
-                Result::Ok(_) => None, //*new_mode,
+        match action {
+            Action::Nothing => (),
+            Action::Reply(reply) => serial.send_reply(reply).await,
+            Action::ApplyConfig(change) => match config.apply(change).await {
+                Result::Ok(reply) => serial.send_reply(reply).await,
                 Result::Err(e) => {
-                    let reply = Reply::Err(e);
-                    serial.send_reply(reply).await;
-                    Some(Mode::Err(e))
+                    serial.send_reply(Reply::Err(e)).await;
+                    last_error = Some(e);
                 }
             },
-        };
-
-        if let Some(new) = new_mode {
-            let mut m = mode.lock().await;
-            let mode = m.deref_mut();
-            *mode = new;
+            Action::ApplyBatch => {
+                let mut replies = ArrayVec::new();
+                let result = match batch.as_ref().map(Batch::actions) {
+                    Some(Ok(actions)) => config.apply_batch(actions, &mut replies).await,
+                    Some(Err(refused)) => Err(refused),
+                    None => Err((0, ConfigErr::CommunicationProblem)),
+                };
+                match result {
+                    Result::Ok(()) => {
+                        for reply in replies {
+                            serial.send_reply(reply).await;
+                        }
+                    }
+                    Result::Err((index, e)) => {
+                        serial.send_reply(Reply::BatchFailed(index)).await;
+                        serial.send_reply(Reply::Err(e)).await;
+                        last_error = Some(e);
+                    }
+                }
+            }
+            Action::SendInfo => {
+                serial.send_reply(Reply::Info(description::VERSION)).await;
+                serial.send_reply(Reply::Id(device_id())).await;
+            }
+            Action::ChangeBaud(rate) => change_baud(serial, rate).await,
+            Action::SendStatus => {
+                let status = Status {
+                    mode: current,
+                    last_error,
+                    uptime_ms: embassy::time::Instant::now().as_millis(),
+                    buffered: sampling::BUFFERED.load(Ordering::Relaxed) as u16,
+                    capacity: sampling::CHANNEL_SIZE as u16,
+                };
+                let mut buf = [0u8; Status::MAX_SIZE];
+                let payload = status.serialize(&mut buf);
+                serial.send_payload(Reply::Status(payload.len() as u32), payload).await;
+            }
+            Action::SendConfig => {
+                let described = config.0.lock().await.describe();
+                let mut buf = [0u8; DeviceConfig::MAX_SIZE];
+                let payload = described.serialize(&mut buf);
+                serial.send_payload(Reply::Config(payload.len() as u32), payload).await;
+            }
         }
     }
 }
 
-pub async fn send_data<'d,'a>(serial: &Serial<'d,'a>, channel: &Channel) {
-    let mut data = [0i16;8];
+/// send continues samples in packets of up to `STREAM_PACKET`, a packet ends
+/// early where samples were dropped and the device reports an
+/// overrun before the next one
+pub async fn send_data<'d,'a>(serial: &Serial<'d,'a>, channel: &Channel, config: &Config) {
+    let mut data = [0i16; STREAM_PACKET];
+    let mut len = 0;
+    let mut header = StreamHeader { seq: 0, index: 0 };
+    // index of the sample we expect next
+    let mut next = 0u32;
     loop {
-        for i in &mut data {
-            *i = channel.receive().await.unwrap();
+        let (index, value) = if len == 0 {
+            channel.receive().await.unwrap()
+        } else {
+            let receive = channel.receive();
+            let timeout = Timer::after(Duration::from_millis(TAIL));
+            pin_mut!(receive, timeout);
+            match select(receive, timeout).await {
+                Either::Left((sample, _)) => sample.unwrap(),
+                // sampling stopped or is slow, send what we hold
+                Either::Right(_) => {
+                    let encoding = config.0.lock().await.encoding;
+                    serial.send_stream(header, &data[..len], encoding).await;
+                    header.seq = header.seq.wrapping_add(1);
+                    len = 0;
+                    continue;
+                }
+            }
+        };
+        sampling::BUFFERED.fetch_sub(1, Ordering::Relaxed);
+        // the index wraps after u32::MAX samples, further back than
+        // half of that means sampling started over
+        if (index.wrapping_sub(next) as i32) < 0 {
+            // the tail of the last stream goes out first
+            if len > 0 {
+                let encoding = config.0.lock().await.encoding;
+                serial.send_stream(header, &data[..len], encoding).await;
+            }
+            header.seq = 0;
+            next = index;
+            len = 0;
+        }
+        if index != next {
+            if len > 0 {
+                let encoding = config.0.lock().await.encoding;
+                serial.send_stream(header, &data[..len], encoding).await;
+                header.seq = header.seq.wrapping_add(1);
+                len = 0;
+            }
+            serial.send_reply(Reply::Overrun { lost: index.wrapping_sub(next) }).await;
+        }
+        if len == 0 {
+            header.index = index;
+        }
+        data[len] = value;
+        len += 1;
+        next = index.wrapping_add(1);
+
+        if len == data.len() {
+            let encoding = config.0.lock().await.encoding;
+            serial.send_stream(header, &data, encoding).await;
+            header.seq = header.seq.wrapping_add(1);
+            len = 0;
         }
-        serial.send_data(data).await;
     }
 }
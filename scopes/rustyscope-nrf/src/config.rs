@@ -1,38 +1,27 @@
 use arrayvec::ArrayVec;
-use rustyscope_traits::{ConfigAction, ConfigErr};
-use embassy::time::Duration;
-use crate::hal::gpio;
+use rustyscope_traits::{ConfigAction, ConfigErr, DeviceConfig, Encoding, PinId, Reply, MAX_BATCH};
+use crate::board::{AdcChannel, AdcPins};
 use crate::hal::pac;
 use crate::Mutex;
+use crate::description::{RATE_LIMITS, RESOLUTION};
 use core::ops::DerefMut;
 
-pub enum AdcPin {
-    P0_02(gpio::p0::P0_02<gpio::Disconnected>),
-    P0_03(gpio::p0::P0_03<gpio::Disconnected>),
-    P0_04(gpio::p0::P0_04<gpio::Disconnected>),
-    P0_05(gpio::p0::P0_05<gpio::Disconnected>),
-    P0_28(gpio::p0::P0_28<gpio::Disconnected>),
-    P0_29(gpio::p0::P0_29<gpio::Disconnected>),
-    P0_30(gpio::p0::P0_30<gpio::Disconnected>),
-    P0_31(gpio::p0::P0_31<gpio::Disconnected>),
-}
-
-struct AdcPins {
-    p0_02: Option<gpio::p0::P0_02<gpio::Disconnected>>,
-    p0_03: Option<gpio::p0::P0_03<gpio::Disconnected>>,
-    p0_04: Option<gpio::p0::P0_04<gpio::Disconnected>>,
-    p0_05: Option<gpio::p0::P0_05<gpio::Disconnected>>,
-    p0_28: Option<gpio::p0::P0_28<gpio::Disconnected>>,
-    p0_29: Option<gpio::p0::P0_29<gpio::Disconnected>>,
-    p0_30: Option<gpio::p0::P0_30<gpio::Disconnected>>,
-    p0_31: Option<gpio::p0::P0_31<gpio::Disconnected>>,
-}
-
 pub struct InnerConfig {
-    pub analog_enabled: ArrayVec<AdcPin, 8>,
+    pub analog_enabled: ArrayVec<AdcChannel, 8>,
     analog_available: AdcPins,
-    // resolution: u8,
-    pub sample_period: Option<Duration>
+    /// bits per sample, the saadc is set up with this
+    pub resolution: u8,
+    /// timer ticks between two scans, see `description::RATE_LIMITS`
+    pub sample_ticks: Option<u32>,
+    pub encoding: Encoding,
+}
+
+/// what a batch can change, to undo it
+struct Snapshot {
+    /// each channel as its pin and the negative one of a pair
+    pins: ArrayVec<(PinId, Option<PinId>), 8>,
+    sample_ticks: Option<u32>,
+    encoding: Encoding,
 }
 
 pub struct Config (pub Mutex<InnerConfig>);
@@ -41,111 +30,197 @@ impl Config {
     pub fn from_gpios(p0: pac::P0) -> Self {
         Self(Mutex::new(InnerConfig::from_gpios(p0), true))
     }
-    pub async fn apply(&self, change: ConfigAction) -> Result<(), ConfigErr> {
+    pub async fn apply(&self, change: ConfigAction) -> Result<Reply, ConfigErr> {
         let mut guard = self.0.lock().await;
         let config = guard.deref_mut();
         config.apply(change)
     }
+
+    pub async fn apply_batch(&self, actions: &[ConfigAction], replies: &mut ArrayVec<Reply, MAX_BATCH>)
+        -> Result<(), (u8, ConfigErr)> {
+        let mut guard = self.0.lock().await;
+        let config = guard.deref_mut();
+        config.apply_batch(actions, replies)
+    }
 }
 
 impl InnerConfig {
     pub fn from_gpios(p0: pac::P0) -> Self {
         use crate::hal::gpio::p0::Parts;
-        let gpios = Parts::new(p0);
-
         Self {
-            analog_available: AdcPins {
-                p0_02: Some(gpios.p0_02),
-                p0_03: Some(gpios.p0_03),
-                p0_04: Some(gpios.p0_04),
-                p0_05: Some(gpios.p0_05),
-                p0_28: Some(gpios.p0_28),
-                p0_29: Some(gpios.p0_29),
-                p0_30: Some(gpios.p0_30),
-                p0_31: Some(gpios.p0_31),
-            },
+            analog_available: AdcPins::new(Parts::new(p0)),
             analog_enabled: ArrayVec::new(),
-            sample_period: None,
-            // resolution: 12,
+            sample_ticks: None,
+            encoding: Encoding::Raw,
+            resolution: RESOLUTION,
         }
     }
 
-    pub fn apply(&mut self, change: ConfigAction) -> Result<(), ConfigErr> {
+    /// the config as sent in `Reply::Config`
+    pub fn describe(&self) -> DeviceConfig {
+        let pins = self.analog_enabled.iter().map(|c| (c.pin(), c.minus()));
+        let rate = self.sample_ticks.map(|t| RATE_LIMITS.rate(t));
+        DeviceConfig::new(pins, rate, self.resolution, self.encoding)
+    }
+
+    /// a channel more must still fit in the configured period
+    fn check_room(&self) -> Result<(), ConfigErr> {
+        match self.sample_ticks {
+            Some(ticks) => {
+                let channels = self.analog_enabled.len() + 1;
+                RATE_LIMITS.fits(ticks, channels, self.resolution)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// position of the channel with an enabled pin in the sample order
+    fn position(&self, pin: PinId) -> Result<usize, ConfigErr> {
+        self.analog_enabled
+            .iter()
+            .position(|c| c.has(pin))
+            .ok_or(ConfigErr::PinNotEnabled(pin))
+    }
+
+    pub fn apply(&mut self, change: ConfigAction) -> Result<Reply, ConfigErr> {
+        match change {
+            ConfigAction::AnalogPins(_) | ConfigAction::AnalogDiffPair(..) => self.check_room()?,
+            ConfigAction::AnalogRate(rate) => {
+                let channels = self.analog_enabled.len();
+                let ticks = RATE_LIMITS.period(rate, channels, self.resolution)?;
+                self.sample_ticks = Some(ticks);
+                return Ok(Reply::Rate(RATE_LIMITS.rate(ticks)));
+            }
+            _ => (),
+        }
+        self.change(change)?;
+        Ok(Reply::Ok)
+    }
+
+    /// carry out a change without checking the pins against the
+    /// rate, the callers do that
+    fn change(&mut self, change: ConfigAction) -> Result<(), ConfigErr> {
         use ConfigAction::*;
 
         match change {
             ResetPins => {
-                for p in self.analog_enabled.drain(..) {
-                    match p {
-                        AdcPin::P0_02(p02) => self.analog_available.p0_02 = Some(p02),
-                        AdcPin::P0_03(p03) => self.analog_available.p0_03 = Some(p03),
-                        AdcPin::P0_04(p04) => self.analog_available.p0_04 = Some(p04),
-                        AdcPin::P0_05(p05) => self.analog_available.p0_05 = Some(p05),
-                        AdcPin::P0_28(p28) => self.analog_available.p0_28 = Some(p28),
-                        AdcPin::P0_29(p29) => self.analog_available.p0_29 = Some(p29),
-                        AdcPin::P0_30(p30) => self.analog_available.p0_30 = Some(p30),
-                        AdcPin::P0_31(p31) => self.analog_available.p0_31 = Some(p31),
-                    }
+                while let Some(c) = self.analog_enabled.pop() {
+                    self.give_back(c);
                 }
             }
             DigitalPins(_pin) => Err(ConfigErr::Unimplemented)?,
             AnalogPins(pin) => {
-                let adc_pin = match pin {
-                    2 => self // TODO turn into macro
-                        .analog_available
-                        .p0_02
-                        .take()
-                        .ok_or(ConfigErr::PinTaken(pin))
-                        .map(|p| AdcPin::P0_02(p))?,
-                    3 => self // TODO turn into macro
-                        .analog_available
-                        .p0_03
-                        .take()
-                        .ok_or(ConfigErr::PinTaken(pin))
-                        .map(|p| AdcPin::P0_03(p))?,
-                    4 => self // TODO turn into macro
-                        .analog_available
-                        .p0_04
-                        .take()
-                        .ok_or(ConfigErr::PinTaken(pin))
-                        .map(|p| AdcPin::P0_04(p))?,
-                    5 => self // TODO turn into macro
-                        .analog_available
-                        .p0_05
-                        .take()
-                        .ok_or(ConfigErr::PinTaken(pin))
-                        .map(|p| AdcPin::P0_05(p))?,
-                    28 => self
-                        .analog_available
-                        .p0_28
-                        .take()
-                        .ok_or(ConfigErr::PinTaken(pin))
-                        .map(|p| AdcPin::P0_28(p))?,
-                    29 => self
-                        .analog_available
-                        .p0_29
-                        .take()
-                        .ok_or(ConfigErr::PinTaken(pin))
-                        .map(|p| AdcPin::P0_29(p))?,
-                    30 => self
-                        .analog_available
-                        .p0_30
-                        .take()
-                        .ok_or(ConfigErr::PinTaken(pin))
-                        .map(|p| AdcPin::P0_30(p))?,
-                    31 => self
-                        .analog_available
-                        .p0_31
-                        .take()
-                        .ok_or(ConfigErr::PinTaken(pin))
-                        .map(|p| AdcPin::P0_31(p))?,
-                    _ => return Err(ConfigErr::InvalidPin(pin)),
+                let adc_pin = self.analog_available.take(pin)?;
+                self.analog_enabled.push(AdcChannel::Single(adc_pin));
+            }
+            AnalogDiffPair(p, n) => {
+                if p == n {
+                    Err(ConfigErr::InvalidPin(n))?
+                }
+                let plus = self.analog_available.take(p)?;
+                let minus = match self.analog_available.take(n) {
+                    Ok(minus) => minus,
+                    Err(e) => {
+                        self.analog_available.give_back(plus);
+                        Err(e)?
+                    }
                 };
-                self.analog_enabled.push(adc_pin);
+                self.analog_enabled.push(AdcChannel::Diff(plus, minus));
+            }
+            // a pair goes as a whole
+            RemovePin(pin) => {
+                let c = self.analog_enabled.remove(self.position(pin)?);
+                self.give_back(c);
+            }
+            MovePin { pin, to } => {
+                let c = self.analog_enabled.remove(self.position(pin)?);
+                let to = (to as usize).min(self.analog_enabled.len());
+                self.analog_enabled.insert(to, c);
+            }
+            // needs the final pins, see `apply` and `apply_batch`
+            AnalogRate(_) => (),
+            // refused rather than clamped, see `Encoding::max_bits`
+            Encoding(encoding) => match encoding.max_bits() {
+                Some(bits) if bits < self.resolution => Err(ConfigErr::EncodingTooNarrow { bits })?,
+                _ => self.encoding = encoding,
+            },
+        }
+        Ok(())
+    }
+
+    fn give_back(&mut self, channel: AdcChannel) {
+        match channel {
+            AdcChannel::Single(p) => self.analog_available.give_back(p),
+            AdcChannel::Diff(p, n) => {
+                self.analog_available.give_back(p);
+                self.analog_available.give_back(n);
+            }
+        }
+    }
+
+    /// apply every action or none, `replies` gets the answer to each.
+    /// On failure the config is put back and the index of the action
+    /// that failed is returned
+    pub fn apply_batch(&mut self, actions: &[ConfigAction], replies: &mut ArrayVec<Reply, MAX_BATCH>)
+        -> Result<(), (u8, ConfigErr)> {
+        let before = self.snapshot();
+        let result = self.try_batch(actions, replies);
+        if result.is_err() {
+            self.restore(before);
+            replies.clear();
+        }
+        result
+    }
+
+    fn try_batch(&mut self, actions: &[ConfigAction], replies: &mut ArrayVec<Reply, MAX_BATCH>)
+        -> Result<(), (u8, ConfigErr)> {
+        // the last of each, checked once all pins are known
+        let mut rate = None;
+        let mut added = None;
+        for (i, &action) in actions.iter().enumerate() {
+            match action {
+                ConfigAction::AnalogRate(r) => rate = Some((i, r)),
+                ConfigAction::AnalogPins(_) | ConfigAction::AnalogDiffPair(..) => added = Some(i),
+                _ => (),
             }
-            AnalogRate(rate) => self.sample_period = Some(Duration::from_secs(1)/rate),
+            self.change(action).map_err(|e| (i as u8, e))?;
+            replies.push(Reply::Ok);
         }
 
+        let channels = self.analog_enabled.len();
+        match (rate, self.sample_ticks, added) {
+            (Some((i, rate)), _, _) => {
+                let ticks = RATE_LIMITS.period(rate, channels, self.resolution).map_err(|e| (i as u8, e))?;
+                self.sample_ticks = Some(ticks);
+                replies[i] = Reply::Rate(RATE_LIMITS.rate(ticks));
+            }
+            (None, Some(ticks), Some(i)) => {
+                RATE_LIMITS.fits(ticks, channels, self.resolution).map_err(|e| (i as u8, e))?;
+            }
+            _ => (),
+        }
         Ok(())
     }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pins: self.analog_enabled.iter().map(|c| (c.pin(), c.minus())).collect(),
+            sample_ticks: self.sample_ticks,
+            encoding: self.encoding,
+        }
+    }
+
+    /// go back to a snapshot, its pins were all enabled at
+    /// once so they can be again
+    fn restore(&mut self, snapshot: Snapshot) {
+        let _ = self.change(ConfigAction::ResetPins);
+        for (pin, minus) in snapshot.pins {
+            let _ = match minus {
+                Some(minus) => self.change(ConfigAction::AnalogDiffPair(pin, minus)),
+                None => self.change(ConfigAction::AnalogPins(pin)),
+            };
+        }
+        self.sample_ticks = snapshot.sample_ticks;
+        self.encoding = snapshot.encoding;
+    }
 }
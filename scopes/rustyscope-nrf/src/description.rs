@@ -1,8 +1,12 @@
-use rustyscope_traits::Abilities;
+//! What this scope can do, shared with the viewer through
+//! `rustyscope_traits::nrf52`. The build fails when the board or
+//! the timer no longer match it
 
-#[allow(dead_code)] // is actually when using this implementation as a lib
-pub const ABILITIES: Abilities = Abilities {
-    adc_pins: &[2, 3, 4, 5, 28, 29, 30, 31],
-    adc_res: &[8, 10, 12, 14],
-    adc_ref: &["internal (0.6 V)", "VDD/4"],
-};
+pub use rustyscope_traits::nrf52::{ABILITIES, RATE_LIMITS, RESOLUTION, VERSION};
+use rustyscope_traits::Scanner;
+use crate::board;
+use crate::scan::Scan;
+
+const _: () = assert!(Scan::TIMER_HZ == RATE_LIMITS.tick_hz, "the burst timer runs at another rate than described");
+const _: () = assert!(board::described(board::ADC_PINS, ABILITIES.adc_pins),
+    "the board adc pins differ from rustyscope_traits::nrf52::ADC_PINS");
@@ -1,37 +1,42 @@
 use crate::hal::saadc::Saadc;
 use crate::hal::pac::SAADC;
-use embedded_hal::adc::OneShot;
 use embassy::time::{Timer, Duration, Instant};
-use rustyscope_traits::SampleKind;
+use rustyscope_traits::{stream_max_rate, Input, Plan, Reply, SampleKind, Scanner};
+use futures_lite::future::yield_now;
 use crate::Mode;
 use crate::Config;
 use crate::Mutex;
 use crate::Serial;
+use crate::description::RATE_LIMITS;
+use crate::scan::Scan;
 
 use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::AdcPin;
 use futures_intrusive::channel::LocalChannel;
-pub type Channel = LocalChannel<i16, [i16; 32]>;
+/// samples with their index since continues sampling started,
+/// a jump in the index means samples were dropped in between
+pub type Channel = LocalChannel<(u32, i16), [(u32, i16); CHANNEL_SIZE]>;
+pub const CHANNEL_SIZE: usize = 32;
+/// samples waiting in the channel, it can not tell itself
+pub static BUFFERED: AtomicUsize = AtomicUsize::new(0);
+/// rounds per second while streaming before a rate is configured
+const DEFAULT_RATE: u32 = 250;
 
-fn sample(adc: &mut Saadc, pin: &mut AdcPin) -> i16 {
-    match pin {
-        AdcPin::P0_02(p) => adc.read(p).unwrap(),
-        AdcPin::P0_03(p) => adc.read(p).unwrap(),
-        AdcPin::P0_04(p) => adc.read(p).unwrap(),
-        AdcPin::P0_05(p) => adc.read(p).unwrap(),
-        AdcPin::P0_28(p) => adc.read(p).unwrap(),
-        AdcPin::P0_29(p) => adc.read(p).unwrap(),
-        AdcPin::P0_30(p) => adc.read(p).unwrap(),
-        AdcPin::P0_31(p) => adc.read(p).unwrap(),
-    }
-}
-
-pub async fn sample_loop<'a, 'd>(serial: &Serial<'a, 'd>, mode: &Mutex<Mode>, config: &Config, channel: &Channel, saadc: SAADC) {
-    use crate::hal::saadc::{SaadcConfig, Reference, Gain};
+pub async fn sample_loop<'a, 'd>(serial: &Serial<'a, 'd>, mode: &Mutex<Mode>, config: &Config, channel: &Channel, saadc: SAADC, mut scan: Scan) {
+    use crate::hal::saadc::{SaadcConfig, Reference, Gain, Resolution, Time};
+    let resolution = match config.0.lock().await.resolution {
+        8 => Resolution::_8BIT,
+        10 => Resolution::_10BIT,
+        14 => Resolution::_14BIT,
+        _ => Resolution::_12BIT,
+    };
+    // acquisition time is part of description::RATE_LIMITS
     let mut saadc_config = SaadcConfig { 
         reference: Reference::VDD1_4,
         gain: Gain::GAIN1_4,
+        resolution,
+        time: Time::_10US,
         ..SaadcConfig::default() };
     let mut adc = Saadc::new(saadc, saadc_config);
 
@@ -46,51 +51,99 @@ pub async fn sample_loop<'a, 'd>(serial: &Serial<'a, 'd>, mode: &Mutex<Mode>, co
         match curr_mode {
             Mode::Idle => Timer::after(Duration::from_millis(500)).await,
             Mode::Continues(Analog) => {
-                todo!("not yet finished, see TODO");
-                let mut guard = config.0.lock().await;
-                let config = guard.deref_mut();
-                for pin in &mut config.analog_enabled {
-                    let val = sample(&mut adc, pin);
-                    channel.send(val).await.unwrap();
+                let (channels, ticks, limited) = {
+                    let guard = config.0.lock().await;
+                    let ticks = guard.sample_ticks.unwrap_or(RATE_LIMITS.tick_hz / DEFAULT_RATE);
+                    let channels = guard.analog_enabled.len();
+                    // the burst limits of the rate do not hold here, the
+                    // uart has to keep up or the stream only overruns
+                    let max = stream_max_rate(serial.baud(), channels, guard.resolution, guard.encoding).max(1);
+                    let min_ticks = (RATE_LIMITS.tick_hz + max - 1) / max;
+                    (channels as u32, ticks.max(min_ticks), ticks < min_ticks)
+                };
+                if limited {
+                    serial.send_reply(Reply::Rate(RATE_LIMITS.rate(ticks))).await;
+                }
+                let us = ticks as u64 * 1_000_000 / RATE_LIMITS.tick_hz as u64;
+                // the embassy timer is coarser than TIMER1
+                let period = Duration::from_micros(us).max(Duration::from_ticks(1));
+                let start = Instant::now();
+                let mut round = 0u32;
+                while matches!(*mode.lock().await, Mode::Continues(Analog)) {
+                    {
+                        let mut guard = config.0.lock().await;
+                        let config = guard.deref_mut();
+                        for (i, ch) in config.analog_enabled.iter_mut().enumerate() {
+                            let val = ch.read(&mut adc);
+                            let index = round.wrapping_mul(channels).wrapping_add(i as u32);
+                            // waiting for the uart would stall sampling, drop
+                            // the sample instead and let the index show it
+                            match channel.try_send((index, val)) {
+                                Ok(()) => { BUFFERED.fetch_add(1, Ordering::Relaxed); }
+                                Err(_) => defmt::warn!("channel full, dropped sample {}", index),
+                            }
+                        }
+                    }
+                    // rounds missed while we were held up are skipped, the
+                    // index jumps over their samples so the host sees a gap
+                    let elapsed = Instant::now().duration_since(start);
+                    round = (elapsed.as_ticks() / period.as_ticks()) as u32 + 1;
+                    Timer::at(start + period * round).await;
                 }
-                Timer::after(Duration::from_millis(500)).await;
             }
-            Mode::Continues(Digital) => todo!(),
             Mode::Burst(Analog) => {
                 let mut data = [0i16; 2_000];
-                let mut guard = config.0.lock().await;
-                let config = guard.deref_mut();
+                // the config stays unlocked while sampling so
+                // `GetConfig` can be answered
+                let (plan, encoding) = {
+                    let mut guard = config.0.lock().await;
+                    let config = guard.deref_mut();
+                    let channels = config.analog_enabled.len();
+                    let plan = match config.sample_ticks {
+                        Some(ticks) => Plan::new::<Scan>(ticks, channels, data.len()),
+                        None => Plan::fastest::<Scan>(&RATE_LIMITS, channels, config.resolution, data.len()),
+                    };
+                    if plan.is_ok() {
+                        scan.select(&config.analog_enabled, config.resolution);
+                    }
+                    (plan, config.encoding)
+                };
+                let taken = match plan {
+                    Ok(plan) => {
+                        // safe: `data` is not touched until the scanner is stopped by `poll`
+                        unsafe { scan.start(plan.period, &mut data[..plan.samples()]) };
+                        // keep handling commands, a `Stop` ends the burst early
+                        let taken = loop {
+                            let abort = !matches!(*mode.lock().await, Mode::Burst(_));
+                            if let Some(taken) = plan.poll(&mut scan, abort) {
+                                break taken;
+                            }
+                            yield_now().await;
+                        };
+                        scan.release();
+                        Ok(taken)
+                    }
+                    Err(e) => Err(e),
+                };
 
-                let len = config.analog_enabled.len();
-                let pins = &mut config.analog_enabled;
-                let values = data.iter_mut();
-                let start = Instant::now();
-                let mut next = start;
-                for (i, val) in values.enumerate() {
-                    let idx = i % len;
-                    let pin = &mut pins[idx];
-                    busy_wait_till(next);
-                    *val = sample(&mut adc, pin);
-                    next += config.sample_period.unwrap_or(Duration::from_secs(0));
+                // done before answering so the host can ask for the next burst right away
+                {
+                    let mut m = mode.lock().await;
+                    let m = m.deref_mut();
+                    *m = m.next(Input::BurstDone).0;
+                }
+                match taken {
+                    Ok(taken) => {
+                        let duration = taken.duration_us(Scan::TIMER_HZ);
+                        let timing = taken.timing(Scan::TIMER_HZ);
+                        let samples = &data[..taken.samples()];
+                        serial.send_burst_data(samples, duration, timing, encoding).await;
+                    }
+                    Err(e) => serial.send_reply(Reply::Err(e)).await,
                 }
-                let duration = start.elapsed().as_micros();
-                serial.send_burst_data(&data, duration).await;
-
-                let mut new_mode = mode.lock().await;
-                let new_mode = new_mode.deref_mut();
-                *new_mode = Mode::Idle;
             }
-            Mode::Burst(Digital) => todo!(),
-            Mode::Err(ref e) => {
-                defmt::error!("config err occured: {}", e);
-                let mut new_mode = mode.lock().await;
-                let new_mode = new_mode.deref_mut();
-                *new_mode = Mode::Idle;
-            },
+            // never entered, `Mode::next` refuses digital sampling
+            Mode::Continues(Digital) | Mode::Burst(Digital) => *mode.lock().await = Mode::Idle,
         }
     }
 }
-
-fn busy_wait_till(time: Instant) {
-    while Instant::now() < time { continue }
-}
@@ -0,0 +1,112 @@
+//! Bursts timed by TIMER1: every compare event starts a scan of the
+//! enabled SAADC channels through PPI and EasyDMA stores the results
+
+use crate::hal::pac::{PPI, SAADC, TIMER1};
+use rustyscope_traits::Scanner;
+use crate::AdcChannel;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// ppi channel that links the timer to the saadc
+const PPI_CH: usize = 0;
+
+pub struct Scan {
+    timer: TIMER1,
+    ppi: PPI,
+}
+
+impl Scan {
+    pub fn new(timer: TIMER1, ppi: PPI) -> Self {
+        timer.mode.write(|w| w.mode().timer());
+        timer.bitmode.write(|w| w.bitmode()._32bit());
+        // 16 MHz
+        timer.prescaler.write(|w| unsafe { w.prescaler().bits(0) });
+        timer.shorts.write(|w| w.compare0_clear().enabled());
+
+        let saadc = unsafe { &*SAADC::ptr() };
+        ppi.ch[PPI_CH].eep.write(|w| unsafe { w.bits(&timer.events_compare[0] as *const _ as u32) });
+        ppi.ch[PPI_CH].tep.write(|w| unsafe { w.bits(&saadc.tasks_sample as *const _ as u32) });
+        Self { timer, ppi }
+    }
+
+    /// give every channel its own saadc channel in order, the hal
+    /// driver only uses channel 0 so the saadc is shared with it
+    pub fn select(&mut self, channels: &[AdcChannel], resolution: u8) {
+        let saadc = unsafe { &*SAADC::ptr() };
+        saadc.resolution.write(|w| match resolution {
+            8 => w.val()._8bit(),
+            10 => w.val()._10bit(),
+            14 => w.val()._14bit(),
+            _ => w.val()._12bit(),
+        });
+        for (i, ch) in saadc.ch.iter().enumerate() {
+            let channel = match channels.get(i) {
+                Some(channel) => channel,
+                None => {
+                    ch.pselp.write(|w| w.pselp().nc());
+                    ch.pseln.write(|w| w.pseln().nc());
+                    continue;
+                }
+            };
+            // 3 us acquisition plus 2 us conversion, see description::RATE_LIMITS
+            ch.config.write(|w| {
+                w.refsel().vdd1_4().gain().gain1_4()
+                    .tacq()._3us().resp().bypass().resn().bypass().burst().disabled();
+                if channel.is_diff() { w.mode().diff() } else { w.mode().se() }
+            });
+            ch.pselp.write(|w| channel.input(w));
+            ch.pseln.write(|w| channel.input_n(w));
+        }
+    }
+
+    /// hand the saadc back to the oneshot driver, which sets its
+    /// pin before every read but its channel config only once
+    pub fn release(&mut self) {
+        let saadc = unsafe { &*SAADC::ptr() };
+        for ch in saadc.ch.iter() {
+            ch.pselp.write(|w| w.pselp().nc());
+            ch.pseln.write(|w| w.pseln().nc());
+        }
+        saadc.ch[0].config.write(|w| w.refsel().vdd1_4().gain().gain1_4()
+            .tacq()._10us().mode().se().resp().bypass().resn().bypass().burst().disabled());
+    }
+}
+
+impl Scanner for Scan {
+    const TIMER_HZ: u32 = 16_000_000;
+    /// RESULT.MAXCNT is 15 bits wide
+    const MAX_TRANSFER: usize = (1 << 15) - 1;
+
+    unsafe fn start(&mut self, period: u32, buf: &mut [i16]) {
+        let saadc = &*SAADC::ptr();
+        saadc.result.ptr.write(|w| w.ptr().bits(buf.as_mut_ptr() as u32));
+        saadc.result.maxcnt.write(|w| w.maxcnt().bits(buf.len() as u16));
+        saadc.events_started.reset();
+        saadc.events_end.reset();
+        saadc.tasks_start.write(|w| w.bits(1));
+        while saadc.events_started.read().bits() == 0 {}
+
+        self.timer.cc[0].write(|w| w.bits(period));
+        self.timer.tasks_clear.write(|w| w.bits(1));
+        self.ppi.chenset.write(|w| w.bits(1 << PPI_CH));
+        // the first scan right away, the timer takes the others
+        saadc.tasks_sample.write(|w| w.bits(1));
+        self.timer.tasks_start.write(|w| w.bits(1));
+    }
+
+    fn done(&self) -> bool {
+        let saadc = unsafe { &*SAADC::ptr() };
+        saadc.events_end.read().bits() != 0
+    }
+
+    fn stop(&mut self) -> usize {
+        let saadc = unsafe { &*SAADC::ptr() };
+        self.timer.tasks_stop.write(|w| unsafe { w.bits(1) });
+        self.ppi.chenclr.write(|w| unsafe { w.bits(1 << PPI_CH) });
+        saadc.events_stopped.reset();
+        saadc.tasks_stop.write(|w| unsafe { w.bits(1) });
+        while saadc.events_stopped.read().bits() == 0 {}
+        // easydma wrote `buf` behind the compilers back
+        compiler_fence(Ordering::SeqCst);
+        saadc.result.amount.read().amount().bits() as usize
+    }
+}